@@ -0,0 +1,42 @@
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use bbrdb::{choose_device, DeviceChoice, DeviceStrategy, Handle};
+
+const ROUND_SIZE: usize = 0x4000;
+const ROUNDS: u32 = 64;
+const CARD_BLOCKS: u32 = 64;
+
+fn main() -> Result<()> {
+    let device = match choose_device(DeviceStrategy::Single)? {
+        DeviceChoice::Chosen(d) => d,
+        DeviceChoice::Ambiguous(_) => bail!("more than one console connected"),
+    };
+
+    let mut handle = Handle::new(&device)?;
+    handle.Init()?;
+
+    // link only: the echo app doesn't touch the card
+    let link = handle.LoopbackTest(ROUND_SIZE, ROUNDS)?;
+    println!(
+        "link: {} bytes each way in {:?} ({:.1} KiB/s), {} bad rounds",
+        link.bytes,
+        link.elapsed,
+        link.throughput() / 1024.0,
+        link.mismatched_rounds
+    );
+
+    // link + card
+    let start = Instant::now();
+    for blk in 0..CARD_BLOCKS {
+        handle.ReadSingleBlock(blk)?;
+    }
+    let elapsed = start.elapsed();
+    let bytes = CARD_BLOCKS as f64 * ROUND_SIZE as f64;
+    println!(
+        "card: {CARD_BLOCKS} blocks in {elapsed:?} ({:.1} KiB/s)",
+        bytes / elapsed.as_secs_f64() / 1024.0
+    );
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+/*
+ * Minimal console-side payload for Handle::LoopbackTest.
+ *
+ * Build against libultra and boot it from the card (or over RAMROM). It sits in a loop
+ * reading whatever the host sends on the debug channel and writes it straight back.
+ */
+
+#include <ultra64.h>
+
+#define BUF_SIZE 0x10000
+
+static u8 buf[BUF_SIZE] __attribute__((aligned(16)));
+
+void rdb_echo(void) {
+    u32 len;
+
+    for (;;) {
+        /* each round is two host transfers: a 4-byte length, then the data itself */
+        osReadHost(&len, sizeof(len));
+        if (len > BUF_SIZE) {
+            len = BUF_SIZE;
+        }
+
+        osReadHost(buf, len);
+        osWriteHost(buf, len);
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusb::UsbContext;
+use serde_json::{json, Map, Value};
+
+use crate::error::*;
+use crate::nand::BlockHash;
+use crate::Handle;
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub operation: &'static str,
+    pub params: Vec<(&'static str, String)>,
+    pub result: std::result::Result<(), String>,
+    pub duration: Duration,
+    pub hashes: Vec<(&'static str, BlockHash)>,
+}
+
+impl AuditEntry {
+    pub fn to_json(&self) -> Value {
+        let params = self
+            .params
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.clone())))
+            .collect::<Map<_, _>>();
+        let hashes = self
+            .hashes
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.to_string(),
+                    Value::String(format!("sha256:{}", to_hex(v))),
+                )
+            })
+            .collect::<Map<_, _>>();
+
+        json!({
+            "timestamp": self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "operation": self.operation,
+            "parameters": params,
+            "result": match &self.result {
+                Ok(()) => json!("ok"),
+                Err(e) => json!({ "error": e }),
+            },
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "hashes": hashes,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    started: DateTime<Utc>,
+    entries: Vec<AuditEntry>,
+    pending_hashes: Vec<(&'static str, BlockHash)>,
+}
+
+impl AuditLog {
+    fn new() -> Self {
+        Self {
+            started: Utc::now(),
+            entries: vec![],
+            pending_hashes: vec![],
+        }
+    }
+
+    pub fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "tool": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "started": self.started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "operations": self.entries.iter().map(AuditEntry::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, &self.to_json())
+            .map_err(std::io::Error::from)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+// lowercase, no separators; how the audit log, manifests and the CLI all print hashes
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn enable_audit_log(&self) {
+        let mut audit = self.audit.borrow_mut();
+        if audit.is_none() {
+            *audit = Some(AuditLog::new());
+        }
+    }
+
+    pub fn audit_log(&self) -> Option<AuditLog> {
+        self.audit.borrow().clone()
+    }
+
+    pub fn take_audit_log(&self) -> Option<AuditLog> {
+        self.audit.borrow_mut().take()
+    }
+
+    pub(crate) fn audit_enabled(&self) -> bool {
+        self.audit.borrow().is_some()
+    }
+
+    pub(crate) fn audit_hash(&self, label: &'static str, hash: BlockHash) {
+        if let Some(audit) = self.audit.borrow_mut().as_mut() {
+            audit.pending_hashes.push((label, hash));
+        }
+    }
+
+    fn audit_record<T>(
+        &self,
+        operation: &'static str,
+        params: Vec<(&'static str, String)>,
+        timestamp: DateTime<Utc>,
+        start: Instant,
+        rv: &Result<T>,
+    ) {
+        if let Some(audit) = self.audit.borrow_mut().as_mut() {
+            let hashes = std::mem::take(&mut audit.pending_hashes);
+            audit.entries.push(AuditEntry {
+                timestamp,
+                operation,
+                params,
+                result: rv.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+                duration: start.elapsed(),
+                hashes,
+            });
+        }
+    }
+
+    pub(crate) fn audited<T>(
+        &self,
+        operation: &'static str,
+        params: Vec<(&'static str, String)>,
+        mut f: impl FnMut(&Self) -> Result<T>,
+    ) -> Result<T> {
+        let (timestamp, start) = (Utc::now(), Instant::now());
+        let writes = self.card_writes.get();
+        let mut rv = self.guarded(&mut f);
+        if let Err(e) = &rv {
+            self.note_card_error(e);
+        }
+        if self.retryable_change(&rv, writes, operation) && self.recheck_after_change(operation) {
+            rv = self.guarded(&mut f);
+        }
+        self.audit_record(operation, params, timestamp, start, &rv);
+        rv
+    }
+
+    pub(crate) fn audited_mut<T>(
+        &mut self,
+        operation: &'static str,
+        params: Vec<(&'static str, String)>,
+        mut f: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let (timestamp, start) = (Utc::now(), Instant::now());
+        let writes = self.card_writes.get();
+        let mut rv = self.guarded_mut(&mut f);
+        if let Err(e) = &rv {
+            self.note_card_error(e);
+        }
+        if self.retryable_change(&rv, writes, operation) && self.reinit_after_change(operation) {
+            rv = self.guarded_mut(&mut f);
+        }
+        self.audit_record(operation, params, timestamp, start, &rv);
+        rv
+    }
+}
@@ -0,0 +1,222 @@
+use std::io::{Read, Write};
+
+use chrono::{DateTime, Utc};
+use rusb::UsbContext;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, Header};
+
+use crate::audit::to_hex;
+use crate::constants::{BLOCK_SIZE, NUM_FATS};
+use crate::error::*;
+use crate::fs::CardStats;
+use crate::nand::{NandImage, WriteReport};
+use crate::Handle;
+
+const BACKUP_VERSION: u64 = 1;
+
+const NAND_MEMBER: &str = "nand.bin";
+const SPARE_MEMBER: &str = "spare.bin";
+const MANIFEST_MEMBER: &str = "manifest.json";
+
+fn invalid(msg: impl Into<String>) -> LibBBRDBError {
+    LibBBRDBError::InvalidBackup(msg.into())
+}
+
+// everything needed to put a card back the way it was, plus enough to tell what it was
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub bbid: u32,
+    pub created: DateTime<Utc>,
+    pub stats: CardStats,
+    pub files: Vec<(String, usize)>,
+    pub image: NandImage,
+}
+
+impl Backup {
+    fn manifest(&self) -> Value {
+        json!({
+            "version": BACKUP_VERSION,
+            "created": self.created.to_rfc3339(),
+            "bbid": format!("{:08X}", self.bbid),
+            "blocks": self.image.num_blocks(),
+            "stats": {
+                "free": self.stats.free,
+                "used": self.stats.used,
+                "bad": self.stats.bad,
+                "seqno": self.stats.seqno,
+                "reserved": self.stats.reserved,
+                "sksa_area": self.stats.sksa_area,
+                "sksa_reserved": self.stats.sksa_reserved,
+                "block_size": self.stats.block_size,
+                "fat_slots": self.stats.fat_slots,
+            },
+            "files": self
+                .files
+                .iter()
+                .map(|(name, size)| json!({ "name": name, "size": size }))
+                .collect::<Vec<_>>(),
+            "synthesised_spare": self.image.synthesised_spare(),
+            "sha256": {
+                NAND_MEMBER: to_hex(&Sha256::digest(self.image.nand())),
+                SPARE_MEMBER: to_hex(&Sha256::digest(self.image.spare())),
+            },
+        })
+    }
+
+    // a plain tar, so the pieces can still be pulled out without this crate
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        let manifest = serde_json::to_vec_pretty(&self.manifest()).map_err(std::io::Error::from)?;
+
+        let mut builder = Builder::new(writer);
+        for (name, data) in [
+            (MANIFEST_MEMBER, manifest.as_slice()),
+            (NAND_MEMBER, self.image.nand()),
+            (SPARE_MEMBER, self.image.spare()),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(self.created.timestamp().max(0) as u64);
+            builder.append_data(&mut header, name, data)?;
+        }
+        builder.into_inner()?.flush()?;
+
+        Ok(())
+    }
+
+    // checks the hashes in the manifest as it goes, so a corrupt archive never gets as far as a card
+    pub fn read_from<R: Read>(reader: R) -> Result<Self> {
+        let (mut manifest, mut nand, mut spare) = (None, None, None);
+
+        let mut archive = Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+
+            match name.as_str() {
+                MANIFEST_MEMBER => {
+                    let value: Value = serde_json::from_slice(&data)
+                        .map_err(|e| invalid(format!("unreadable manifest: {e}")))?;
+                    manifest = Some(value);
+                }
+                NAND_MEMBER => nand = Some(data),
+                SPARE_MEMBER => spare = Some(data),
+                _ => {}
+            }
+        }
+
+        let manifest = manifest.ok_or_else(|| invalid("no manifest"))?;
+        let nand = nand.ok_or_else(|| invalid("no NAND image"))?;
+        let spare = spare.ok_or_else(|| invalid("no spare data"))?;
+
+        if manifest["version"].as_u64() != Some(BACKUP_VERSION) {
+            return Err(invalid(format!(
+                "unsupported version {}",
+                manifest["version"]
+            )));
+        }
+
+        for (member, data) in [(NAND_MEMBER, &nand), (SPARE_MEMBER, &spare)] {
+            let expected = manifest["sha256"][member].as_str();
+            if expected != Some(to_hex(&Sha256::digest(data)).as_str()) {
+                return Err(invalid(format!("{member} doesn't match its hash")));
+            }
+        }
+
+        let field = |v: &Value, name: &str| {
+            v[name]
+                .as_u64()
+                .ok_or_else(|| invalid(format!("manifest is missing {name}")))
+        };
+
+        let bbid = manifest["bbid"]
+            .as_str()
+            .and_then(|s| u32::from_str_radix(s, 16).ok())
+            .ok_or_else(|| invalid("manifest is missing the BBID"))?;
+        let created = manifest["created"]
+            .as_str()
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+            .ok_or_else(|| invalid("manifest is missing its creation time"))?
+            .with_timezone(&Utc);
+
+        let s = &manifest["stats"];
+        let stats = CardStats {
+            free: field(s, "free")? as usize,
+            used: field(s, "used")? as usize,
+            bad: field(s, "bad")? as usize,
+            seqno: field(s, "seqno")? as u32,
+            // not in archives from before they were counted
+            reserved: s["reserved"].as_u64().unwrap_or_default() as usize,
+            sksa_area: s["sksa_area"].as_u64().unwrap_or_default() as usize,
+            sksa_reserved: s["sksa_reserved"].as_u64().unwrap_or_default() as usize,
+            block_size: s["block_size"].as_u64().unwrap_or(BLOCK_SIZE as u64) as usize,
+            fat_slots: s["fat_slots"].as_u64().unwrap_or(NUM_FATS as u64) as usize,
+            fat_slots_readable: None,
+        };
+
+        let files = manifest["files"]
+            .as_array()
+            .ok_or_else(|| invalid("manifest is missing the file listing"))?
+            .iter()
+            .map(|f| {
+                let name = f["name"].as_str().ok_or_else(|| invalid("unnamed file"))?;
+                Ok((name.to_string(), field(f, "size")? as usize))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut image = NandImage::new(nand, spare)?;
+        if field(&manifest, "blocks")? != image.num_blocks() as u64 {
+            return Err(invalid("block count doesn't match the NAND image"));
+        }
+        let synthesised = manifest["synthesised_spare"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|b| b.as_u64())
+                    .map(|b| b as u32)
+                    .collect()
+            })
+            .unwrap_or_default();
+        image.set_synthesised_spare(synthesised);
+
+        Ok(Self {
+            bbid,
+            created,
+            stats,
+            files,
+            image,
+        })
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn Backup(&self) -> Result<Backup> {
+        let bbid = self.GetBBID()?;
+        let stats = self.CardStats()?;
+        let files = self.ListFiles()?;
+        let (image, _) = self.DumpNANDSpare()?;
+
+        Ok(Backup {
+            bbid,
+            created: Utc::now(),
+            stats,
+            files,
+            image,
+        })
+    }
+
+    // writes every block back, spare included, through WriteNAND; the report says which blocks
+    // didn't take, and whether the FAT that came with them could be read back
+    #[allow(non_snake_case)]
+    pub fn RestoreBackup(&mut self, backup: &Backup, fail_fast: bool) -> Result<WriteReport> {
+        let params = vec![("bbid", format!("{:08X}", backup.bbid))];
+        self.audited_mut("RestoreBackup", params, |this| {
+            this.WriteNAND(&backup.image, fail_fast)
+        })
+    }
+}
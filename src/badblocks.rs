@@ -0,0 +1,196 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+#[cfg(feature = "writing")]
+use crate::fs::BlockIndex;
+use crate::progress::ProgressUnit;
+use crate::require_fat;
+use crate::spare::SpareData;
+use crate::Handle;
+
+// what ScanBlocks found, one entry per block on the card
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BadBlockMap {
+    bad: Vec<bool>,
+}
+
+impl BadBlockMap {
+    pub fn new(bad: Vec<bool>) -> Self {
+        Self { bad }
+    }
+
+    // one bit per block, block 0 in the low bit of the first byte
+    pub fn from_bitmap(bitmap: &[u8], num_blocks: u32) -> Result<Self> {
+        if bitmap.len() != (num_blocks as usize).div_ceil(8) {
+            return Err(LibBBRDBError::WrongDataLength);
+        }
+
+        Ok(Self::new(
+            (0..num_blocks as usize)
+                .map(|i| bitmap[i / 8] & (1 << (i % 8)) != 0)
+                .collect(),
+        ))
+    }
+
+    // the number of blocks the map covers
+    pub fn len(&self) -> usize {
+        self.bad.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bad.is_empty()
+    }
+
+    // the number of bad blocks
+    pub fn count(&self) -> usize {
+        self.bad.iter().filter(|&&b| b).count()
+    }
+
+    pub fn indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.bad
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b)
+            .map(|(i, _)| i as u32)
+    }
+
+    pub fn contains(&self, block: u32) -> bool {
+        self.bad.get(block as usize).copied().unwrap_or(false)
+    }
+
+    pub fn as_slice(&self) -> &[bool] {
+        &self.bad
+    }
+
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        let mut rv = vec![0; self.bad.len().div_ceil(8)];
+        for i in self.indices() {
+            rv[i as usize / 8] |= 1 << (i % 8);
+        }
+        rv
+    }
+}
+
+impl From<BadBlockMap> for Vec<bool> {
+    fn from(map: BadBlockMap) -> Self {
+        map.bad
+    }
+}
+
+// a block the sources don't all agree on; None where a source wasn't consulted or couldn't say
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadBlockDisagreement {
+    pub block: u32,
+    pub scan: bool,
+    pub fat: Option<bool>,
+    pub spare: Option<bool>,
+}
+
+impl BadBlockDisagreement {
+    fn disagrees(&self) -> bool {
+        [self.fat, self.spare]
+            .into_iter()
+            .flatten()
+            .any(|bad| bad != self.scan)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BadBlockComparison {
+    pub scan: BadBlockMap,
+    pub fat: Option<BadBlockMap>,
+    // per block, None where the firmware gave no spare or the block couldn't be read at all
+    pub spare: Option<Vec<Option<bool>>>,
+}
+
+impl BadBlockComparison {
+    pub fn disagreements(&self) -> Vec<BadBlockDisagreement> {
+        (0..self.scan.len() as u32)
+            .map(|block| BadBlockDisagreement {
+                block,
+                scan: self.scan.contains(block),
+                fat: self.fat.as_ref().map(|m| m.contains(block)),
+                spare: self
+                    .spare
+                    .as_ref()
+                    .and_then(|s| s.get(block as usize).copied().flatten()),
+            })
+            .filter(BadBlockDisagreement::disagrees)
+            .collect()
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.disagreements().is_empty()
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    fn spare_marker(&self, block: u32) -> Result<Option<bool>> {
+        match self.read_block_with_spare(block) {
+            Ok(b) if b.spare_synthesised() => Ok(None),
+            Ok(b) => Ok(Some(b.spare_data()?.is_bad())),
+            Err(LibBBRDBError::CardError(CardError::BadBlock(_, s))) => {
+                Ok(Some(SpareData::parse(&s)?.is_bad()))
+            }
+            Err(LibBBRDBError::CardError(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // cards that have been through other tools often disagree with themselves about which
+    // blocks are bad. the scan is always done; checking the spares reads the whole card
+    #[allow(non_snake_case)]
+    pub fn CompareBadBlocks(&self, fat: bool, spare: bool) -> Result<BadBlockComparison> {
+        let params = vec![("fat", fat.to_string()), ("spare", spare.to_string())];
+        self.audited("CompareBadBlocks", params, |this| {
+            let fat = if fat {
+                Some(require_fat!(this, _p, fat { Ok(fat.bad_blocks()) })?)
+            } else {
+                None
+            };
+
+            let scan = this.ScanBadBlocks()?;
+
+            let spare = if spare {
+                let progress = this.progress_task(
+                    "Reading spare data",
+                    Some(scan.len() as u64),
+                    ProgressUnit::Blocks,
+                );
+                let markers = (0..scan.len() as u32)
+                    .map(|block| {
+                        let rv = this.spare_marker(block).at_block("CompareBadBlocks", block);
+                        progress.advance(1);
+                        rv
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                drop(progress);
+                Some(markers)
+            } else {
+                None
+            };
+
+            Ok(BadBlockComparison { scan, fat, spare })
+        })
+    }
+
+    // marks every free block the map says is bad as BadBlock in the FAT, so nothing gets
+    // allocated there. blocks that already belong to a file are left for the caller to deal
+    // with. Returns the blocks newly marked
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn MarkBadBlocks(&mut self, map: &BadBlockMap) -> Result<Vec<BlockIndex>> {
+        self.audited_mut("MarkBadBlocks", vec![], |this| {
+            this.check_writable()?;
+
+            this.transaction(|this| {
+                let marked = this.mark_bad_blocks(map)?;
+                if !marked.is_empty() {
+                    this.update_fs()?;
+                }
+                Ok(marked)
+            })
+        })
+    }
+}
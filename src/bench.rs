@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::queue::QUEUE_TRANSFER_SIZE;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchOp {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchOptions {
+    // blocks moved for each queue depth
+    pub blocks: u32,
+    // each one is run in turn; the chunk a read keeps in flight is depth * 4KiB
+    pub queue_depths: Vec<usize>,
+    // also time writes, to free blocks in the file area
+    pub write: bool,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            blocks: 64,
+            queue_depths: vec![1, 2, 4, 8],
+            write: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchResult {
+    pub op: BenchOp,
+    pub queue_depth: usize,
+    pub blocks: u32,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    // bytes of card data per second
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn blocks_per_second(&self) -> f64 {
+        self.blocks as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.queue_depth * QUEUE_TRANSFER_SIZE
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    fn bench_result(&self, op: BenchOp, blocks: u32, elapsed: Duration) -> BenchResult {
+        BenchResult {
+            op,
+            queue_depth: self.queue_depth,
+            blocks,
+            bytes: blocks as u64 * self.layout.block_size as u64,
+            elapsed,
+        }
+    }
+
+    fn bench_reads(&self, blocks: u32) -> Result<(u32, Duration)> {
+        let blocks = blocks.min(self.card_size().ok_or(LibBBRDBError::NotInitialised)?);
+
+        let start = Instant::now();
+        for b in 0..blocks {
+            // a failed read still moved the block over USB, which is all that's being timed
+            match self.read_blocks(b, 1) {
+                Ok(_) | Err(LibBBRDBError::CardError(_)) => {}
+                Err(e) => return Err(e).at_block("benchmark", b),
+            }
+        }
+
+        Ok((blocks, start.elapsed()))
+    }
+
+    fn bench_writes(&mut self, targets: &[u32]) -> Result<(u32, Duration)> {
+        let blank = vec![0xFF; self.layout.block_size];
+
+        let start = Instant::now();
+        for &b in targets {
+            self.write_blocks(b, &[&blank]).at_block("benchmark", b)?;
+        }
+
+        Ok((targets.len() as u32, start.elapsed()))
+    }
+
+    // times block reads, and optionally writes, at each queue depth; the handle's own depth is
+    // put back afterwards. writes only touch blocks the FAT has free
+    pub fn benchmark(&mut self, options: &BenchOptions) -> Result<Vec<BenchResult>> {
+        let params = vec![
+            ("blocks", options.blocks.to_string()),
+            ("write", options.write.to_string()),
+        ];
+        self.audited_mut("Benchmark", params, |this| {
+            let targets = if options.write {
+                this.check_writable()?;
+                let mut free = this.unused_blocks()?;
+                free.truncate(options.blocks as usize);
+                free
+            } else {
+                vec![]
+            };
+
+            let depth = this.queue_depth;
+            let mut results = vec![];
+
+            let rv = options.queue_depths.iter().try_for_each(|&d| {
+                this.queue_depth = d.max(1);
+
+                let (blocks, elapsed) = this.bench_reads(options.blocks)?;
+                results.push(this.bench_result(BenchOp::Read, blocks, elapsed));
+
+                if options.write {
+                    let (blocks, elapsed) = this.bench_writes(&targets)?;
+                    results.push(this.bench_result(BenchOp::Write, blocks, elapsed));
+                }
+
+                Ok(())
+            });
+
+            this.queue_depth = depth;
+            rv.map(|_| results)
+        })
+    }
+}
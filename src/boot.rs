@@ -0,0 +1,133 @@
+use rusb::UsbContext;
+use sha2::{Digest, Sha256};
+
+use crate::error::*;
+use crate::kernel::read_sa_with;
+use crate::nand::BlockHash;
+use crate::sksa_info::CmdHead;
+use crate::Handle;
+
+// the secure kernel lives in the first four blocks of the card
+pub const SK_BLOCKS: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootBlockIssue {
+    Unreadable(u32, String),
+    BadBlock(u32),
+    Erased(u32),
+    Zeroed(u32),
+    // SA1's first block, right after the SK, doesn't hold a CmdHead the boot ROM could use
+    BadSaHead(u32, String),
+    // the spare links out of SA1's first block don't lead through all of its content
+    BrokenSaChain(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkMatch {
+    Known(String),
+    Unknown,
+    NotChecked,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootAreaReport {
+    pub sk_hash: BlockHash,
+    pub sk_match: SkMatch,
+    // SA1's CmdHead, if it could be read
+    pub sa1: Option<CmdHead>,
+    pub issues: Vec<BootBlockIssue>,
+}
+
+impl BootAreaReport {
+    pub fn likely_bootable(&self) -> bool {
+        self.issues.is_empty() && self.sk_match != SkMatch::Unknown
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // checks that the SK blocks are there and that SA1 after them has a sane CmdHead and a spare
+    // chain as long as it says; `known` is a table of (name, SHA-256 of blocks 0-3) for SKs known
+    // to be good, and can be empty to only check the structure
+    #[allow(non_snake_case)]
+    pub fn CheckBootArea(&self, known: &[(&str, BlockHash)]) -> Result<BootAreaReport> {
+        self.guarded(|this| {
+            this.check_initialised()?;
+
+            let mut hasher = Sha256::new();
+            let mut issues = vec![];
+
+            for blk in 0..SK_BLOCKS {
+                let data = match this.read_block_with_spare(blk) {
+                    Ok(b) => b.into_parts().0,
+                    Err(LibBBRDBError::CardError(CardError::BadBlock(n, _))) => {
+                        issues.push(BootBlockIssue::BadBlock(blk));
+                        n
+                    }
+                    Err(e) => {
+                        issues.push(BootBlockIssue::Unreadable(blk, e.to_string()));
+                        continue;
+                    }
+                };
+
+                if data.iter().all(|&b| b == 0xFF) {
+                    issues.push(BootBlockIssue::Erased(blk));
+                } else if data.iter().all(|&b| b == 0) {
+                    issues.push(BootBlockIssue::Zeroed(blk));
+                }
+
+                hasher.update(&data);
+            }
+
+            let sk_hash: BlockHash = hasher.finalize().into();
+
+            let sa1 = match this.read_block_with_spare(SK_BLOCKS) {
+                Ok(b) => match CmdHead::parse(b.data()) {
+                    Ok(head) if head.size == 0 => {
+                        issues.push(BootBlockIssue::BadSaHead(
+                            SK_BLOCKS,
+                            "the CmdHead gives no content".to_string(),
+                        ));
+                        None
+                    }
+                    Ok(head) => Some(head),
+                    Err(e) => {
+                        issues.push(BootBlockIssue::BadSaHead(SK_BLOCKS, e.to_string()));
+                        None
+                    }
+                },
+                Err(LibBBRDBError::CardError(CardError::BadBlock(..))) => {
+                    issues.push(BootBlockIssue::BadBlock(SK_BLOCKS));
+                    None
+                }
+                Err(e) => {
+                    issues.push(BootBlockIssue::Unreadable(SK_BLOCKS, e.to_string()));
+                    None
+                }
+            };
+
+            if sa1.is_some() {
+                if let Err(e) = read_sa_with(SK_BLOCKS, &mut |b| this.read_block_with_spare(b)) {
+                    issues.push(BootBlockIssue::BrokenSaChain(e.to_string()));
+                }
+            }
+
+            let sk_match = if known.is_empty() {
+                SkMatch::NotChecked
+            } else {
+                known
+                    .iter()
+                    .find(|(_, h)| h == &sk_hash)
+                    .map_or(SkMatch::Unknown, |(name, _)| {
+                        SkMatch::Known(name.to_string())
+                    })
+            };
+
+            Ok(BootAreaReport {
+                sk_hash,
+                sk_match,
+                sa1,
+                issues,
+            })
+        })
+    }
+}
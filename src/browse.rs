@@ -0,0 +1,231 @@
+#[cfg(feature = "writing")]
+use std::fs::read;
+use std::fs::write;
+#[cfg(feature = "writing")]
+use std::path::Path;
+
+use anyhow::Result;
+use bbrdb::{CardStats, GlobalHandle, NoProgress};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+const HELP: &str = "↑/↓ move  d download  u upload  r rename  x delete  g refresh  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Upload,
+    Rename,
+    Delete,
+}
+
+impl Prompt {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Upload => "Upload which local file? ",
+            Self::Rename => "Rename to: ",
+            Self::Delete => "Delete it? (y/n) ",
+        }
+    }
+}
+
+struct Browser<'a> {
+    handle: &'a mut GlobalHandle,
+    files: Vec<(String, usize)>,
+    stats: Option<CardStats>,
+    list: ListState,
+    prompt: Option<(Prompt, String)>,
+    status: String,
+}
+
+impl<'a> Browser<'a> {
+    fn new(handle: &'a mut GlobalHandle) -> Result<Self> {
+        let mut rv = Self {
+            handle,
+            files: vec![],
+            stats: None,
+            list: ListState::default(),
+            prompt: None,
+            status: String::new(),
+        };
+        rv.refresh()?;
+        Ok(rv)
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.files = self.handle.ListFiles()?;
+        self.files.sort();
+        self.stats = Some(self.handle.CardStats()?);
+
+        let selected = self.list.selected().unwrap_or_default();
+        self.list.select(match self.files.len() {
+            0 => None,
+            n => Some(selected.min(n - 1)),
+        });
+
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&str> {
+        self.list
+            .selected()
+            .and_then(|i| self.files.get(i))
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn download(&mut self) -> Result<()> {
+        let Some(name) = self.selected().map(str::to_string) else {
+            return Ok(());
+        };
+
+        self.status = match self.handle.ReadFile(&name)? {
+            Some(data) => {
+                write(&name, data)?;
+                format!("downloaded {name}")
+            }
+            None => format!("{name} has gone from the card"),
+        };
+
+        Ok(())
+    }
+
+    #[cfg(feature = "writing")]
+    fn finish_prompt(&mut self, prompt: Prompt, input: String) -> Result<()> {
+        let selected = self.selected().map(str::to_string);
+
+        self.status = match (prompt, selected) {
+            (Prompt::Upload, _) => {
+                let path = Path::new(&input);
+                let name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or_default()
+                    .to_string();
+                self.handle.WriteFile(&read(path)?, &name)?;
+                format!("uploaded {name}")
+            }
+            (Prompt::Rename, Some(from)) => {
+                self.handle.RenameFile(&from, &input)?;
+                format!("renamed {from} to {input}")
+            }
+            (Prompt::Delete, Some(name)) if input == "y" => {
+                self.handle.DeleteFile(&name)?;
+                format!("deleted {name}")
+            }
+            _ => String::new(),
+        };
+
+        self.refresh()
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn finish_prompt(&mut self, _prompt: Prompt, _input: String) -> Result<()> {
+        self.status = "this build can't write to the card".to_string();
+        Ok(())
+    }
+
+    fn start_prompt(&mut self, prompt: Prompt) {
+        if prompt == Prompt::Upload || self.selected().is_some() {
+            self.prompt = Some((prompt, String::new()));
+        }
+    }
+
+    // false once the user asks to leave
+    fn handle_key(&mut self, code: KeyCode) -> Result<bool> {
+        if let Some((prompt, mut input)) = self.prompt.take() {
+            match code {
+                KeyCode::Enter => self.finish_prompt(prompt, input)?,
+                KeyCode::Char(c) if prompt == Prompt::Delete => {
+                    self.finish_prompt(prompt, c.to_string())?
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.prompt = Some((prompt, input));
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.prompt = Some((prompt, input));
+                }
+                KeyCode::Esc => {}
+                _ => self.prompt = Some((prompt, input)),
+            }
+            return Ok(true);
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Char('d') => self.download()?,
+            KeyCode::Char('u') => self.start_prompt(Prompt::Upload),
+            KeyCode::Char('r') => self.start_prompt(Prompt::Rename),
+            KeyCode::Char('x') | KeyCode::Delete => self.start_prompt(Prompt::Delete),
+            KeyCode::Char('g') => {
+                self.refresh()?;
+                self.status = "refreshed".to_string();
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [files, footer] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+
+        let title = match self.stats {
+            Some(s) => format!(" {} files, {} blocks free ", self.files.len(), s.free),
+            None => " files ".to_string(),
+        };
+        let items = self
+            .files
+            .iter()
+            .map(|(name, size)| ListItem::new(format!("{name:<12} {size:>10}")));
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, files, &mut self.list);
+
+        let line = match &self.prompt {
+            Some((prompt, input)) => Line::from(format!("{}{input}", prompt.label())),
+            None if !self.status.is_empty() => Line::from(self.status.as_str()),
+            None => Line::from(HELP),
+        };
+        frame.render_widget(Paragraph::new(line).block(Block::bordered()), footer);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                // errors go in the status line; the card is still there to try something else
+                match self.handle_key(key.code) {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(()),
+                    Err(e) => self.status = format!("error: {e:#}"),
+                }
+            }
+        }
+    }
+}
+
+pub fn browse(handle: &mut GlobalHandle) -> Result<()> {
+    // a progress bar would draw straight over the UI
+    handle.set_progress_sink(NoProgress);
+
+    let mut browser = Browser::new(handle)?;
+
+    let mut terminal = ratatui::init();
+    let rv = browser.run(&mut terminal);
+    ratatui::restore();
+
+    rv
+}
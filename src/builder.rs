@@ -0,0 +1,276 @@
+use std::fmt::Debug;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+use rusb::{Device, UsbContext};
+
+use crate::commands::CommandTable;
+use crate::constants::{QUEUE_DEPTH, TIMEOUT};
+use crate::error::*;
+use crate::layout::CardLayout;
+use crate::native::FileBackend;
+use crate::progress::{BarProgress, ProgressSink};
+use crate::retry::RetryPolicy;
+use crate::Handle;
+
+pub(crate) const TEMP_FILE_NAME: &str = "temp.tmp";
+
+// everything that can be set on a Handle, gathered up front so it's in place before Init
+pub struct HandleBuilder {
+    read_only: bool,
+    retry: RetryPolicy,
+    timeout: Duration,
+    queue_depth: usize,
+    dump_digests: bool,
+    verify_reads: bool,
+    auto_reinit: bool,
+    power_off_on_close: bool,
+    temp_file_name: String,
+    file_backend: FileBackend,
+    command_table: CommandTable,
+    layout: CardLayout,
+    progress: Box<dyn ProgressSink>,
+    audit_log: bool,
+    packet_trace: Option<Box<dyn Write + Send>>,
+    transfer_recording: Option<Box<dyn Write + Send>>,
+}
+
+impl Debug for HandleBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandleBuilder")
+            .field("read_only", &self.read_only)
+            .field("retry", &self.retry)
+            .field("timeout", &self.timeout)
+            .field("queue_depth", &self.queue_depth)
+            .field("dump_digests", &self.dump_digests)
+            .field("verify_reads", &self.verify_reads)
+            .field("auto_reinit", &self.auto_reinit)
+            .field("power_off_on_close", &self.power_off_on_close)
+            .field("temp_file_name", &self.temp_file_name)
+            .field("file_backend", &self.file_backend)
+            .field("command_table", &self.command_table)
+            .field("layout", &self.layout)
+            .field("progress", &self.progress)
+            .field("audit_log", &self.audit_log)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for HandleBuilder {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            retry: RetryPolicy::default(),
+            timeout: TIMEOUT,
+            queue_depth: QUEUE_DEPTH,
+            dump_digests: false,
+            verify_reads: false,
+            auto_reinit: false,
+            power_off_on_close: false,
+            temp_file_name: TEMP_FILE_NAME.to_string(),
+            file_backend: FileBackend::default(),
+            command_table: CommandTable::standard(),
+            layout: CardLayout::default(),
+            progress: Box::new(BarProgress::default()),
+            audit_log: false,
+            packet_trace: None,
+            transfer_recording: None,
+        }
+    }
+}
+
+impl HandleBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    // for each USB transfer, not for a whole operation
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // how many IN transfers to keep waiting on the console at once; 1 reads one at a time
+    pub fn queue_depth(mut self, depth: usize) -> Self {
+        self.queue_depth = depth.max(1);
+        self
+    }
+
+    // CRC32, SHA-1 and SHA-256 of every dump, returned in its DumpReport
+    pub fn dump_digests(mut self, enabled: bool) -> Self {
+        self.dump_digests = enabled;
+        self
+    }
+
+    // after each ReadFile, have the console checksum the file and compare it with what arrived
+    pub fn verify_reads(mut self, enabled: bool) -> Self {
+        self.verify_reads = enabled;
+        self
+    }
+
+    // when the console says the card changed, re-read its FAT and, if it's the same card and
+    // nothing had been written yet, try the operation once more
+    pub fn auto_reinit(mut self, enabled: bool) -> Self {
+        self.auto_reinit = enabled;
+        self
+    }
+
+    // send PowerOff when the handle is closed or dropped
+    pub fn power_off_on_close(mut self, enabled: bool) -> Self {
+        self.power_off_on_close = enabled;
+        self
+    }
+
+    // where WriteFile stages data before renaming it into place
+    pub fn temp_file_name(mut self, name: impl Into<String>) -> Self {
+        self.temp_file_name = name.into();
+        self
+    }
+
+    pub fn file_backend(mut self, backend: FileBackend) -> Self {
+        self.file_backend = backend;
+        self
+    }
+
+    pub fn command_table(mut self, table: CommandTable) -> Self {
+        self.command_table = table;
+        self
+    }
+
+    pub fn card_layout(mut self, layout: CardLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn progress_sink<P: ProgressSink + 'static>(mut self, sink: P) -> Self {
+        self.progress = Box::new(sink);
+        self
+    }
+
+    pub fn audit_log(mut self, enabled: bool) -> Self {
+        self.audit_log = enabled;
+        self
+    }
+
+    pub fn trace_packets_to<W: Write + Send + 'static>(mut self, out: W) -> Self {
+        self.packet_trace = Some(Box::new(out));
+        self
+    }
+
+    pub fn record_transfers_to<W: Write + Send + 'static>(mut self, out: W) -> Self {
+        self.transfer_recording = Some(Box::new(out));
+        self
+    }
+
+    pub fn open<C: UsbContext>(self, device: &Device<C>) -> Result<Handle<C>> {
+        Ok(self.apply(Handle::new(device)?))
+    }
+
+    pub fn replay<C: UsbContext, R: BufRead>(self, capture: R) -> Result<Handle<C>> {
+        Ok(self.apply(Handle::replay(capture)?))
+    }
+
+    fn apply<C: UsbContext>(self, mut handle: Handle<C>) -> Handle<C> {
+        handle.read_only = self.read_only;
+        handle.retry = self.retry;
+        handle.timeout = self.timeout;
+        handle.queue_depth = self.queue_depth;
+        handle.dump_digests = self.dump_digests;
+        handle.verify_reads = self.verify_reads;
+        handle.auto_reinit = self.auto_reinit;
+        handle.power_off_on_close = self.power_off_on_close;
+        handle.temp_file_name = self.temp_file_name;
+        handle.file_backend = self.file_backend;
+        handle.set_command_table(self.command_table);
+        handle.layout = self.layout;
+        handle.progress = self.progress;
+
+        if self.audit_log {
+            handle.enable_audit_log();
+        }
+        if let Some(out) = self.packet_trace {
+            handle.trace_packets_to(out);
+        }
+        if let Some(out) = self.transfer_recording {
+            handle.record_transfers_to(out);
+        }
+
+        handle
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.queue_depth = depth.max(1);
+    }
+
+    pub fn dump_digests(&self) -> bool {
+        self.dump_digests
+    }
+
+    pub fn set_dump_digests(&mut self, enabled: bool) {
+        self.dump_digests = enabled;
+    }
+
+    pub fn verify_reads(&self) -> bool {
+        self.verify_reads
+    }
+
+    pub fn set_verify_reads(&mut self, enabled: bool) {
+        self.verify_reads = enabled;
+    }
+
+    pub fn auto_reinit(&self) -> bool {
+        self.auto_reinit
+    }
+
+    pub fn set_auto_reinit(&mut self, enabled: bool) {
+        self.auto_reinit = enabled;
+    }
+
+    pub fn power_off_on_close(&self) -> bool {
+        self.power_off_on_close
+    }
+
+    pub fn set_power_off_on_close(&mut self, enabled: bool) {
+        self.power_off_on_close = enabled;
+    }
+
+    pub fn temp_file_name(&self) -> &str {
+        &self.temp_file_name
+    }
+
+    pub fn set_temp_file_name(&mut self, name: impl Into<String>) {
+        self.temp_file_name = name.into();
+    }
+}
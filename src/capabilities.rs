@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use log::debug;
+use rusb::UsbContext;
+
+use crate::commands::Command;
+use crate::constants::DRAIN_TIMEOUT;
+use crate::error::*;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    Supported,
+    Unsupported,
+    Unknown,
+}
+
+// an 8.3 name for trying the file commands on a file that isn't there; a console that has them
+// answers with an error status and changes nothing
+pub(crate) const PROBE_FILE: &str = "bbprobe.chk";
+
+// optional commands the crate can do without, and can try without changing anything; the ones
+// Init needs are learnt about by Init using them, and the ones that change the card only by
+// probe_native_changes
+#[cfg(feature = "native_fs")]
+const PROBE_COMMANDS: &[Command] = &[Command::ReadBlockAndSpare, Command::ReadFile];
+#[cfg(not(feature = "native_fs"))]
+const PROBE_COMMANDS: &[Command] = &[Command::ReadBlockAndSpare];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    commands: BTreeMap<Command, Support>,
+}
+
+impl Capabilities {
+    pub(crate) fn record(&mut self, command: Command, support: Support) {
+        self.commands.insert(command, support);
+    }
+
+    // anything that hasn't been probed or used yet is Unknown
+    pub fn supports(&self, command: Command) -> Support {
+        self.commands
+            .get(&command)
+            .copied()
+            .unwrap_or(Support::Unknown)
+    }
+
+    pub fn is_supported(&self, command: Command) -> bool {
+        self.supports(command) == Support::Supported
+    }
+
+    pub fn known(&self) -> impl Iterator<Item = (Command, Support)> + '_ {
+        self.commands.iter().map(|(&c, &s)| (c, s))
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub(crate) fn record_support(&self, command: Command, result: &Result<Vec<u32>>) {
+        let support = match result {
+            Ok(_) => Support::Supported,
+            Err(LibBBRDBError::IncorrectCmdResponse(..)) => Support::Unsupported,
+            Err(_) => return,
+        };
+        self.capabilities.borrow_mut().record(command, support);
+    }
+
+    // Supported or Unsupported once the command has been tried; Unknown if it couldn't be, say
+    // with no card in, in read-only mode, or with PROBE_FILE really on the card
+    pub(crate) fn probe_command(&self, command: Command) -> Support {
+        let known = self.capabilities.borrow().supports(command);
+        if known != Support::Unknown {
+            return known;
+        }
+
+        let rv = match command {
+            Command::ReadBlockAndSpare => {
+                self.probe_spare_reads();
+                let support = match self.spare_reads.get() {
+                    Some(true) => Support::Supported,
+                    Some(false) => Support::Unsupported,
+                    None => return Support::Unknown,
+                };
+                self.capabilities.borrow_mut().record(command, support);
+                return support;
+            }
+            Command::ReadFile => match self.ListFiles() {
+                Ok(files) if files.iter().all(|(name, _)| name != PROBE_FILE) => {
+                    self.native_probe(command)
+                }
+                _ => return Support::Unknown,
+            },
+            _ => return Support::Unknown,
+        };
+
+        self.record_probe(command, rv)
+    }
+
+    // sent once, without command_response's retries, since a command the console doesn't
+    // know is expected to go unanswered
+    pub(crate) fn record_probe(&self, command: Command, rv: Result<()>) -> Support {
+        let support = match rv {
+            Ok(()) => Support::Supported,
+            Err(e) if is_timeout(&e) || matches!(e, LibBBRDBError::IncorrectCmdResponse(..)) => {
+                debug!("probing {command:?}: {e}");
+                self.drain_input(DRAIN_TIMEOUT);
+                Support::Unsupported
+            }
+            Err(e) => {
+                debug!("couldn't probe {command:?}: {e}");
+                return Support::Unknown;
+            }
+        };
+        self.capabilities.borrow_mut().record(command, support);
+        support
+    }
+
+    // tries the optional commands that only read, and reports everything learnt so far; the
+    // ones that change the card stay Unknown until they're used or probe_native_changes runs
+    pub fn capabilities(&self) -> Capabilities {
+        for &command in PROBE_COMMANDS {
+            self.probe_command(command);
+        }
+
+        self.capabilities.borrow().clone()
+    }
+
+    pub fn reset_capabilities(&self) {
+        *self.capabilities.borrow_mut() = Default::default();
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::mpsc::{channel, Receiver};
+
+use rusb::UsbContext;
+
+use crate::commands::Command;
+use crate::error::*;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CardEvent {
+    Removed,
+    Inserted,
+    // reseated or swapped; anything read from the card before this may be out of date
+    Changed,
+}
+
+fn card_error(error: &LibBBRDBError) -> Option<&CardError> {
+    match error {
+        LibBBRDBError::CardError(e) => Some(e),
+        LibBBRDBError::RetriesExhausted(_, e) | LibBBRDBError::Context(_, e) => card_error(e),
+        _ => None,
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // every event from now on goes to each receiver; drop one to unsubscribe it
+    pub fn subscribe_card_events(&self) -> Receiver<CardEvent> {
+        let (tx, rx) = channel();
+        self.card_events.borrow_mut().push(tx);
+        rx
+    }
+
+    // the last thing seen happening to the card; None until Init or PollCard has looked
+    pub fn card_state(&self) -> Option<CardEvent> {
+        self.card_state.get()
+    }
+
+    pub(crate) fn observe_card(&self, now: CardEvent) {
+        let before = self.card_state.replace(Some(now));
+        // finding a card the first time we look isn't news
+        if before != Some(now) && (before.is_some() || now != CardEvent::Inserted) {
+            self.card_events
+                .borrow_mut()
+                .retain(|tx| tx.send(now).is_ok());
+        }
+    }
+
+    pub(crate) fn note_card_error(&self, error: &LibBBRDBError) {
+        match card_error(error) {
+            Some(CardError::NotPresent) => self.observe_card(CardEvent::Removed),
+            Some(CardError::Changed) => self.observe_card(CardEvent::Changed),
+            _ => {}
+        }
+    }
+
+    // asks the console whether the card is still there without reading it, for noticing a
+    // removal while nothing else is going on; true if there's a card
+    #[allow(non_snake_case)]
+    pub fn PollCard(&self) -> Result<bool> {
+        self.guarded(|this| {
+            let status = this.command_response(Command::GetSeqNo, 0, 1)?[0];
+            if (status as i32) < 0 {
+                let error = CardError::from_u32(status).into();
+                this.note_card_error(&error);
+                return match error {
+                    LibBBRDBError::CardError(CardError::NotPresent) => Ok(false),
+                    LibBBRDBError::CardError(CardError::Changed) => Ok(true),
+                    e => Err(e),
+                };
+            }
+
+            // after a Changed, it's re-initialising that says the card is ready again
+            if this.card_state() != Some(CardEvent::Changed) {
+                this.observe_card(CardEvent::Inserted);
+            }
+            Ok(true)
+        })
+    }
+}
@@ -0,0 +1,81 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::fs::{BlockIndex, FATEntry};
+use crate::listing::{ListedFile, Listing};
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockUse {
+    Free,
+    // the SKSA and the FAT generations
+    Reserved,
+    Bad,
+    // by the file in this slot of the file table
+    File(usize),
+    // allocated in the FAT, but no file's chain reaches it
+    Orphan,
+}
+
+// what every block on the card is for, as the FAT sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardMap {
+    pub blocks: Vec<BlockUse>,
+    pub files: Vec<ListedFile>,
+}
+
+impl CardMap {
+    // a block in more than one chain goes to the first file that reaches it; CheckFS reports
+    // the rest as cross-linked
+    pub fn from_listing(listing: &Listing) -> Self {
+        let mut blocks = listing
+            .fat
+            .iter()
+            .map(|e| match e {
+                FATEntry::Free => BlockUse::Free,
+                FATEntry::Reserved => BlockUse::Reserved,
+                FATEntry::BadBlock => BlockUse::Bad,
+                FATEntry::EndOfChain | FATEntry::Chain(_) => BlockUse::Orphan,
+            })
+            .collect::<Vec<_>>();
+
+        for f in &listing.files {
+            for &b in &f.blocks {
+                if let Some(u @ BlockUse::Orphan) = blocks.get_mut(b as usize) {
+                    *u = BlockUse::File(f.slot);
+                }
+            }
+        }
+
+        Self {
+            blocks,
+            files: listing.files.clone(),
+        }
+    }
+
+    pub fn owner(&self, block: BlockIndex) -> Option<&ListedFile> {
+        match self.blocks.get(block as usize)? {
+            BlockUse::File(slot) => self.files.iter().find(|f| f.slot == *slot),
+            _ => None,
+        }
+    }
+
+    pub fn count(&self, usage: BlockUse) -> usize {
+        self.blocks.iter().filter(|&&u| u == usage).count()
+    }
+
+    pub fn chain(&self, name: &str) -> Option<&[BlockIndex]> {
+        self.files
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| f.blocks.as_slice())
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn CardMap(&self) -> Result<CardMap> {
+        Ok(CardMap::from_listing(&self.Listing()?))
+    }
+}
@@ -1,15 +1,19 @@
 use core::num;
+use std::collections::BTreeMap;
 use std::mem::size_of;
 
+use log::warn;
 use rusb::UsbContext;
 
-use crate::constants::STATUS_OFFSET;
+use crate::constants::DRAIN_TIMEOUT;
 use crate::error::*;
 use crate::fs::Fat;
-use crate::rdb::RDBCommand;
+use crate::nand::BlockWithSpare;
+use crate::rdb::{to_u32, RDBCommand};
+use crate::spare::SpareData;
 use crate::Handle;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u32)]
 pub enum Command {
     Ping = 0x01,
@@ -53,6 +57,74 @@ pub enum Command {
     SignHash = 0x20,
 }
 
+// wire numbers for each command; everything seen so far uses the standard numbering, but a
+// console variant that differs only needs a table with overrides. The table is only ever set by
+// hand, through HandleBuilder::command_table or set_command_table: nothing picks one from the SK
+// IdentifySKSA finds, since reading the SK takes commands whose numbers the table decides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTable {
+    name: &'static str,
+    overrides: BTreeMap<Command, u32>,
+}
+
+impl Default for CommandTable {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl Command {
+    // the ones that change what's on the card
+    pub fn writes_card(self) -> bool {
+        matches!(
+            self,
+            Self::WriteBlock
+                | Self::WriteFile
+                | Self::DeleteFile
+                | Self::RenameFile
+                | Self::WriteBlockAndSpare
+                | Self::InitFS
+                | Self::WriteFileBlock
+                | Self::CreateFile
+        )
+    }
+
+    // safe to send again when the answer to the first one went missing
+    pub fn idempotent(self) -> bool {
+        !self.writes_card() && !matches!(self, Self::PowerOff | Self::ScanBlocks)
+    }
+}
+
+impl CommandTable {
+    pub fn standard() -> Self {
+        Self {
+            name: "standard",
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_override(mut self, command: Command, number: u32) -> Self {
+        self.overrides.insert(command, number);
+        self
+    }
+
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn number(&self, command: Command) -> u32 {
+        self.overrides
+            .get(&command)
+            .copied()
+            .unwrap_or(command as u32)
+    }
+}
+
 pub trait CommandArgs {
     fn encode(self) -> Vec<u8>;
 }
@@ -76,11 +148,20 @@ impl CommandArgs for &[u8] {
 }
 
 impl<C: UsbContext> Handle<C> {
+    pub fn command_table(&self) -> &CommandTable {
+        &self.command_table
+    }
+
+    // capabilities learnt under the old numbering no longer apply
+    pub fn set_command_table(&mut self, table: CommandTable) {
+        self.command_table = table;
+        self.reset_capabilities();
+    }
+
     fn write_data_len(&self, data: &[u8]) -> Result<()> {
-        let len = data.len();
-        assert!(len <= i32::MAX as usize);
+        let len = i32::try_from(data.len()).map_err(|_| LibBBRDBError::DataTooLarge(data.len()))?;
 
-        self.write_data(RDBCommand::HostData, (len as i32).to_be_bytes())?;
+        self.write_data(RDBCommand::HostData, len.to_be_bytes())?;
 
         self.write_data(RDBCommand::HostData, data)
     }
@@ -90,30 +171,38 @@ impl<C: UsbContext> Handle<C> {
     }
 
     pub(crate) fn send_command<T: CommandArgs>(&self, command: Command, args: T) -> Result<()> {
+        if command.writes_card() {
+            if self.read_only {
+                return Err(LibBBRDBError::ReadOnly);
+            }
+            self.card_writes.set(self.card_writes.get() + 1);
+        }
+
         let mut data = vec![];
 
-        data.extend((command as u32).to_be_bytes());
+        data.extend(self.command_table.number(command).to_be_bytes());
         data.extend(args.encode());
 
         self.write_data(RDBCommand::HostData, &data)
     }
 
     fn get_response(&self, len: usize) -> Result<Vec<u32>> {
-        self.read_data(len).map(|d| {
-            d.chunks(size_of::<u32>())
-                .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
-                .collect()
-        })
+        self.read_data(len)
+            .map(|d| d.chunks_exact(size_of::<u32>()).map(to_u32).collect())
     }
 
     pub(crate) fn check_cmd_response(&self, command: Command, len: usize) -> Result<Vec<u32>> {
         let data = self.get_response((len + 1) * size_of::<u32>())?;
         let c = data.first().map(u32::to_owned).unwrap_or_default();
-        if c != 255 - command as u32 {
-            return Err(LibBBRDBError::IncorrectCmdResponse(c, 255 - command as u32));
-        }
+        let expected = 255 - self.command_table.number(command);
+        let rv = if c != expected {
+            Err(LibBBRDBError::IncorrectCmdResponse(c, expected))
+        } else {
+            Ok(data[1..].to_vec())
+        };
 
-        Ok(data[1..].to_vec())
+        self.record_support(command, &rv);
+        rv
     }
 
     pub(crate) fn command_response<T: CommandArgs>(
@@ -122,16 +211,33 @@ impl<C: UsbContext> Handle<C> {
         args: T,
         len: usize,
     ) -> Result<Vec<u32>> {
-        self.send_command(command, args)?;
-        self.check_cmd_response(command, len)
+        let args = args.encode();
+
+        if !command.idempotent() {
+            self.send_command(command, args.as_slice())?;
+            return self.check_cmd_response(command, len);
+        }
+
+        let mut resend = false;
+        self.with_retries(|| {
+            // a late answer to the last try would otherwise be taken for this one's
+            if resend {
+                self.drain_input(DRAIN_TIMEOUT);
+            }
+            resend = true;
+
+            self.send_command(command, args.as_slice())?;
+            self.check_cmd_response(command, len)
+        })
     }
 
     pub(crate) fn read_blocks(&self, block: u32, num_blocks: u32) -> Result<Vec<u8>> {
-        let mut rv = vec![];
+        let mut rv = Vec::with_capacity(num_blocks as usize * self.layout.block_size);
 
         for blk in block..block + num_blocks {
             let status = self.command_response(Command::ReadBlock, blk, 1)?[0];
-            rv.extend(self.read_data(0x4000)?);
+            self.access.borrow_mut().record_read(blk);
+            self.read_data_into(self.layout.block_size, &mut rv)?;
 
             if status != 0 {
                 return Err(CardError::from_u32(status).into());
@@ -146,29 +252,62 @@ impl<C: UsbContext> Handle<C> {
         block: u32,
         num_blocks: u32,
     ) -> Result<(Vec<u8>, Vec<u8>)> {
-        let mut nand = vec![];
-        let mut spare = vec![];
+        let mut nand = Vec::with_capacity(num_blocks as usize * self.layout.block_size);
+        let mut spare = Vec::with_capacity(num_blocks as usize * self.layout.spare_size);
 
         for blk in block..block + num_blocks {
             let status = self.command_response(Command::ReadBlockAndSpare, blk, 1)?[0];
-            let n = self.read_data(0x4000)?;
-            let s = self.read_data(0x10)?;
+            self.access.borrow_mut().record_read(blk);
+            let (n, s) = (nand.len(), spare.len());
+            self.read_data_into(self.layout.block_size, &mut nand)?;
+            self.read_data_into(self.layout.spare_size, &mut spare)?;
 
-            if s[STATUS_OFFSET].count_zeros() > 1 {
-                return Err(CardError::BadBlock(n, s).into());
+            if SpareData::parse(&spare[s..])?.is_bad() {
+                return Err(CardError::BadBlock(nand.split_off(n), spare.split_off(s)).into());
             }
 
             if status != 0 {
                 return Err(CardError::from_u32(status).into());
             }
-
-            nand.extend(n);
-            spare.extend(s);
         }
 
         Ok((nand, spare))
     }
 
+    // some firmware rejects ReadBlockAndSpare outright. Block 0 is always there, so reading it
+    // decides, before anything else reads the card; the answer lasts as long as the handle
+    pub(crate) fn probe_spare_reads(&self) {
+        if self.spare_reads.get().is_some() {
+            return;
+        }
+
+        let supported = match self.read_blocks_spare(0, 1) {
+            Ok(_) => true,
+            // no way to tell until there's a card
+            Err(LibBBRDBError::CardError(CardError::NotPresent)) => return,
+            Err(LibBBRDBError::CardError(_)) => true,
+            Err(e) => {
+                warn!("spare reads unsupported ({e}), falling back to data-only reads");
+                self.drain_input(DRAIN_TIMEOUT);
+                false
+            }
+        };
+        self.spare_reads.set(Some(supported));
+    }
+
+    pub(crate) fn read_block_with_spare(&self, block: u32) -> Result<BlockWithSpare> {
+        if self.spare_reads.get() != Some(false) {
+            match self.read_blocks_spare(block, 1) {
+                Err(e @ LibBBRDBError::IncorrectCmdResponse(..)) => {
+                    warn!("block {block}: spare read failed ({e}), reading data only");
+                }
+                r => return r.and_then(|(n, s)| BlockWithSpare::new(n, s)),
+            }
+        }
+
+        BlockWithSpare::synthesised(self.read_blocks(block, 1)?)
+    }
+
     pub(crate) fn write_blocks(&mut self, block: u32, data: &[&[u8]]) -> Result<()> {
         for (index, nand) in data.iter().enumerate() {
             let index = index as u32;
@@ -176,6 +315,7 @@ impl<C: UsbContext> Handle<C> {
             let blk = block + index;
 
             self.send_command(Command::WriteBlock, blk)?;
+            self.access.borrow_mut().record_write(blk);
             self.write_data(RDBCommand::HostData, nand)?;
 
             let status = self.check_cmd_response(Command::WriteBlock, 1)?[0];
@@ -194,6 +334,7 @@ impl<C: UsbContext> Handle<C> {
             let blk = block + index;
 
             self.send_command(Command::WriteBlockAndSpare, blk)?;
+            self.access.borrow_mut().record_write(blk);
             self.write_data(RDBCommand::HostData, nand)?;
             self.write_data(RDBCommand::HostData, spare)?;
 
@@ -221,6 +362,9 @@ impl<C: UsbContext> Handle<C> {
             return Err(LibBBRDBError::UnhandledCardSize);
         };
 
+        // the FAT is read with ReadBlockAndSpare where the console has it
+        self.probe_spare_reads();
+
         match self.read_fat(cardsize) {
             Ok(f) => Ok(Some((Some(f), cardsize))),
             Err(LibBBRDBError::NoFAT) => Ok(Some((None, cardsize))),
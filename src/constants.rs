@@ -14,12 +14,25 @@ pub(crate) const RDB_BULK_EP_IN: u8 = 0x82;
 pub(crate) const RDB_BLOCK_SIZE: usize = 256 - 2 * size_of::<u8>();
 pub(crate) const RDB_BLOCKS_PER_CHUNK: usize = 80;
 
-pub(crate) const BLOCK_SIZE: usize = 0x4000;
+pub const BLOCK_SIZE: usize = 0x4000;
 pub(crate) const BLOCK_CHUNK_SIZE: usize = 0x1000;
-pub(crate) const SPARE_SIZE: usize = 0x10;
+pub const SPARE_SIZE: usize = 0x10;
+
+pub(crate) const RAMROM_REQUEST_SIZE: usize = 2 * size_of::<u32>();
+pub(crate) const RAMROM_MAX_TRANSFER: u32 = 0x10000;
 
 pub(crate) const TIMEOUT: Duration = Duration::from_secs(1);
+pub(crate) const QUEUE_DEPTH: usize = 4;
+// a scan of a large card takes several seconds, and the console says nothing until it's done
+pub(crate) const SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+// a read_data that's had nothing new for this long has stalled, however the transfers are doing
+pub(crate) const READ_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+// and one gets this long, plus a second for every READ_MIN_RATE bytes, before it's abandoned
+pub(crate) const READ_DEADLINE: Duration = Duration::from_secs(30);
+pub(crate) const READ_MIN_RATE: usize = 0x8000;
+// how long the stream has to be quiet before a stalled read counts as flushed
+pub(crate) const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+// how long reset gives a console that restarted its end to announce itself
+pub(crate) const RESET_SYNC_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub(crate) const NUM_FATS: u32 = 16;
-
-pub(crate) const STATUS_OFFSET: usize = 5;
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use rusb::UsbContext;
+use sha1::{Digest, Sha1};
+
+use crate::error::*;
+use crate::saves::{content_file, CONTENT_EXTENSIONS};
+use crate::ticket::Ticket;
+use crate::Handle;
+
+// the CmdHead hash is of the decrypted content, so checking it needs the title key; a caller
+// that has one returns the plaintext, or None if it can't decrypt this title
+pub type ContentDecryptor<'a> = dyn Fn(&Ticket, &[u8]) -> Option<Vec<u8>> + 'a;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ContentStatus {
+    Ok,
+    // the size and chain are fine, but there was no way to check the hash
+    Unverified,
+    NoTicket,
+    Unreadable(String),
+    Truncated { expected: u32, actual: usize },
+    HashMismatch,
+}
+
+impl ContentStatus {
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            Self::Unreadable(_) | Self::Truncated { .. } | Self::HashMismatch
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContentCheck {
+    pub content_id: u32,
+    pub name: String,
+    pub status: ContentStatus,
+}
+
+// trouble with one file's blocks or chain, rather than with the console or the connection
+fn file_problem(e: &LibBBRDBError) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Card | ErrorKind::Filesystem | ErrorKind::Verification
+    )
+}
+
+impl<C: UsbContext> Handle<C> {
+    fn check_content(
+        &self,
+        name: &str,
+        ticket: Option<&Ticket>,
+        decrypt: Option<&ContentDecryptor>,
+    ) -> Result<ContentStatus> {
+        let Some(ticket) = ticket else {
+            return Ok(ContentStatus::NoTicket);
+        };
+
+        let data = match self.ReadFile(name) {
+            Ok(Some(d)) => d,
+            Ok(None) => return Err(LibBBRDBError::FileNotFound(name.to_string())),
+            Err(e) if file_problem(&e) => return Ok(ContentStatus::Unreadable(e.to_string())),
+            Err(e) => return Err(e),
+        };
+
+        let expected = ticket.head.size;
+        if data.len() < expected as usize {
+            return Ok(ContentStatus::Truncated {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        let Some(plain) = decrypt.and_then(|d| d(ticket, &data)) else {
+            return Ok(ContentStatus::Unverified);
+        };
+
+        let hash = plain
+            .get(..expected as usize)
+            .map(|p| <[u8; 20]>::from(Sha1::digest(p)));
+        Ok(if hash == Some(ticket.head.hash) {
+            ContentStatus::Ok
+        } else {
+            ContentStatus::HashMismatch
+        })
+    }
+
+    // reads every .app/.aes/.rec and checks it against the ticket for its title
+    #[allow(non_snake_case)]
+    pub fn VerifyContent(&self, decrypt: Option<&ContentDecryptor>) -> Result<Vec<ContentCheck>> {
+        self.audited("VerifyContent", vec![], |this| {
+            let tickets = this.ReadTickets()?.unwrap_or_default();
+            let by_id = tickets
+                .tickets
+                .iter()
+                .map(|t| (t.content_id(), t))
+                .collect::<BTreeMap<_, _>>();
+
+            let files = this
+                .ListFiles()?
+                .into_iter()
+                .filter_map(|(name, _)| match content_file(&name) {
+                    Some((id, ext)) if CONTENT_EXTENSIONS.contains(&ext.as_str()) => {
+                        Some((id, name))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            // each ReadFile shows its own progress
+            let mut rv = Vec::with_capacity(files.len());
+            for (content_id, name) in files {
+                let status = this
+                    .check_content(&name, by_id.get(&content_id).copied(), decrypt)
+                    .for_file("VerifyContent", &name)?;
+                rv.push(ContentCheck {
+                    content_id,
+                    name,
+                    status,
+                });
+            }
+
+            Ok(rv)
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::Cursor;
+
+use binrw::{binread, BinRead};
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::sksa_info::c_string;
+use crate::Handle;
+
+pub const CRL_FILE: &str = "crl.sys";
+
+const CRL_SIGNATURE_SIZE: usize = 0x200;
+// everything up to the revoked names
+const CRL_HEAD_SIZE: usize = CRL_SIGNATURE_SIZE + 0x14 + 0x40 + 4;
+
+// one revocation list; crl.sys holds one for each authority that has revoked anything
+#[binread]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[br(big)]
+pub struct Crl {
+    #[br(pad_before = CRL_SIGNATURE_SIZE)]
+    pub kind: u32,
+    pub sig_type: u32,
+    // compared against the CA and CP CRL versions in a CmdHead
+    #[br(pad_before = 4)]
+    pub version: u32,
+    pub date: u32,
+    #[br(map = c_string)]
+    pub issuer: String,
+    #[br(temp)]
+    count: u32,
+    // names of the revoked certificates, relative to the issuer
+    #[br(count = count, map = |v: Vec<[u8; 64]>| v.into_iter().map(c_string).collect())]
+    pub revoked: Vec<String>,
+}
+
+impl Crl {
+    // the whole list goes until a blank head, or the end of the file
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Self>> {
+        let mut cursor = Cursor::new(data);
+        let mut rv = vec![];
+
+        while let Some(head) = data.get(cursor.position() as usize..) {
+            if head.len() < CRL_HEAD_SIZE || head[..CRL_HEAD_SIZE].iter().all(|&b| b == 0) {
+                break;
+            }
+            rv.push(Self::read(&mut cursor)?);
+        }
+
+        Ok(rv)
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // None if the card has no crl.sys, which a card that's never seen a revocation may not
+    #[allow(non_snake_case)]
+    pub fn ReadCrls(&self) -> Result<Option<Vec<Crl>>> {
+        self.ReadFile(CRL_FILE)?
+            .map(|d| Crl::parse_all(&d))
+            .transpose()
+            .for_file("ReadCrls", CRL_FILE)
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+
+use log::debug;
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::rdb::RDBCommand;
+use crate::Handle;
+
+// who a packet from the console is meant for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Command,
+    Console,
+    Debug,
+    RamRom,
+}
+
+impl RDBCommand {
+    pub(crate) fn channel(self) -> Channel {
+        match self {
+            Self::DevicePrint | Self::DeviceLogCT | Self::DeviceLog | Self::DeviceFault => {
+                Channel::Console
+            }
+
+            Self::DeviceDebug
+            | Self::DeviceDebugDone
+            | Self::DeviceDebugReady
+            | Self::DeviceKDebug
+            | Self::DeviceProfData => Channel::Debug,
+
+            Self::DeviceRamRom => Channel::RamRom,
+
+            _ => Channel::Command,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Demux {
+    command: VecDeque<(RDBCommand, Vec<u8>)>,
+    console: VecDeque<(RDBCommand, Vec<u8>)>,
+    debug: VecDeque<(RDBCommand, Vec<u8>)>,
+    ramrom: VecDeque<(RDBCommand, Vec<u8>)>,
+}
+
+impl Demux {
+    pub(crate) fn clear(&mut self) {
+        *self = Default::default();
+    }
+
+    pub(crate) fn take_all(&mut self) -> Vec<(RDBCommand, Vec<u8>)> {
+        let all = std::mem::take(self);
+        [all.command, all.console, all.debug, all.ramrom]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    fn queue(&mut self, channel: Channel) -> &mut VecDeque<(RDBCommand, Vec<u8>)> {
+        match channel {
+            Channel::Command => &mut self.command,
+            Channel::Console => &mut self.console,
+            Channel::Debug => &mut self.debug,
+            Channel::RamRom => &mut self.ramrom,
+        }
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub(crate) fn route_packet(&self, cmd: RDBCommand, data: Vec<u8>) {
+        debug!("queueing {cmd:?} for {:?}", cmd.channel());
+        self.demux
+            .borrow_mut()
+            .queue(cmd.channel())
+            .push_back((cmd, data));
+    }
+
+    // returns the next packet for `channel`, queueing anything else that arrives in the meantime
+    pub(crate) fn read_packet_for(&self, channel: Channel) -> Result<(RDBCommand, Vec<u8>)> {
+        if let Some(p) = self.demux.borrow_mut().queue(channel).pop_front() {
+            return Ok(p);
+        }
+
+        loop {
+            let (cmd, data) = self.read_raw_rdb_packet()?;
+            if cmd == RDBCommand::DeviceSync {
+                self.acknowledge_sync()?;
+                return Err(LibBBRDBError::ConsoleResynced);
+            }
+            if cmd.channel() == channel {
+                return Ok((cmd, data));
+            }
+            self.route_packet(cmd, data);
+        }
+    }
+
+    // faults, debugger and profiler traffic that arrived while something else was reading
+    pub fn take_debug_packets(&self) -> Vec<(RDBCommand, Vec<u8>)> {
+        self.demux.borrow_mut().debug.drain(..).collect()
+    }
+}
@@ -0,0 +1,109 @@
+use std::ops::Range;
+
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::layout::CardLayout;
+use crate::progress::ProgressUnit;
+use crate::Handle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionDiff {
+    pub name: &'static str,
+    pub blocks: Range<u32>,
+    pub changed: Vec<u32>,
+}
+
+// which blocks differ between two NAND images, by the area of the card they're in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NandDiff {
+    pub regions: Vec<RegionDiff>,
+    // blocks the card wouldn't give back, so they couldn't be compared
+    pub unreadable: Vec<u32>,
+}
+
+impl NandDiff {
+    fn new(layout: &CardLayout, cardsize: u32, changed: Vec<u32>, unreadable: Vec<u32>) -> Self {
+        let regions = layout
+            .regions(cardsize)
+            .into_iter()
+            .filter(|(_, r)| !r.is_empty())
+            .map(|(name, blocks)| RegionDiff {
+                name,
+                changed: changed
+                    .iter()
+                    .copied()
+                    .filter(|b| blocks.contains(b))
+                    .collect(),
+                blocks,
+            })
+            .collect();
+
+        Self {
+            regions,
+            unreadable,
+        }
+    }
+
+    pub fn changed(&self) -> impl Iterator<Item = u32> + '_ {
+        self.regions.iter().flat_map(|r| r.changed.iter().copied())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed().next().is_none() && self.unreadable.is_empty()
+    }
+}
+
+// both images are plain NAND data, without any spare, covering the whole card
+pub fn diff_nand(layout: &CardLayout, old: &[u8], new: &[u8]) -> Result<NandDiff> {
+    if old.len() != new.len() || !old.len().is_multiple_of(layout.block_size) {
+        return Err(LibBBRDBError::WrongDataLength);
+    }
+
+    let changed = old
+        .chunks(layout.block_size)
+        .zip(new.chunks(layout.block_size))
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, _)| i as u32)
+        .collect();
+
+    Ok(NandDiff::new(
+        layout,
+        (old.len() / layout.block_size) as u32,
+        changed,
+        vec![],
+    ))
+}
+
+impl<C: UsbContext> Handle<C> {
+    // what's changed on the card since `image`, a plain dump of all of it, was taken
+    #[allow(non_snake_case)]
+    pub fn DiffWithCard(&self, image: &[u8]) -> Result<NandDiff> {
+        self.audited("DiffWithCard", vec![], |this| {
+            let cardsize = this.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+            let block_size = this.layout.block_size;
+            if image.len() != cardsize as usize * block_size {
+                return Err(LibBBRDBError::WrongDataLength);
+            }
+
+            let mut changed = vec![];
+            let mut unreadable = vec![];
+
+            let progress =
+                this.progress_task("Comparing", Some(cardsize as u64), ProgressUnit::Blocks);
+            for (i, expected) in image.chunks(block_size).enumerate() {
+                let block = i as u32;
+                match this.read_blocks(block, 1) {
+                    Ok(data) if data != expected => changed.push(block),
+                    Ok(_) => {}
+                    Err(LibBBRDBError::CardError(_)) => unreadable.push(block),
+                    Err(e) => return Err(e).at_block("DiffWithCard", block),
+                }
+                progress.advance(1);
+            }
+
+            Ok(NandDiff::new(&this.layout, cardsize, changed, unreadable))
+        })
+    }
+}
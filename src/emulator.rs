@@ -0,0 +1,94 @@
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+use log::warn;
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::fs::fat_from_image;
+use crate::kernel::{sksa_from_image, write_sksa_files};
+use crate::layout::CardLayout;
+use crate::nand::NandImage;
+use crate::Handle;
+
+// what export_emulator_layout wrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmulatorExport {
+    pub files: Vec<(String, usize)>,
+    // files whose chains are broken in the image, or whose names can't be used on the host
+    pub skipped: Vec<String>,
+    pub seqno: u32,
+    pub sa2: bool,
+}
+
+fn host_safe(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+// lays a dump with its spare out as iQue emulation setups expect it:
+//   nand.bin, spare.bin  the whole card, data and spare apart
+//   sksa/                sk.bin, sa1.bin, sa2.bin and sksa.json, as DumpSKSAFiles writes them
+//   fs.bin               the current FAT generation's blocks, BBFS block first
+//   files/               every file on the card: each title's .app and .rec, tickets, saves
+pub fn export_emulator_layout<P: AsRef<Path>>(
+    image: &NandImage,
+    layout: &CardLayout,
+    dir: P,
+) -> Result<EmulatorExport> {
+    let dir = dir.as_ref();
+    let block_size = layout.block_size;
+
+    if !image.synthesised_spare().is_empty() {
+        warn!(
+            "{} blocks have no real spare data; the SA chain may not be followable",
+            image.synthesised_spare().len()
+        );
+    }
+
+    let sksa = sksa_from_image(image)?;
+    let fat = fat_from_image(layout, image.nand())?;
+
+    create_dir_all(dir)?;
+    write(dir.join("nand.bin"), image.nand())?;
+    write(dir.join("spare.bin"), image.spare())?;
+
+    write_sksa_files(&sksa, &dir.join("sksa"))?;
+
+    let mut fs = Vec::with_capacity(fat.locations().len() * block_size);
+    for &b in fat.locations() {
+        let offset = b as usize * block_size;
+        fs.extend(&image.nand()[offset..offset + block_size]);
+    }
+    write(dir.join("fs.bin"), fs)?;
+
+    let files_dir = dir.join("files");
+    create_dir_all(&files_dir)?;
+
+    let mut files = vec![];
+    let mut skipped = vec![];
+    for (name, data) in fat.file_contents(image.nand(), block_size) {
+        match data {
+            Some(data) if host_safe(&name) => {
+                write(files_dir.join(&name), &data)?;
+                files.push((name, data.len()));
+            }
+            _ => skipped.push(name),
+        }
+    }
+
+    Ok(EmulatorExport {
+        files,
+        skipped,
+        seqno: fat.seqno(),
+        sa2: sksa.sa2.is_some(),
+    })
+}
+
+impl<C: UsbContext> Handle<C> {
+    // dumps the whole card with its spare and exports that, in one go
+    #[allow(non_snake_case)]
+    pub fn ExportEmulatorLayout<P: AsRef<Path>>(&self, dir: P) -> Result<EmulatorExport> {
+        let (image, _) = self.DumpNANDSpare()?;
+        export_emulator_layout(&image, &self.layout, dir)
+    }
+}
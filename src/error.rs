@@ -1,3 +1,5 @@
+use std::fmt;
+
 use thiserror::Error;
 
 use crate::rdb::RDBCommand;
@@ -37,6 +39,93 @@ impl CardError {
             x => Self::Unknown(x),
         }
     }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotPresent => "card_not_present",
+            Self::Failure => "card_failure",
+            Self::Invalid => "card_invalid",
+            Self::Changed => "card_changed",
+            Self::Unknown(_) => "card_unknown",
+            Self::BadBlock(..) => "card_bad_block",
+        }
+    }
+
+    // the status the console actually sent, where there was one
+    pub fn raw(&self) -> Option<i32> {
+        match self {
+            Self::NotPresent => Some(-1),
+            Self::Failure => Some(-2),
+            Self::Invalid => Some(-3),
+            Self::Changed => Some(-4),
+            Self::Unknown(x) => Some(*x),
+            Self::BadBlock(..) => None,
+        }
+    }
+}
+
+// broad groups of error codes, numbered for exit codes. 0-2 are left alone since they already
+// mean success, failure and bad usage to a shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ErrorKind {
+    Usb = 3,
+    Timeout = 4,
+    NoDevice = 5,
+    NotInitialised = 6,
+    Protocol = 7,
+    CardNotPresent = 8,
+    Card = 9,
+    Filesystem = 10,
+    NotFound = 11,
+    InvalidInput = 12,
+    Io = 13,
+    Verification = 14,
+    Unsupported = 15,
+    Internal = 16,
+    ReadOnly = 17,
+}
+
+impl ErrorKind {
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+}
+
+// what was going on when an error happened, so a failure deep in a long operation says where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: &'static str,
+    pub block: Option<u32>,
+    pub file: Option<String>,
+    pub offset: Option<u64>,
+}
+
+impl ErrorContext {
+    fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            block: None,
+            file: None,
+            offset: None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if let Some(file) = &self.file {
+            write!(f, " of {file}")?;
+        }
+        if let Some(block) = self.block {
+            write!(f, " at block {block}")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {offset:#X}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Error)]
@@ -50,6 +139,9 @@ pub enum LibBBRDBError {
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
 
+    #[error("No iQue Player found: {0}")]
+    NoDeviceFound(String),
+
     #[error("Device not initialised. Did you call Init?")]
     NotInitialised,
 
@@ -74,9 +166,18 @@ pub enum LibBBRDBError {
     #[error("Unexpected RDB command (got {0:?}, expected one of {1:?}")]
     RDBUnexpected(RDBCommand, Vec<RDBCommand>),
 
+    #[error("RAMROM request too large: {0:#X} bytes at offset {1:#X}")]
+    RamRomRequestTooLarge(u32, u32),
+
     #[error("Card size must be a multiple of 4096 blocks")]
     UnhandledCardSize,
 
+    #[error("Refusing to write block {0:#X}, which is in the reserved area")]
+    ReservedAreaWrite(u32),
+
+    #[error("Too much data for one transfer: {0:#X} bytes")]
+    DataTooLarge(usize),
+
     #[error("Card error: {0}")]
     CardError(#[from] CardError),
 
@@ -86,9 +187,30 @@ pub enum LibBBRDBError {
     #[error("No valid FATs were found")]
     NoFAT,
 
+    #[error("FAT block {0:04X} did not read back correctly after writing")]
+    FATVerifyFailed(u32),
+
+    #[error("No FAT slots are free to write a new generation to")]
+    NoFATSlots,
+
     #[error("File not found: {0}")]
     FileNotFound(String),
 
+    #[error("A file called {0} already exists")]
+    FileExists(String),
+
+    #[error("Can't recover {0}: {1}")]
+    NotRecoverable(String, String),
+
+    #[error("FAT chain for {0} loops back on block {1:04X}")]
+    ChainLoop(String, u32),
+
+    #[error("{1:#X} blocks from {0:#X} run past the end of the card ({2:#X} blocks)")]
+    BlockRangeOutOfBounds(u32, u32, u32),
+
+    #[error("Block {0:#X} can't be referenced from the FAT")]
+    BlockIndexTooLarge(u32),
+
     #[error("Filename \"{0}\" too long (max 8.3)")]
     FileNameTooLong(String),
 
@@ -107,8 +229,262 @@ pub enum LibBBRDBError {
     #[error("Failed to verify file {0} (expected checksum {1:08X})")]
     ChecksumFailed(String, u32),
 
+    #[error("Invalid block layout: {0:#X} bytes of data with {1:#X} bytes of spare")]
+    InvalidBlockLayout(usize, usize),
+
+    #[error("Block {0} no longer matches the expected contents; not writing")]
+    BlockChanged(u32),
+
     #[error("Set time: returned {0} (error)")]
     SetTime(i32),
+
+    #[error("Invalid time: {0}")]
+    InvalidTime(String),
+
+    #[error("Gave up after {0} attempts: {1}")]
+    RetriesExhausted(u32, Box<LibBBRDBError>),
+
+    #[error("The console restarted its end of the connection; the operation was abandoned")]
+    ConsoleResynced,
+
+    #[error(
+        "Lost track of the console's data at byte {0:02X}; {1:#X} bytes were discarded to resync"
+    )]
+    Desynced(u8, usize),
+
+    #[error("Timed out waiting for the console to sync")]
+    SyncTimeout,
+
+    #[error("Gave up reading from the console ({2}) with {0:#X} of {1:#X} bytes")]
+    ReadStalled(usize, usize, &'static str),
+
+    #[error("Internal error in bbrdb (this is a bug): {0}")]
+    InternalError(String),
+
+    #[error("Invalid SKSA: {0}")]
+    InvalidSKSA(String),
+
+    #[error("Invalid capture line: {0}")]
+    InvalidCapture(String),
+
+    #[error("Invalid backup archive: {0}")]
+    InvalidBackup(String),
+
+    #[error("Invalid ticket.sys: {0}")]
+    InvalidTicket(String),
+
+    #[error("Invalid sig.db: {0}")]
+    InvalidSigDb(String),
+
+    #[error("The background worker has stopped")]
+    WorkerGone,
+
+    #[error("The handle is read-only")]
+    ReadOnly,
+
+    #[error("Not supported by this console: {0}")]
+    Unsupported(&'static str),
+
+    #[error("{0}: {1}")]
+    Context(ErrorContext, Box<LibBBRDBError>),
+}
+
+impl LibBBRDBError {
+    // the one stable identifier for an error, for callers that can't match on the enum: kind()
+    // and so the exit number are worked out from it. Never change, regroup or renumber one
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::LibUSBError(rusb::Error::Timeout) => "usb_timeout",
+            Self::LibUSBError(rusb::Error::NoDevice) => "usb_no_device",
+            Self::LibUSBError(rusb::Error::Access) => "usb_access",
+            Self::LibUSBError(rusb::Error::Busy) => "usb_busy",
+            Self::LibUSBError(_) => "usb",
+            Self::BinRWError(_) => "parse",
+            Self::IOError(_) => "io",
+            Self::NoDeviceFound(_) => "no_device_found",
+            Self::NotInitialised => "not_initialised",
+            Self::IncorrectDescriptor => "incorrect_descriptor",
+            Self::WrongDataLength => "wrong_data_length",
+            Self::RDBUnknown(_) => "rdb_unknown",
+            Self::RDBUnhandled(_) => "rdb_unhandled",
+            Self::IncorrectCmdResponse(..) => "incorrect_cmd_response",
+            Self::PlayerNotReady => "player_not_ready",
+            Self::RDBUnexpected(..) => "rdb_unexpected",
+            Self::RamRomRequestTooLarge(..) => "ramrom_request_too_large",
+            Self::UnhandledCardSize => "unhandled_card_size",
+            Self::ReservedAreaWrite(_) => "reserved_area_write",
+            Self::DataTooLarge(_) => "data_too_large",
+            Self::CardError(e) => e.code(),
+            Self::InvalidFATChecksum(_) => "invalid_fat_checksum",
+            Self::NoFAT => "no_fat",
+            Self::FATVerifyFailed(_) => "fat_verify_failed",
+            Self::NoFATSlots => "no_fat_slots",
+            Self::ChainLoop(..) => "chain_loop",
+            Self::BlockIndexTooLarge(_) => "block_index_too_large",
+            Self::BlockRangeOutOfBounds(..) => "block_range_out_of_bounds",
+            Self::FileNotFound(_) => "file_not_found",
+            Self::FileExists(_) => "file_exists",
+            Self::NotRecoverable(..) => "not_recoverable",
+            Self::FileNameTooLong(_) => "file_name_too_long",
+            Self::InvalidFilename(_) => "invalid_filename",
+            Self::IncorrectNumBlocks(..) => "incorrect_num_blocks",
+            Self::NoEmptyFileSlots => "no_empty_file_slots",
+            Self::NoFreeBlocks => "no_free_blocks",
+            Self::ChecksumFailed(..) => "checksum_failed",
+            Self::InvalidBlockLayout(..) => "invalid_block_layout",
+            Self::BlockChanged(_) => "block_changed",
+            Self::SetTime(_) => "set_time",
+            Self::InvalidTime(_) => "invalid_time",
+            Self::RetriesExhausted(..) => "retries_exhausted",
+            Self::ConsoleResynced => "console_resynced",
+            Self::Desynced(..) => "desynced",
+            Self::SyncTimeout => "sync_timeout",
+            Self::ReadStalled(..) => "read_stalled",
+            Self::InternalError(_) => "internal_error",
+            Self::InvalidSKSA(_) => "invalid_sksa",
+            Self::InvalidCapture(_) => "invalid_capture",
+            Self::InvalidBackup(_) => "invalid_backup",
+            Self::InvalidTicket(_) => "invalid_ticket",
+            Self::InvalidSigDb(_) => "invalid_sig_db",
+            Self::WorkerGone => "worker_gone",
+            Self::ReadOnly => "read_only",
+            Self::Unsupported(_) => "unsupported",
+            Self::Context(_, e) => e.code(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        // a retried failure is whatever the last attempt was
+        if let Self::RetriesExhausted(_, e) = self {
+            return e.kind();
+        }
+
+        match self.code() {
+            "usb_timeout" | "sync_timeout" | "read_stalled" => ErrorKind::Timeout,
+            "usb_no_device" | "no_device_found" => ErrorKind::NoDevice,
+            "usb_access" | "usb_busy" | "usb" => ErrorKind::Usb,
+            "io" => ErrorKind::Io,
+            "not_initialised" => ErrorKind::NotInitialised,
+            "parse"
+            | "incorrect_descriptor"
+            | "wrong_data_length"
+            | "rdb_unknown"
+            | "rdb_unhandled"
+            | "incorrect_cmd_response"
+            | "player_not_ready"
+            | "rdb_unexpected"
+            | "console_resynced"
+            | "desynced" => ErrorKind::Protocol,
+            "card_not_present" => ErrorKind::CardNotPresent,
+            "card_failure" | "card_invalid" | "card_changed" | "card_unknown"
+            | "card_bad_block" | "set_time" => ErrorKind::Card,
+            "invalid_fat_checksum"
+            | "no_fat"
+            | "no_fat_slots"
+            | "chain_loop"
+            | "no_empty_file_slots"
+            | "no_free_blocks"
+            | "file_exists"
+            | "not_recoverable" => ErrorKind::Filesystem,
+            "file_not_found" => ErrorKind::NotFound,
+            "ramrom_request_too_large"
+            | "reserved_area_write"
+            | "data_too_large"
+            | "file_name_too_long"
+            | "invalid_filename"
+            | "incorrect_num_blocks"
+            | "invalid_block_layout"
+            | "block_range_out_of_bounds"
+            | "invalid_time"
+            | "invalid_sksa"
+            | "invalid_capture"
+            | "invalid_backup"
+            | "invalid_ticket"
+            | "invalid_sig_db" => ErrorKind::InvalidInput,
+            "fat_verify_failed" | "checksum_failed" | "block_changed" => ErrorKind::Verification,
+            "unhandled_card_size" | "block_index_too_large" | "unsupported" => {
+                ErrorKind::Unsupported
+            }
+            "read_only" => ErrorKind::ReadOnly,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    // the underlying error, with any context stripped off
+    pub fn root(&self) -> &LibBBRDBError {
+        match self {
+            Self::Context(_, e) => e.root(),
+            e => e,
+        }
+    }
+
+    pub fn into_root(self) -> LibBBRDBError {
+        match self {
+            Self::Context(_, e) => e.into_root(),
+            e => e,
+        }
+    }
+
+    // the outermost context, if there is any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context(c, _) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) trait ResultExt<T> {
+    fn in_operation(self, operation: &'static str) -> Result<T>;
+    fn at_block(self, operation: &'static str, block: u32) -> Result<T>;
+    fn for_file(self, operation: &'static str, file: &str) -> Result<T>;
+    fn at_offset(self, operation: &'static str, offset: u64) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn in_operation(self, operation: &'static str) -> Result<T> {
+        self.map_err(|e| LibBBRDBError::Context(ErrorContext::new(operation), Box::new(e)))
+    }
+
+    fn at_block(self, operation: &'static str, block: u32) -> Result<T> {
+        self.map_err(|e| {
+            let mut context = ErrorContext::new(operation);
+            context.block = Some(block);
+            LibBBRDBError::Context(context, Box::new(e))
+        })
+    }
+
+    fn for_file(self, operation: &'static str, file: &str) -> Result<T> {
+        self.map_err(|e| {
+            let mut context = ErrorContext::new(operation);
+            context.file = Some(file.to_string());
+            LibBBRDBError::Context(context, Box::new(e))
+        })
+    }
+
+    fn at_offset(self, operation: &'static str, offset: u64) -> Result<T> {
+        self.map_err(|e| {
+            let mut context = ErrorContext::new(operation);
+            context.offset = Some(offset);
+            LibBBRDBError::Context(context, Box::new(e))
+        })
+    }
+}
+
+pub(crate) fn is_timeout(error: &LibBBRDBError) -> bool {
+    match error {
+        LibBBRDBError::LibUSBError(rusb::Error::Timeout) => true,
+        LibBBRDBError::RetriesExhausted(_, e) | LibBBRDBError::Context(_, e) => is_timeout(e),
+        _ => false,
+    }
+}
+
+pub(crate) fn is_card_changed(error: &LibBBRDBError) -> bool {
+    match error {
+        LibBBRDBError::CardError(CardError::Changed) => true,
+        LibBBRDBError::RetriesExhausted(_, e) | LibBBRDBError::Context(_, e) => is_card_changed(e),
+        _ => false,
+    }
 }
 
 pub(crate) fn wrap_libusb_error<T>(value: rusb::Result<T>) -> Result<T> {
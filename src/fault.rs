@@ -0,0 +1,113 @@
+use std::io::Cursor;
+
+use binrw::{binread, BinRead};
+
+use crate::error::*;
+
+// libultra sends the whole OSThread of the faulting thread: a 0x20-byte header followed by
+// its saved __OSThreadContext
+pub(crate) const FAULT_SIZE: usize = 0x1B0;
+
+pub const GPR_NAMES: [&str; 29] = [
+    "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "s0",
+    "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "gp", "sp", "s8", "ra",
+];
+
+#[binread]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[br(big)]
+pub struct FaultReport {
+    #[br(pad_before = 4)]
+    pub priority: i32,
+    #[br(pad_before = 8)]
+    pub state: u16,
+    pub flags: u16,
+    pub thread_id: i32,
+    #[br(pad_after = 4)]
+    pub fp_used: i32,
+
+    pub gpr: [u64; 29],
+    pub lo: u64,
+    pub hi: u64,
+    pub sr: u32,
+    pub epc: u32,
+    pub cause: u32,
+    pub badvaddr: u32,
+    pub rcp: u32,
+    pub fpcsr: u32,
+    // raw bits; use f64::from_bits to interpret
+    pub fpr: [u64; 16],
+}
+
+impl FaultReport {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < FAULT_SIZE {
+            return Err(LibBBRDBError::WrongDataLength);
+        }
+
+        Ok(Self::read(&mut Cursor::new(data))?)
+    }
+
+    pub fn exception_code(&self) -> u32 {
+        (self.cause >> 2) & 0x1F
+    }
+
+    pub fn exception_name(&self) -> &'static str {
+        match self.exception_code() {
+            0 => "Interrupt",
+            1 => "TLB modification",
+            2 => "TLB miss (load/fetch)",
+            3 => "TLB miss (store)",
+            4 => "Address error (load/fetch)",
+            5 => "Address error (store)",
+            6 => "Bus error (fetch)",
+            7 => "Bus error (data)",
+            8 => "Syscall",
+            9 => "Breakpoint",
+            10 => "Reserved instruction",
+            11 => "Coprocessor unusable",
+            12 => "Arithmetic overflow",
+            13 => "Trap",
+            15 => "Floating point",
+            23 => "Watch",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn in_branch_delay_slot(&self) -> bool {
+        self.cause & 0x8000_0000 != 0
+    }
+
+    pub fn registers(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        GPR_NAMES.iter().copied().zip(self.gpr.iter().copied())
+    }
+}
+
+impl std::fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Fault in thread {}: {} (cause {:08X}){}",
+            self.thread_id,
+            self.exception_name(),
+            self.cause,
+            if self.in_branch_delay_slot() {
+                " in branch delay slot"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(
+            f,
+            "epc {:08X}  badvaddr {:08X}  sr {:08X}",
+            self.epc, self.badvaddr, self.sr
+        )?;
+
+        for (i, (name, value)) in self.registers().enumerate() {
+            write!(f, "{name} {value:016X}")?;
+            f.write_str(if i % 3 == 2 { "\n" } else { "  " })?;
+        }
+        writeln!(f, "lo {:016X}  hi {:016X}", self.lo, self.hi)
+    }
+}
@@ -0,0 +1,72 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::fs::BlockIndex;
+use crate::listing::Listing;
+use crate::Handle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileFragments {
+    pub slot: usize,
+    pub name: String,
+    pub blocks: usize,
+    // runs of consecutive blocks; 1 for a file that's all in one piece
+    pub fragments: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FragmentationStats {
+    pub files: Vec<FileFragments>,
+    // the breaks in every chain over how many there could be: 0 with every file contiguous,
+    // 1 with no two blocks of any file next to each other
+    pub score: f64,
+}
+
+impl FragmentationStats {
+    pub fn from_listing(listing: &Listing) -> Self {
+        let files = listing
+            .files
+            .iter()
+            .map(|f| FileFragments {
+                slot: f.slot,
+                name: f.name.clone(),
+                blocks: f.blocks.len(),
+                fragments: fragments(&f.blocks),
+            })
+            .collect::<Vec<_>>();
+
+        let (breaks, possible) = files
+            .iter()
+            .filter(|f| f.blocks > 1)
+            .fold((0, 0), |(b, p), f| (b + f.fragments - 1, p + f.blocks - 1));
+
+        Self {
+            score: if possible == 0 {
+                0.0
+            } else {
+                breaks as f64 / possible as f64
+            },
+            files,
+        }
+    }
+
+    pub fn fragmented(&self) -> impl Iterator<Item = &FileFragments> {
+        self.files.iter().filter(|f| f.fragments > 1)
+    }
+}
+
+fn fragments(chain: &[BlockIndex]) -> usize {
+    if chain.is_empty() {
+        return 0;
+    }
+    1 + chain.windows(2).filter(|w| w[1] != w[0] + 1).count()
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn FragmentationStats(&self) -> Result<FragmentationStats> {
+        Ok(FragmentationStats::from_listing(&self.Listing()?))
+    }
+}
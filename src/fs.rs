@@ -1,36 +1,59 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::ffi::CString;
 use std::io::Cursor;
-use std::iter::repeat;
 use std::num::Wrapping;
 
 use binrw::binrw;
 use binrw::BinRead;
 use binrw::BinResult;
 use binrw::BinWrite;
-use indicatif::ProgressBar;
-use indicatif::ProgressStyle;
+use log::debug;
+#[cfg(feature = "writing")]
+use log::warn;
 use rusb::UsbContext;
+use sha2::{Digest, Sha256};
 
+use crate::badblocks::BadBlockMap;
+#[cfg(feature = "writing")]
+use crate::boot::SK_BLOCKS;
+#[cfg(feature = "writing")]
+use crate::capabilities::Support;
 use crate::commands::Command;
 use crate::constants::BLOCK_SIZE;
-use crate::constants::NUM_FATS;
-use crate::constants::SPARE_SIZE;
+#[cfg(feature = "writing")]
+use crate::constants::DRAIN_TIMEOUT;
 use crate::error::*;
+use crate::layout::CardLayout;
+#[cfg(feature = "writing")]
+use crate::listing::SYSTEM_FILES;
+use crate::listing::{FileCategory, ListedFile, Listing};
+use crate::progress::ProgressUnit;
 use crate::rdb::RDBCommand;
 use crate::require_fat;
 use crate::require_init;
+#[cfg(feature = "writing")]
+use crate::saves::{content_file, CONTENT_EXTENSIONS};
+#[cfg(feature = "writing")]
+use crate::ticket::Ticket;
+use crate::undelete::DeletedFile;
 use crate::Handle;
 
 fn next_block_size(size: u32) -> u32 {
     (size + (BLOCK_SIZE - 1) as u32) & !((BLOCK_SIZE - 1) as u32)
 }
 
-#[derive(Debug)]
+fn bytes_to_blocks(bytes: usize) -> usize {
+    (bytes + BLOCK_SIZE - 1) / BLOCK_SIZE
+}
+
+#[derive(Debug, Clone)]
 pub struct Fat {
     entries: Vec<FATEntry>,
     files: Vec<FileEntry>,
     seqno: u32,
     blkno: u32,
+    locations: Vec<u32>,
 }
 
 #[derive(Debug)]
@@ -39,75 +62,345 @@ struct _Fat {
     files: Vec<FileEntry>,
     seqno: Option<u32>,
     blkno: Option<u32>,
+    locations: Vec<u32>,
 }
 
-impl From<_Fat> for Fat {
-    fn from(value: _Fat) -> Self {
-        Self {
+// only complete once at least one FAT block has been added
+impl TryFrom<_Fat> for Fat {
+    type Error = LibBBRDBError;
+
+    fn try_from(value: _Fat) -> Result<Self> {
+        Ok(Self {
             entries: value.entries,
             files: value.files,
-            seqno: value.seqno.unwrap(),
-            blkno: value.blkno.unwrap(),
-        }
+            seqno: value.seqno.ok_or(LibBBRDBError::NoFAT)?,
+            blkno: value.blkno.ok_or(LibBBRDBError::NoFAT)?,
+            locations: value.locations,
+        })
     }
 }
 
 impl Fat {
-    pub fn check(&self) -> Result<()> {
-        for file in &self.files {
-            if file.valid != FileValid::Valid {
+    fn stats(&self, layout: &CardLayout) -> CardStats {
+        let (free, used, bad) = self.entries.iter().fold((0, 0, 0), |(a, b, c), e| match e {
+            FATEntry::Free => (a + 1, b, c),
+            FATEntry::BadBlock => (a, b, c + 1),
+            _ => (a, b + 1, c),
+        });
+        let reserved =
+            |entries: &[FATEntry]| entries.iter().filter(|e| **e == FATEntry::Reserved).count();
+        let sksa_area = (layout.reserved_blocks as usize).min(self.entries.len());
+
+        CardStats {
+            free,
+            used,
+            bad,
+            seqno: self.seqno,
+            reserved: reserved(&self.entries),
+            sksa_area,
+            sksa_reserved: reserved(&self.entries[..sksa_area]),
+            block_size: layout.block_size,
+            fat_slots: layout.num_fats as usize,
+            fat_slots_readable: None,
+        }
+    }
+
+    pub(crate) fn bad_blocks(&self) -> BadBlockMap {
+        BadBlockMap::new(
+            self.entries
+                .iter()
+                .map(|e| *e == FATEntry::BadBlock)
+                .collect(),
+        )
+    }
+
+    fn report(&self, layout: &CardLayout) -> FsReport {
+        let file_slots_used = self.files.iter().filter(|f| f.valid()).count();
+        let stats = self.stats(layout);
+
+        FsReport {
+            stats,
+            fat_block: self.blkno,
+            reserved: stats.reserved,
+            file_slots_used,
+            file_slots_free: FILE_SLOTS.saturating_sub(file_slots_used),
+        }
+    }
+
+    fn list_files(&self) -> Vec<(String, usize)> {
+        self.files
+            .iter()
+            .filter(|f| f.valid())
+            .map(|f| (f.format_name(), f.size()))
+            .collect()
+    }
+
+    pub(crate) fn listing(&self) -> Listing {
+        let files = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.valid())
+            .map(|(slot, f)| {
+                let mut chain = self.chain(f);
+                let blocks = chain.by_ref().collect();
+                let name = f.format_name();
+                ListedFile {
+                    slot,
+                    category: FileCategory::of(&name),
+                    name,
+                    size: f.size(),
+                    start: f.start,
+                    blocks,
+                    end: chain.end(),
+                }
+            })
+            .collect();
+
+        Listing {
+            seqno: self.seqno,
+            fat_block: self.blkno,
+            files,
+            fat: self.entries.clone(),
+        }
+    }
+
+    pub(crate) fn same_generation(&self, other: &Fat) -> bool {
+        self.seqno == other.seqno && self.blkno == other.blkno && self.entries == other.entries
+    }
+
+    pub(crate) fn seqno(&self) -> u32 {
+        self.seqno
+    }
+
+    pub(crate) fn locations(&self) -> &[u32] {
+        &self.locations
+    }
+
+    // every file's contents out of `nand`, plain data covering the whole card; None for a file
+    // whose chain is broken
+    pub(crate) fn file_contents<'a>(
+        &'a self,
+        nand: &'a [u8],
+        block_size: usize,
+    ) -> impl Iterator<Item = (String, Option<Vec<u8>>)> + 'a {
+        self.files.iter().filter(|f| f.valid()).map(move |f| {
+            let data = self.intact_chain(f).and_then(|blocks| {
+                let mut data = Vec::with_capacity(blocks.len() * block_size);
+                for b in blocks {
+                    let offset = b as usize * block_size;
+                    data.extend(nand.get(offset..offset + block_size)?);
+                }
+                data.truncate(f.size());
+                Some(data)
+            });
+
+            (f.format_name(), data)
+        })
+    }
+
+    fn live_file(&self, name: &str) -> Option<&FileEntry> {
+        self.files
+            .iter()
+            .find(|f| f.valid() && f.format_name() == name)
+    }
+
+    // the blocks of `file`'s chain, as long as it ends where the file's size says it should
+    fn intact_chain(&self, file: &FileEntry) -> Option<Vec<BlockIndex>> {
+        let mut chain = self.chain(file);
+        let blocks = chain.by_ref().collect::<Vec<_>>();
+        let expected = bytes_to_blocks(file.size as usize);
+
+        (chain.end() == Some(ChainEnd::End) && blocks.len() == expected).then_some(blocks)
+    }
+
+    // files in this generation that `current` no longer has, or has a different version of
+    pub(crate) fn deleted_files(&self, current: &Fat) -> Vec<DeletedFile> {
+        self.files
+            .iter()
+            .filter(|f| f.valid())
+            .filter_map(|f| {
+                let name = f.format_name();
+                let live = current.live_file(&name);
+                if live.is_some_and(|l| l.start == f.start && l.size == f.size) {
+                    return None;
+                }
+
+                let (blocks, intact) = match self.intact_chain(f) {
+                    Some(b) => (b, true),
+                    None => (self.chain(f).collect(), false),
+                };
+                let reused = blocks
+                    .iter()
+                    .any(|&b| current.entries.get(b as usize) != Some(&FATEntry::Free));
+
+                Some(DeletedFile {
+                    recoverable: intact && !reused && live.is_none(),
+                    name,
+                    size: f.size(),
+                    seqno: self.seqno,
+                    blocks,
+                })
+            })
+            .collect()
+    }
+
+    // puts `name` back the way `older` had it, provided none of its blocks have been used since
+    #[cfg(feature = "writing")]
+    pub(crate) fn restore_from(&mut self, older: &Fat, name: &str) -> Result<()> {
+        let not_recoverable = |why: String| LibBBRDBError::NotRecoverable(name.to_string(), why);
+
+        let file = older
+            .live_file(name)
+            .ok_or_else(|| LibBBRDBError::FileNotFound(name.to_string()))?;
+        if self.live_file(name).is_some() {
+            return Err(LibBBRDBError::FileExists(name.to_string()));
+        }
+
+        let blocks = older.intact_chain(file).ok_or_else(|| {
+            not_recoverable(format!("its chain in generation {} is broken", older.seqno))
+        })?;
+        if let Some(b) = blocks
+            .iter()
+            .find(|&&b| self.entries.get(b as usize) != Some(&FATEntry::Free))
+        {
+            return Err(not_recoverable(format!(
+                "block {b:#X} has been reused since"
+            )));
+        }
+
+        let slot = self
+            .files
+            .iter_mut()
+            .find(|f| !f.valid())
+            .ok_or(LibBBRDBError::NoEmptyFileSlots)?;
+        *slot = file.clone();
+        for b in blocks {
+            self.entries[b as usize] = older.entries[b as usize];
+        }
+
+        Ok(())
+    }
+
+    // the SAs live in the reserved area after the SK, stepping over any bad blocks in it
+    #[cfg(feature = "writing")]
+    pub(crate) fn sksa_blocks(&self, count: usize) -> Result<Vec<u32>> {
+        let mut rv = Vec::with_capacity(count);
+
+        for (i, e) in self.entries.iter().enumerate().skip(SK_BLOCKS as usize) {
+            if rv.len() == count {
+                break;
+            }
+            match e {
+                FATEntry::Reserved => rv.push(i as u32),
+                FATEntry::BadBlock => continue,
+                _ => break,
+            }
+        }
+
+        if rv.len() < count {
+            return Err(LibBBRDBError::InvalidSKSA(format!(
+                "the SAs need {count} blocks but only {} are reserved for them",
+                rv.len()
+            )));
+        }
+
+        Ok(rv)
+    }
+
+    pub fn chain(&self, file: &FileEntry) -> Chain<'_> {
+        self.chain_from(file.start)
+    }
+
+    pub(crate) fn chain_from(&self, start: FATEntry) -> Chain<'_> {
+        Chain {
+            entries: &self.entries,
+            next: start,
+            prev: None,
+            visited: HashSet::new(),
+            end: None,
+        }
+    }
+
+    pub fn check(&self) -> Vec<FsIssue> {
+        let mut issues = vec![];
+        let mut owners: Vec<Option<usize>> = vec![None; self.entries.len()];
+
+        for (index, file) in self.files.iter().enumerate() {
+            if !file.valid() {
                 continue;
             }
 
-            let mut b = &file.start;
-            while b != &FATEntry::EndOfChain {
-                match b {
-                    FATEntry::Chain(n) => b = &self.entries[*n as usize],
-                    FATEntry::Free | FATEntry::BadBlock | FATEntry::Reserved => {
-                        println!("Found invalid file");
+            let name = file.format_name();
+            let mut chain = self.chain(file);
+            let mut length = 0;
+
+            for n in chain.by_ref() {
+                let owner = &mut owners[n as usize];
+                match owner {
+                    Some(o) => issues.push(FsIssue::CrossLink {
+                        file: name.clone(),
+                        other: self.files[*o].format_name(),
+                        block: n,
+                    }),
+                    None => *owner = Some(index),
+                }
+
+                length += 1;
+            }
+
+            match chain.end() {
+                Some(ChainEnd::End) => {
+                    let expected = bytes_to_blocks(file.size as usize);
+                    if length != expected {
+                        issues.push(FsIssue::SizeMismatch {
+                            file: name,
+                            blocks: length,
+                            expected,
+                        });
                     }
-                    FATEntry::EndOfChain => unreachable!(),
                 }
+                Some(ChainEnd::Loop(block)) => issues.push(FsIssue::Loop { file: name, block }),
+                Some(ChainEnd::OutOfRange(block)) => {
+                    issues.push(FsIssue::DanglingChain { file: name, block })
+                }
+                Some(ChainEnd::Invalid(block, entry)) => issues.push(FsIssue::InvalidEntry {
+                    file: name,
+                    block,
+                    entry,
+                }),
+                None => {}
             }
         }
 
-        Ok(())
+        issues
     }
 
-    pub fn blocks(&self) -> Vec<FSBlock> {
+    pub fn blocks(&self) -> Result<Vec<FSBlock>> {
         let blocks = self.entries.chunks(0x1000);
 
         blocks
             .into_iter()
             .enumerate()
-            .map(|(index, b)| FSBlock {
-                fat: b.try_into().unwrap(),
-                entries: if index == 0 {
-                    self.files
-                        .iter()
-                        .cloned()
-                        .chain(repeat(FileEntry::default()))
-                        .take(409)
-                        .collect::<Vec<_>>()
-                        .try_into()
-                        .unwrap()
-                } else {
-                    repeat(FileEntry::default())
-                        .take(409)
-                        .collect::<Vec<_>>()
-                        .try_into()
-                        .unwrap()
-                },
-                footer: FSFooter {
-                    fs_type: if index == 0 {
-                        FSType::Bbfs
+            .map(|(index, b)| {
+                Ok(FSBlock {
+                    fat: b.try_into().map_err(|_| LibBBRDBError::UnhandledCardSize)?,
+                    entries: if index == 0 {
+                        std::array::from_fn(|i| self.files.get(i).cloned().unwrap_or_default())
                     } else {
-                        FSType::Bbfl
+                        std::array::from_fn(|_| FileEntry::default())
+                    },
+                    footer: FSFooter {
+                        fs_type: if index == 0 {
+                            FSType::Bbfs
+                        } else {
+                            FSType::Bbfl
+                        },
+                        seqno: self.seqno.wrapping_add(1),
+                        link_block: 0,
+                        chksum: 0,
                     },
-                    seqno: self.seqno.wrapping_add(1),
-                    link_block: 0,
-                    chksum: 0,
-                },
+                })
             })
             .collect()
     }
@@ -120,31 +413,124 @@ impl _Fat {
             files: vec![],
             seqno: None,
             blkno: None,
+            locations: vec![],
         }
     }
 
-    pub fn add_block(&mut self, block: FSBlock, num: u32) -> u16 {
+    // a block from another generation means the chain is broken, not that we are
+    pub fn add_block(&mut self, block: FSBlock, num: u32) -> Result<u16> {
+        if self.seqno.is_some_and(|n| n != block.footer.seqno)
+            || self.blkno.is_some_and(|n| n != num)
+        {
+            return Err(LibBBRDBError::NoFAT);
+        }
+        self.seqno = Some(block.footer.seqno);
+        self.blkno = Some(num);
+
         let link = block.footer.link_block;
 
         self.entries.extend(block.fat);
         if self.files.is_empty() {
             self.files.extend(block.entries);
         }
-        match self.seqno {
-            Some(n) => assert_eq!(n, block.footer.seqno),
-            None => self.seqno = Some(block.footer.seqno),
+
+        Ok(link)
+    }
+}
+
+pub type BlockIndex = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainEnd {
+    End,
+    Loop(BlockIndex),
+    OutOfRange(BlockIndex),
+    Invalid(Option<BlockIndex>, FATEntry),
+}
+
+#[derive(Debug, Clone)]
+pub struct Chain<'a> {
+    entries: &'a [FATEntry],
+    next: FATEntry,
+    prev: Option<BlockIndex>,
+    visited: HashSet<BlockIndex>,
+    end: Option<ChainEnd>,
+}
+
+impl Chain<'_> {
+    // how the walk finished; None until the iterator has been exhausted
+    pub fn end(&self) -> Option<ChainEnd> {
+        self.end
+    }
+}
+
+impl Iterator for Chain<'_> {
+    type Item = BlockIndex;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.end.is_some() {
+            return None;
+        }
+
+        let n = match self.next {
+            FATEntry::Chain(n) => n.into(),
+            FATEntry::EndOfChain => {
+                self.end = Some(ChainEnd::End);
+                return None;
+            }
+            e => {
+                self.end = Some(ChainEnd::Invalid(self.prev, e));
+                return None;
+            }
+        };
+
+        if n as usize >= self.entries.len() {
+            self.end = Some(ChainEnd::OutOfRange(n));
+            return None;
         }
-        match self.blkno {
-            Some(n) => assert_eq!(n, num),
-            None => self.blkno = Some(num),
+
+        if !self.visited.insert(n) {
+            self.end = Some(ChainEnd::Loop(n));
+            return None;
         }
 
-        link
+        self.prev = Some(n);
+        self.next = self.entries[n as usize];
+
+        Some(n)
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsIssue {
+    DanglingChain {
+        file: String,
+        block: BlockIndex,
+    },
+    Loop {
+        file: String,
+        block: BlockIndex,
+    },
+    CrossLink {
+        file: String,
+        other: String,
+        block: BlockIndex,
+    },
+    InvalidEntry {
+        file: String,
+        block: Option<BlockIndex>,
+        entry: FATEntry,
+    },
+    SizeMismatch {
+        file: String,
+        blocks: usize,
+        expected: usize,
+    },
+}
+
 #[binrw]
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FATEntry {
     #[brw(magic = 0x0000u16)]
     Free,
@@ -157,6 +543,16 @@ pub enum FATEntry {
     Chain(u16),
 }
 
+impl FATEntry {
+    // the FAT only has 16 bits per entry, and the top few values are markers
+    pub fn chain(block: BlockIndex) -> Result<Self> {
+        match u16::try_from(block) {
+            Ok(n) if n != 0 && n < 0xFFFD => Ok(Self::Chain(n)),
+            _ => Err(LibBBRDBError::BlockIndexTooLarge(block)),
+        }
+    }
+}
+
 #[binrw]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum FileValid {
@@ -192,10 +588,10 @@ impl Default for FileEntry {
 
 impl FileEntry {
     pub(crate) fn format_name(&self) -> String {
-        let name = self.name.split(|b| b == &0).next().unwrap();
+        let name = self.name.split(|b| b == &0).next().unwrap_or_default();
         let name = String::from_utf8_lossy(name);
 
-        let ext = self.ext.split(|b| b == &0).next().unwrap();
+        let ext = self.ext.split(|b| b == &0).next().unwrap_or_default();
         let ext = String::from_utf8_lossy(ext);
 
         if ext == "" {
@@ -274,10 +670,28 @@ pub(crate) struct FSBlock {
 
 const FAT_CHECKSUM: u16 = 0xCAD7;
 
+pub const FILE_SLOTS: usize = 409;
+
+// what WipeUserData leaves: the fixed system files, and the content ticket.sys has tickets for
+#[cfg(feature = "writing")]
+fn is_kept_on_wipe(name: &str, ticketed: &HashSet<u32>) -> bool {
+    let name = name.to_ascii_lowercase();
+    SYSTEM_FILES.contains(&name.as_str())
+        || matches!(
+            content_file(&name),
+            Some((id, ext)) if CONTENT_EXTENSIONS.contains(&ext.as_str()) && ticketed.contains(&id)
+        )
+}
+
+// the sum ChksumFile compares against: every byte of the file, added up
+pub fn file_checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0, |a, &e| a.wrapping_add(e as _))
+}
+
 fn check_fat_checksum(data: &[u8]) -> Result<()> {
     let sum: Wrapping<u16> = data
-        .chunks(2)
-        .map(|c| Wrapping(u16::from_be_bytes(c.try_into().unwrap())))
+        .chunks_exact(2)
+        .map(|c| Wrapping(u16::from_be_bytes([c[0], c[1]])))
         .sum();
     if sum.0 != FAT_CHECKSUM {
         Err(LibBBRDBError::InvalidFATChecksum(sum.0))
@@ -286,21 +700,166 @@ fn check_fat_checksum(data: &[u8]) -> Result<()> {
     }
 }
 
-fn fix_fat_checksum(data: &mut [u8]) {
+pub(crate) fn fix_fat_checksum(data: &mut [u8]) {
     let sum: Wrapping<u16> = data[..0x3FFE]
         .as_ref()
-        .chunks(2)
-        .map(|c| Wrapping(u16::from_be_bytes(c.try_into().unwrap())))
+        .chunks_exact(2)
+        .map(|c| Wrapping(u16::from_be_bytes([c[0], c[1]])))
         .sum();
     let checksum = FAT_CHECKSUM.wrapping_sub(sum.0);
     data[0x3FFE..].copy_from_slice(&checksum.to_be_bytes());
 }
 
+fn parse_fat_block(nand: &[u8]) -> Result<FSBlock> {
+    check_fat_checksum(nand)?;
+
+    let mut cursor = Cursor::new(nand);
+    Ok(FSBlock::read_be(&mut cursor)?)
+}
+
+// fetches the FS block stored in a card block, from the card or from an image of it
+type ReadFatBlock<'a> = dyn Fn(u32) -> Result<FSBlock> + 'a;
+
+fn best_fat_with(layout: &CardLayout, cardsize: u32, read: &ReadFatBlock) -> Result<Fat> {
+    if cardsize == 0 {
+        return Err(LibBBRDBError::UnhandledCardSize);
+    }
+
+    let mut best_seqno = 0;
+    let mut best_fat = None;
+
+    for f in 0..layout.num_fats {
+        let fat = read(layout.fat_block(cardsize, f));
+        if let Ok(b) = fat {
+            if b.footer.fs_type == FSType::Bbfs && b.footer.seqno >= best_seqno {
+                best_seqno = b.footer.seqno;
+                best_fat = Some(f);
+            }
+        }
+    }
+
+    match best_fat {
+        Some(f) => fat_chain_with(layout, cardsize, f, read),
+        None => Err(LibBBRDBError::NoFAT),
+    }
+}
+
+// the generation whose BBFS block is in `slot`, with any BBFL blocks it links to
+fn fat_chain_with(
+    layout: &CardLayout,
+    cardsize: u32,
+    slot: u32,
+    read: &ReadFatBlock,
+) -> Result<Fat> {
+    let mut fat = _Fat::new();
+    let mut link = layout.fat_block(cardsize, slot);
+
+    while link != 0 {
+        let b = read(link)?;
+
+        // an older generation's links can point at blocks a newer one has since reused
+        let stale = match fat.seqno {
+            Some(n) => n != b.footer.seqno,
+            None => b.footer.fs_type != FSType::Bbfs,
+        };
+        if stale || fat.locations.contains(&link) {
+            return Err(LibBBRDBError::NoFAT);
+        }
+
+        fat.locations.push(link);
+        link = fat.add_block(b, slot)? as u32;
+    }
+
+    fat.try_into()
+}
+
+// the newest generation in `nand`, plain data covering the whole card
+pub(crate) fn fat_from_image(layout: &CardLayout, nand: &[u8]) -> Result<Fat> {
+    let block_size = layout.block_size;
+    if !nand.len().is_multiple_of(block_size) {
+        return Err(LibBBRDBError::WrongDataLength);
+    }
+
+    let read = |b: u32| {
+        let offset = b as usize * block_size;
+        nand.get(offset..offset + block_size)
+            .ok_or(LibBBRDBError::WrongDataLength)
+            .and_then(parse_fat_block)
+    };
+    best_fat_with(layout, (nand.len() / block_size) as u32, &read)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CardStats {
     pub free: usize,
     pub used: usize,
     pub bad: usize,
     pub seqno: u32,
+    // counted in `used` too
+    pub reserved: usize,
+    // the blocks before the file area, and how many of them the FAT keeps for the SKSA
+    pub sksa_area: usize,
+    pub sksa_reserved: usize,
+    pub block_size: usize,
+    pub fat_slots: usize,
+    // only known once the slots have been read, by DetailedCardStats
+    pub fat_slots_readable: Option<usize>,
+}
+
+impl CardStats {
+    pub fn total(&self) -> usize {
+        self.free + self.used + self.bad
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        (self.free * self.block_size) as u64
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        (self.used * self.block_size) as u64
+    }
+
+    pub fn bad_bytes(&self) -> u64 {
+        (self.bad * self.block_size) as u64
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        (self.total() * self.block_size) as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsReport {
+    pub stats: CardStats,
+    pub fat_block: u32,
+    pub reserved: usize,
+    pub file_slots_used: usize,
+    pub file_slots_free: usize,
+}
+
+// everything a UI usually shows at once, taken from the same FAT generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsSnapshot {
+    pub files: Vec<(String, usize)>,
+    pub stats: CardStats,
+    pub block_map: Vec<FATEntry>,
+    pub seqno: u32,
+}
+
+// one FAT generation as it sits on the card
+#[derive(Debug, Clone)]
+pub struct FatGeneration {
+    pub seqno: u32,
+    // the FAT slot its BBFS block is in
+    pub slot: u32,
+    // the card blocks it's stored in, BBFS block first
+    pub locations: Vec<u32>,
+    // whether it's the generation Init picked
+    pub current: bool,
+    pub stats: CardStats,
+    pub listing: Listing,
 }
 
 impl<C: UsbContext> Handle<C> {
@@ -311,59 +870,66 @@ impl<C: UsbContext> Handle<C> {
 
         fix_fat_checksum(&mut data);
 
-        self.write_blocks(block, &[&data])
-    }
+        self.write_blocks(block, &[&data])?;
 
-    fn read_fat_block(&self, block: u32) -> Result<FSBlock> {
-        let (nand, _) = self.read_blocks_spare(block, 1)?;
+        if self.read_block_with_spare(block)?.data() != data {
+            return Err(LibBBRDBError::FATVerifyFailed(block));
+        }
 
-        check_fat_checksum(&nand)?;
+        Ok(())
+    }
 
-        let mut cursor = Cursor::new(&nand);
-        Ok(FSBlock::read_be(&mut cursor)?)
+    fn read_fat_block(&self, block: u32) -> Result<FSBlock> {
+        parse_fat_block(self.read_block_with_spare(block)?.data())
     }
 
     fn find_best_fat(&self, cardsize: u32) -> Result<Fat> {
-        let mut fat = _Fat::new();
-
-        if cardsize == 0 {
-            return Err(LibBBRDBError::UnhandledCardSize);
-        }
+        best_fat_with(&self.layout, cardsize, &|b| self.read_fat_block(b))
+    }
 
-        let mut best_seqno = 0;
-        let mut best_fat = None;
+    fn read_fat_chain(&self, cardsize: u32, slot: u32) -> Result<Fat> {
+        fat_chain_with(&self.layout, cardsize, slot, &|b| self.read_fat_block(b))
+    }
 
-        for f in 0..NUM_FATS {
-            let fat = self.read_fat_block(cardsize - f - 1);
-            if let Ok(b) = fat {
-                if b.footer.fs_type == FSType::Bbfs && b.footer.seqno >= best_seqno {
-                    best_seqno = b.footer.seqno;
-                    best_fat = Some(f);
-                }
+    // every generation that still reads back whole, newest first
+    pub(crate) fn fat_generations(&self, cardsize: u32) -> Result<Vec<Fat>> {
+        let mut rv = vec![];
+
+        for slot in 0..self.layout.num_fats {
+            match self.read_fat_chain(cardsize, slot) {
+                Ok(f) => rv.push(f),
+                Err(
+                    e @ (LibBBRDBError::NoFAT
+                    | LibBBRDBError::InvalidFATChecksum(_)
+                    | LibBBRDBError::BinRWError(_)
+                    | LibBBRDBError::CardError(_)),
+                ) => debug!("FAT slot {slot}: {e}"),
+                Err(e) => return Err(e),
             }
         }
 
-        if let Some(f) = best_fat {
-            let mut link = cardsize - f - 1;
-
-            while link != 0 {
-                let b = self.read_fat_block(link)?;
-
-                link = fat.add_block(b, f) as u32;
-            }
-
-            Ok(fat.into())
-        } else {
-            Err(LibBBRDBError::NoFAT)
-        }
+        rv.sort_by_key(|f| Reverse(f.seqno));
+        Ok(rv)
     }
 
     pub(crate) fn read_fat(&self, cardsize: u32) -> Result<Fat> {
-        let fat = self.find_best_fat(cardsize)?;
+        self.find_best_fat(cardsize)
+    }
+
+    pub(crate) fn reload_fat(&mut self) -> Result<()> {
+        let cardsize = require_init!(self, player { Ok(player.cardsize) })?;
 
-        fat.check()?;
+        let fat = match self.read_fat(cardsize) {
+            Ok(f) => Some(f),
+            Err(LibBBRDBError::NoFAT) => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(player) = &mut self.device {
+            player.fat = fat;
+        }
 
-        Ok(fat)
+        Ok(())
     }
 
     fn get_file(&mut self, filename: &str) -> Result<Option<&mut FileEntry>> {
@@ -400,17 +966,6 @@ impl<C: UsbContext> Handle<C> {
         }
     }
 
-    fn bytes_to_blocks(bytes: usize) -> usize {
-        (bytes + BLOCK_SIZE - 1) / BLOCK_SIZE
-    }
-
-    fn get_file_block_count(&self, filename: &str) -> Result<usize> {
-        match self.find_file(filename)? {
-            Some(f) => Ok(Self::bytes_to_blocks(f.size as usize)),
-            None => Err(LibBBRDBError::FileNotFound(filename.to_string())),
-        }
-    }
-
     fn get_free_block_count(&self) -> Result<usize> {
         require_fat!(self, _p, fat {
             Ok(fat.entries.iter().fold(0, |a, e| {
@@ -424,46 +979,90 @@ impl<C: UsbContext> Handle<C> {
     }
 
     #[cfg(feature = "writing")]
-    fn update_fs(&mut self) -> Result<()> {
-        require_fat!(self, player, fat {
-            let mut next_index = fat.blkno;
-            let mut next_block = || {
-                next_index = next_index.wrapping_add(1) % 16;
-                player.cardsize - next_index - 1
-            };
-
-            let mut blocks = fat.blocks();
+    pub(crate) fn update_fs(&mut self) -> Result<()> {
+        let (blocks, addrs, head) = require_fat!(self, player, fat {
+            let mut blocks = fat.blocks()?;
 
+            // never overwrite any block of the current generation, so it stays valid until the new one is complete
+            let mut next_index = fat.blkno;
+            let mut indices = vec![];
             let mut addrs = vec![];
-            for _ in 0..blocks.len() {
-                addrs.push(next_block());
+            while addrs.len() < blocks.len() {
+                next_index = next_index.wrapping_add(1) % self.layout.num_fats;
+                if next_index == fat.blkno {
+                    return Err(LibBBRDBError::NoFATSlots);
+                }
+
+                let addr = self.layout.fat_block(player.cardsize, next_index);
+                if !fat.locations.contains(&addr) {
+                    indices.push(next_index);
+                    addrs.push(addr);
+                }
             }
 
             for (index, block) in blocks.iter_mut().enumerate() {
                 block.footer.link_block = addrs.get(index + 1).copied().unwrap_or(0) as _;
             }
 
-            for (block, &addr) in blocks.into_iter().zip(&addrs) {
-                self.write_fat_block(addr, block)?;
-            }
+            Ok((blocks, addrs, indices[0]))
+        })?;
+
+        // the BBFS block goes last; until it lands, the previous generation is still the best one on the card
+        for (block, &addr) in blocks.into_iter().zip(&addrs).rev() {
+            self.write_fat_block(addr, block)?;
+        }
+
+        require_fat!(mut self, _p, fat {
+            fat.seqno = fat.seqno.wrapping_add(1);
+            fat.blkno = head;
+            fat.locations = addrs;
 
             Ok(())
         })
     }
 
-    fn free_blocks(&mut self, mut next_block: FATEntry) -> Result<()> {
+    #[cfg(feature = "writing")]
+    pub(crate) fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let snapshot = require_fat!(self, _p, fat { Ok(fat.clone()) })?;
+
+        let rv = f(self);
+
+        if rv.is_err() {
+            if let Some(player) = &mut self.device {
+                player.fat = Some(snapshot);
+            }
+        }
+
+        rv
+    }
+
+    #[cfg(feature = "writing")]
+    pub(crate) fn mark_bad_blocks(&mut self, map: &BadBlockMap) -> Result<Vec<BlockIndex>> {
+        require_fat!(mut self, _p, fat {
+            let mut marked = vec![];
+            for b in map.indices() {
+                if let Some(e @ FATEntry::Free) = fat.entries.get_mut(b as usize) {
+                    *e = FATEntry::BadBlock;
+                    marked.push(b);
+                }
+            }
+
+            Ok(marked)
+        })
+    }
+
+    fn free_blocks(&mut self, start: FATEntry) -> Result<()> {
         require_fat!(mut self, _p, fat {
-            while let FATEntry::Chain(b) = next_block {
-                let b = b as usize;
-                next_block = fat.entries[b];
-                fat.entries[b] = FATEntry::Free;
+            let blocks = fat.chain_from(start).collect::<Vec<_>>();
+            for b in blocks {
+                fat.entries[b as usize] = FATEntry::Free;
             }
 
             Ok(())
         })
     }
 
-    fn delete_file(&mut self, filename: &str) -> Result<()> {
+    pub(crate) fn delete_file(&mut self, filename: &str) -> Result<()> {
         let file = match self.get_file(filename)? {
             Some(f) => f,
             None => return Ok(()),
@@ -478,36 +1077,33 @@ impl<C: UsbContext> Handle<C> {
     fn read_file_blocks(&self, file: &FileEntry) -> Result<Option<Vec<u8>>> {
         require_fat!(self, _p, fat {
             let mut filebuf = Vec::with_capacity(file.size());
-            let mut next_block = file.start;
-            let bar = ProgressBar::new(file.size() as u64).with_style(
-                ProgressStyle::with_template(
-                    "{wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})",
-                )
-                .unwrap(),
-            );
-
-            while filebuf.len() < file.size() && matches!(next_block, FATEntry::Chain(_)) {
-                let FATEntry::Chain(b) = next_block else {
-                    unreachable!()
-                };
+            let progress =
+                self.progress_task("Reading file", Some(file.size() as u64), ProgressUnit::Bytes);
+
+            let mut chain = fat.chain(file);
+            for b in chain.by_ref() {
+                if filebuf.len() >= file.size() {
+                    break;
+                }
 
-                let (read_block, _) = self.read_blocks_spare(b.into(), 1)?;
+                let read_block = self.read_block_with_spare(b).at_block("read", b)?;
+                let read_block = read_block.data();
                 let to_write =
                     &read_block[..read_block.len().min(file.size() - filebuf.len())];
-                bar.inc(to_write.len() as u64);
+                progress.advance(to_write.len() as u64);
                 filebuf.extend(to_write);
-                next_block = fat.entries[b as usize];
+            }
+
+            // a looping chain would otherwise come back as a silently short file
+            if let Some(ChainEnd::Loop(block)) = chain.end() {
+                return Err(LibBBRDBError::ChainLoop(file.format_name(), block));
             }
 
             Ok(Some(filebuf))
         })
     }
 
-    fn calc_file_checksum(data: &[u8]) -> u32 {
-        data.iter().fold(0, |a, &e| a.wrapping_add(e as _))
-    }
-
-    fn checksum_file(&self, filename: &str, chksum: u32, size: u32) -> Result<bool> {
+    pub(crate) fn encode_filename(filename: &str) -> Result<(u32, Vec<u8>)> {
         FileEntry::default().set_name(filename)?;
 
         let name = CString::new(filename)
@@ -522,8 +1118,18 @@ impl<C: UsbContext> Handle<C> {
         }
         //let padded_len = (len + 3) & !3;
 
-        self.send_command(Command::ChksumFile, len)?;
-        self.write_data(RDBCommand::HostData, name)?;
+        Ok((len, name))
+    }
+
+    pub(crate) fn send_filename(&self, command: Command, filename: &str) -> Result<()> {
+        let (len, name) = Self::encode_filename(filename)?;
+
+        self.send_command(command, len)?;
+        self.write_data(RDBCommand::HostData, name)
+    }
+
+    pub(crate) fn checksum_file(&self, filename: &str, chksum: u32, size: u32) -> Result<bool> {
+        self.send_filename(Command::ChksumFile, filename)?;
 
         let checksum_data = {
             let mut v = vec![];
@@ -534,38 +1140,37 @@ impl<C: UsbContext> Handle<C> {
         self.write_data(RDBCommand::HostData, checksum_data)?;
 
         let status = self.check_cmd_response(Command::ChksumFile, 1)?[0];
-        println!("status: {}", status as i32);
+        debug!("{filename}: checksum status {}", status as i32);
         Ok(status == 0)
     }
 
+    // the old copy's blocks stay in use until the new one is on the card, so they don't count
     fn validate_file_write(&mut self, filename: &str, chksum: u32, size: u32) -> Result<bool> {
-        match self.find_file(filename)? {
-            Some(f) => {
-                if self.checksum_file(filename, chksum, f.size() as u32)? {
-                    Ok(false)
-                } else {
-                    let block_count = self.get_file_block_count(filename)?;
-                    Ok(size < ((self.get_free_block_count()? + block_count) * BLOCK_SIZE) as u32)
-                }
+        if let Some(f) = self.find_file(filename)? {
+            if self.checksum_file(filename, chksum, f.size() as u32)? {
+                return Ok(false);
             }
-            None => Ok(size <= (self.get_free_block_count()? * BLOCK_SIZE) as u32),
         }
+
+        Ok(size <= (self.get_free_block_count()? * self.layout.block_size) as u32)
     }
 
     #[cfg(feature = "writing")]
-    fn write_file_blocks(&mut self, data: &[u8], blocks_to_write: &[u16]) -> Result<()> {
-        const BLANK_SPARE: [u8; SPARE_SIZE] = [0xFF; SPARE_SIZE];
+    fn write_file_blocks(&mut self, data: &[u8], blocks_to_write: &[BlockIndex]) -> Result<()> {
+        let (block_size, blank_spare) =
+            (self.layout.block_size, vec![0xFF; self.layout.spare_size]);
 
         require_init!(self, player
         {
-            assert!(
-                blocks_to_write
-                    .iter()
-                    .all(|&e| (0x40..(player.cardsize - NUM_FATS)).contains(&(e as u32))),
-                "Trying to write to reserved area"
-            );
+            let file_area = self.layout.file_area(player.cardsize);
+            if let Some(&b) = blocks_to_write
+                .iter()
+                .find(|&&e| !file_area.contains(&e))
+            {
+                return Err(LibBBRDBError::ReservedAreaWrite(b));
+            }
 
-            let chunks = data.chunks(BLOCK_SIZE);
+            let chunks = data.chunks(block_size);
 
             if blocks_to_write.len() != chunks.len() {
                 return Err(LibBBRDBError::IncorrectNumBlocks(
@@ -574,21 +1179,16 @@ impl<C: UsbContext> Handle<C> {
                 ));
             }
 
-            let bar = ProgressBar::new(data.len() as u64).with_style(
-                ProgressStyle::with_template(
-                    "{wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})",
-                )
-                .unwrap(),
-            );
-
-            for (block, &index) in chunks.zip(blocks_to_write) {
-                let mut block = block.to_vec();
-                block.extend(vec![0x00; BLOCK_SIZE - block.len()]);
-                self.write_blocks_spare(index.into(), &[(&block, &BLANK_SPARE)])?;
-                bar.inc(block.len() as u64);
-            }
-
-            Ok(())
+            self.with_progress("Writing file", Some(data.len() as u64), ProgressUnit::Bytes, |this| {
+                for (block, &index) in chunks.zip(blocks_to_write) {
+                    let mut block = block.to_vec();
+                    block.extend(vec![0x00; block_size - block.len()]);
+                    this.write_blocks_spare(index, &[(&block, &blank_spare)])
+                        .at_block("write", index)?;
+                    this.progress().advance(block.len() as u64);
+                }
+                Ok(())
+            })
         })
     }
 
@@ -607,23 +1207,35 @@ impl<C: UsbContext> Handle<C> {
     fn write_file_entry(
         &mut self,
         filename: &str,
-        start_block: usize,
+        start_block: BlockIndex,
         filesize: u32,
     ) -> Result<&FileEntry> {
+        let start = FATEntry::chain(start_block)?;
         let entry = self.find_blank_file_entry()?;
         entry.set_name(filename)?;
         entry.valid = FileValid::Valid;
-        entry.start = FATEntry::Chain(start_block as u16);
+        entry.start = start;
         entry.set_size(filesize);
 
         Ok(entry)
     }
 
-    fn find_next_free_block(&self, start_at: usize) -> Result<usize> {
+    // free blocks in the file area, which nothing on the card will miss if they're scribbled on
+    pub(crate) fn unused_blocks(&self) -> Result<Vec<BlockIndex>> {
+        require_fat!(self, p, fat {
+            Ok(self
+                .layout
+                .file_area(p.cardsize)
+                .filter(|&b| matches!(fat.entries.get(b as usize), Some(FATEntry::Free)))
+                .collect())
+        })
+    }
+
+    fn find_next_free_block(&self, start_at: BlockIndex) -> Result<BlockIndex> {
         require_fat!(self, _p, fat {
-            for (index, i) in fat.entries[start_at..].iter().enumerate() {
+            for (index, i) in fat.entries.iter().enumerate().skip(start_at as usize) {
                 if matches!(i, FATEntry::Free) {
-                    return Ok(index + start_at);
+                    return Ok(index as BlockIndex);
                 }
             }
             Err(LibBBRDBError::NoFreeBlocks)
@@ -631,25 +1243,25 @@ impl<C: UsbContext> Handle<C> {
     }
 
     #[cfg(feature = "writing")]
-    fn update_fs_links(&mut self, start_block: usize, size: u32) -> Result<Vec<u16>> {
+    fn update_fs_links(&mut self, start_block: BlockIndex, size: u32) -> Result<Vec<BlockIndex>> {
         require_fat!(self, _p, _f { Ok(()) })?;
 
         let mut free_blocks = Vec::with_capacity(next_block_size(size) as usize);
-        let mut prev = start_block as u16;
+        let mut prev = start_block;
         free_blocks.push(prev);
 
-        let mut allocated_size = BLOCK_SIZE as u32;
+        let mut allocated_size = self.layout.block_size as u32;
         while allocated_size < size {
-            let next = self.find_next_free_block(prev as usize + 1)? as u16;
+            let next = self.find_next_free_block(prev + 1)?;
             free_blocks.push(next);
             prev = next;
-            allocated_size += BLOCK_SIZE as u32;
+            allocated_size += self.layout.block_size as u32;
         }
 
         require_fat!(mut self, _p, fat {
             let mut current_block = free_blocks[0];
             for &next_block in &free_blocks[1..] {
-                fat.entries[current_block as usize] = FATEntry::Chain(next_block);
+                fat.entries[current_block as usize] = FATEntry::chain(next_block)?;
                 current_block = next_block;
             }
             fat.entries[current_block as usize] = FATEntry::EndOfChain;
@@ -660,111 +1272,322 @@ impl<C: UsbContext> Handle<C> {
 
     #[cfg(feature = "writing")]
     fn write_blocks_to_temp_file(&mut self, data: &[u8]) -> Result<()> {
-        self.delete_file("temp.tmp")?;
+        let temp = self.temp_file_name.clone();
+        self.delete_file(&temp)?;
 
-        let start_block = self.find_next_free_block(0x40)?;
+        let start_block = self.find_next_free_block(self.layout.reserved_blocks)?;
         let size = data.len() as u32;
 
-        let entry = self.write_file_entry("temp.tmp", start_block, size)?;
+        let entry = self.write_file_entry(&temp, start_block, size)?;
         let written_size = entry.size() as u32;
 
         let blocks_to_write = self.update_fs_links(start_block, written_size)?;
         self.write_file_blocks(data, &blocks_to_write)
     }
 
-    #[cfg(not(feature = "raw_access"))]
-    fn check_and_cleanup_temp_file(
-        &mut self,
-        filename: &str,
-        chksum: u32,
-        size: u32,
-    ) -> Result<()> {
-        self.checksum_file("temp.tmp", chksum, size)?;
-        if true {
-            self.rename_file("temp.tmp", filename)
-        } else {
-            Err(LibBBRDBError::ChecksumFailed(filename.to_string(), chksum))
-        }
+    // writes `data` into free blocks and names it `filename` in the in-memory FAT, replacing any
+    // file already there; nothing on the card changes until update_fs
+    #[cfg(feature = "writing")]
+    pub(crate) fn stage_file(&mut self, data: &[u8], filename: &str) -> Result<()> {
+        let temp = self.temp_file_name.clone();
+        self.write_blocks_to_temp_file(data)?;
+        self.rename_file(&temp, filename)
     }
 
     #[cfg(feature = "writing")]
     #[allow(non_snake_case)]
     pub fn DeleteFile(&mut self, filename: &str) -> Result<()> {
-        self.delete_file(filename)?;
-        self.update_fs()
+        self.audited_mut(
+            "DeleteFile",
+            vec![("filename", filename.to_string())],
+            |this| {
+                this.check_writable()?;
+
+                if this.use_native(Command::DeleteFile) {
+                    return this.native_delete_file(filename);
+                }
+
+                this.transaction(|this| {
+                    this.delete_file(filename)?;
+                    this.update_fs()
+                })
+            },
+        )
+        .for_file("DeleteFile", filename)
+    }
+
+    // deletes every save and user file, and any content without a ticket, in one FAT update,
+    // keeping the system files and the content ticket.sys names; the SKSA and bad blocks aren't
+    // files, so they're left alone too. Returns what was deleted
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn WipeUserData(&mut self) -> Result<Vec<String>> {
+        self.audited_mut("WipeUserData", vec![], |this| {
+            this.check_writable()?;
+
+            let ticketed = this
+                .ReadTickets()?
+                .unwrap_or_default()
+                .tickets
+                .iter()
+                .map(Ticket::content_id)
+                .collect::<HashSet<_>>();
+
+            let doomed = this
+                .ListFiles()?
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| !is_kept_on_wipe(name, &ticketed))
+                .collect::<Vec<_>>();
+
+            this.transaction(|this| {
+                for name in &doomed {
+                    this.delete_file(name)?;
+                }
+                this.update_fs()
+            })?;
+
+            Ok(doomed)
+        })
     }
 
     #[cfg(feature = "writing")]
     #[allow(non_snake_case)]
     pub fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
-        self.rename_file(from, to)?;
-        self.update_fs()
+        let params = vec![("from", from.to_string()), ("to", to.to_string())];
+        self.audited_mut("RenameFile", params, |this| {
+            this.check_writable()?;
+
+            if this.use_native(Command::RenameFile) {
+                match this.native_rename_file(from, to) {
+                    Err(e) if is_timeout(&e) => {
+                        warn!("native rename went unanswered, falling back to editing the FAT");
+                        this.drain_input(DRAIN_TIMEOUT);
+                        this.capabilities
+                            .borrow_mut()
+                            .record(Command::RenameFile, Support::Unsupported);
+                        // it may have got as far as renaming before going quiet
+                        this.reload_fat()?;
+                        if this.find_file(from)?.is_none() && this.find_file(to)?.is_some() {
+                            return Ok(());
+                        }
+                    }
+                    Err(e @ LibBBRDBError::IncorrectCmdResponse(..))
+                    | Err(e @ LibBBRDBError::CardError(CardError::Invalid)) => {
+                        warn!("native rename failed ({e}), falling back to editing the FAT");
+                    }
+                    r => return r,
+                }
+            }
+
+            this.transaction(|this| {
+                this.rename_file(from, to)?;
+                this.update_fs()
+            })
+        })
+        .for_file("RenameFile", from)
     }
 
     #[allow(non_snake_case)]
     pub fn CardStats(&self) -> Result<CardStats> {
-        require_fat!(self, _p, fat {
-            let (free, used, bad) = fat.entries.iter().fold((0, 0, 0), |(a, b, c), e| match e {
-                FATEntry::Free => (a + 1, b, c),
-                FATEntry::BadBlock => (a, b, c + 1),
-                _ => (a, b + 1, c),
-            });
+        require_fat!(self, _p, fat { Ok(fat.stats(&self.layout)) })
+    }
+
+    // CardStats, plus a read of every FAT slot to see how many still hold a valid FS block
+    #[allow(non_snake_case)]
+    pub fn DetailedCardStats(&self) -> Result<CardStats> {
+        self.guarded(|this| {
+            let mut stats = this.CardStats()?;
+            let cardsize = require_init!(this, player { Ok(player.cardsize) })?;
+
+            let readable = (0..this.layout.num_fats)
+                .filter(|&slot| {
+                    let block = this.layout.fat_block(cardsize, slot);
+                    match this.read_fat_block(block) {
+                        Ok(_) => true,
+                        Err(e) => {
+                            debug!("FAT slot {slot}: {e}");
+                            false
+                        }
+                    }
+                })
+                .count();
+            stats.fat_slots_readable = Some(readable);
 
-            Ok(CardStats { free, used, bad, seqno: fat.seqno })
+            Ok(stats)
         })
     }
 
+    #[allow(non_snake_case)]
+    pub fn FsReport(&self) -> Result<FsReport> {
+        require_fat!(self, _p, fat { Ok(fat.report(&self.layout)) })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn FsSnapshot(&self) -> Result<FsSnapshot> {
+        require_fat!(self, _p, fat {
+            Ok(FsSnapshot {
+                files: fat.list_files(),
+                stats: fat.stats(&self.layout),
+                block_map: fat.entries.clone(),
+                seqno: fat.seqno,
+            })
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn CheckFS(&self) -> Result<Vec<FsIssue>> {
+        require_fat!(self, _p, fat { Ok(fat.check()) })
+    }
+
     #[allow(non_snake_case)]
     pub fn ReadFile(&self, filename: &str) -> Result<Option<Vec<u8>>> {
-        let file = match self.find_file(filename)? {
-            Some(f) => f,
-            None => return Ok(None),
-        };
-        self.read_file_blocks(file)
+        self.audited(
+            "ReadFile",
+            vec![("filename", filename.to_string())],
+            |this| {
+                let file = match this.find_file(filename)? {
+                    Some(f) => f,
+                    None => return Ok(None),
+                };
+
+                let data = if this.use_native(Command::ReadFile) {
+                    Some(this.native_read_file(filename)?)
+                } else {
+                    this.read_file_blocks(file)?
+                };
+
+                if let (Some(d), true) = (&data, this.verify_reads) {
+                    let sum = file_checksum(d);
+                    if !this.checksum_file(filename, sum, d.len() as u32)? {
+                        return Err(LibBBRDBError::ChecksumFailed(filename.to_string(), sum));
+                    }
+                }
+
+                if let (Some(d), true) = (&data, this.audit_enabled()) {
+                    this.audit_hash("data", Sha256::digest(d).into());
+                }
+
+                Ok(data)
+            },
+        )
+        .for_file("ReadFile", filename)
+    }
+
+    // has the console sum the file itself, so a card file can be checked against a local copy
+    // (see file_checksum) without reading it back over USB
+    #[allow(non_snake_case)]
+    pub fn VerifyFileChecksum(&self, filename: &str, expected_sum: u32, size: u32) -> Result<bool> {
+        let params = vec![
+            ("filename", filename.to_string()),
+            ("checksum", format!("{expected_sum:08x}")),
+            ("size", size.to_string()),
+        ];
+        self.audited("VerifyFileChecksum", params, |this| {
+            if this.find_file(filename)?.is_none() {
+                return Err(LibBBRDBError::FileNotFound(filename.to_string()));
+            }
+            this.checksum_file(filename, expected_sum, size)
+        })
+        .for_file("VerifyFileChecksum", filename)
     }
 
     #[allow(non_snake_case)]
     pub fn ListFiles(&self) -> Result<Vec<(String, usize)>> {
+        require_fat!(self, _p, fat { Ok(fat.list_files()) })
+    }
+
+    // the blocks `filename` occupies, in order, from the FAT as last read; the walk stops at a
+    // loop or a bad entry, and Chain::end says how it finished
+    #[allow(non_snake_case)]
+    pub fn FileChain(&self, filename: &str) -> Result<Chain<'_>> {
         require_fat!(self, _p, fat {
-            Ok(fat.files.iter().filter_map(|f| if f.valid() { Some((f.format_name(), f.size())) } else { None }).collect())
+            match fat.live_file(filename) {
+                Some(f) => Ok(fat.chain(f)),
+                None => Err(LibBBRDBError::FileNotFound(filename.to_string())),
+            }
+        })
+    }
+
+    // every generation that still reads back whole, newest first
+    #[allow(non_snake_case)]
+    pub fn ReadAllFats(&self) -> Result<Vec<FatGeneration>> {
+        self.guarded(|this| {
+            let cardsize = require_init!(this, player { Ok(player.cardsize) })?;
+            let current = this
+                .device
+                .as_ref()
+                .and_then(|p| p.fat.as_ref())
+                .map(|f| f.blkno);
+
+            Ok(this
+                .fat_generations(cardsize)?
+                .into_iter()
+                .map(|f| FatGeneration {
+                    seqno: f.seqno,
+                    slot: f.blkno,
+                    current: current == Some(f.blkno),
+                    stats: f.stats(&this.layout),
+                    listing: f.listing(),
+                    locations: f.locations,
+                })
+                .collect())
         })
     }
 
     #[allow(non_snake_case)]
     pub fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
-        require_fat!(self, _p, fat {
-            let mut data = vec![];
+        self.guarded(|this| {
+            require_fat!(this, _p, fat {
+                let mut data = vec![];
 
-            for block in fat.blocks() {
-                let mut blk = vec![];
-                let mut cursor = Cursor::new(&mut blk);
-                block.write_be(&mut cursor)?;
+                for block in fat.blocks()? {
+                    let mut blk = vec![];
+                    let mut cursor = Cursor::new(&mut blk);
+                    block.write_be(&mut cursor)?;
 
-                fix_fat_checksum(&mut blk);
+                    fix_fat_checksum(&mut blk);
 
-                data.extend(blk);
-            }
+                    data.extend(blk);
+                }
 
-            Ok(data)
+                Ok(data)
+            })
         })
     }
 
     #[cfg(feature = "writing")]
     #[allow(non_snake_case)]
     pub fn WriteFile(&mut self, data: &[u8], filename: &str) -> Result<()> {
-        let chksum = Self::calc_file_checksum(data);
-        let size = data.len() as u32;
-
-        if !self.validate_file_write(filename, chksum, size)? {
-            return Ok(());
+        if self.audit_enabled() {
+            self.audit_hash("data", Sha256::digest(data).into());
         }
 
-        self.delete_file(filename)?;
+        let params = vec![
+            ("filename", filename.to_string()),
+            ("size", data.len().to_string()),
+        ];
+        self.audited_mut("WriteFile", params, |this| {
+            this.check_writable()?;
 
-        self.write_blocks_to_temp_file(data)?;
-        self.update_fs()?;
+            if this.use_native(Command::WriteFile) {
+                return this.native_write_file(data, filename);
+            }
 
-        self.check_and_cleanup_temp_file(filename, chksum, size)?;
-        self.update_fs()
+            let chksum = file_checksum(data);
+            let size = data.len() as u32;
+
+            if !this.validate_file_write(filename, chksum, size)? {
+                return Ok(());
+            }
+
+            // one generation, so a write cut short leaves the card with the old file or the new
+            // one; the old file's blocks aren't reused, and no temporary file is left behind
+            this.transaction(|this| {
+                this.stage_file(data, filename)?;
+                this.update_fs()
+            })
+        })
+        .for_file("WriteFile", filename)
     }
 }
@@ -0,0 +1,50 @@
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use log::{error, warn};
+use rusb::UsbContext;
+
+use crate::constants::DRAIN_TIMEOUT;
+use crate::error::*;
+use crate::Handle;
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // whatever the console was halfway through sending is meaningless now, so throw it away
+    // along with anything the demultiplexer was holding on to; unwinding has already let go of
+    // any borrows the panicking operation held
+    fn resync_after_panic(&self) {
+        let discarded = self.drain_input(DRAIN_TIMEOUT);
+        if discarded > 0 {
+            warn!("discarded {discarded:#X} bytes after a panic");
+        }
+    }
+
+    fn caught<T>(&self, rv: std::thread::Result<Result<T>>) -> Result<T> {
+        rv.unwrap_or_else(|payload| {
+            let msg = panic_message(payload.as_ref());
+            error!("internal error: {msg}");
+            self.resync_after_panic();
+            Err(LibBBRDBError::InternalError(msg))
+        })
+    }
+
+    pub(crate) fn guarded<T>(&self, f: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        let rv = catch_unwind(AssertUnwindSafe(|| f(self)));
+        self.caught(rv)
+    }
+
+    pub(crate) fn guarded_mut<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let rv = catch_unwind(AssertUnwindSafe(|| f(&mut *self)));
+        self.caught(rv)
+    }
+}
@@ -0,0 +1,36 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::fs::CardStats;
+use crate::usb::RDBType;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConsoleInfo {
+    pub bbid: u32,
+    pub device_type: RDBType,
+    pub card_blocks: u32,
+    // None when the card has no readable FAT
+    pub stats: Option<CardStats>,
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn ConsoleInfo(&self) -> Result<ConsoleInfo> {
+        let card_blocks = self.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+
+        let stats = match self.CardStats() {
+            Ok(s) => Some(s),
+            Err(LibBBRDBError::NoFAT) => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(ConsoleInfo {
+            bbid: self.bbid().map_or_else(|| self.GetBBID(), Ok)?,
+            device_type: self.device_type(),
+            card_blocks,
+            stats,
+        })
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+use rusb::UsbContext;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::audit::to_hex;
+use crate::boot::SK_BLOCKS;
+use crate::constants::BLOCK_SIZE;
+use crate::error::*;
+use crate::nand::{BlockWithSpare, NandImage};
+use crate::progress::ProgressUnit;
+#[cfg(feature = "writing")]
+use crate::require_fat;
+use crate::sksa_info::CmdHead;
+#[cfg(feature = "writing")]
+use crate::spare::SpareData;
+use crate::Handle;
+
+// each SA starts with a block holding its CMD; the metadata head sits at 0x2800 in it
+pub(crate) const CMD_HEAD_OFFSET: usize = 0x2800;
+
+const SA_LINK_END: u8 = 0xFF;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SksaRegion {
+    pub(crate) data: Vec<u8>,
+    pub(crate) blocks: Vec<u32>,
+}
+
+impl SksaRegion {
+    fn descriptor(&self, file: &str) -> Value {
+        json!({
+            "file": file,
+            "size": self.data.len(),
+            "blocks": self.blocks,
+            "sha256": to_hex(&Sha256::digest(&self.data)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SksaParts {
+    pub(crate) sk: SksaRegion,
+    pub(crate) sa1: SksaRegion,
+    pub(crate) sa2: Option<SksaRegion>,
+}
+
+fn cmd_content_size(cmd: &[u8]) -> Result<usize> {
+    Ok(CmdHead::parse(cmd)?.size as usize)
+}
+
+fn sa_blocks(cmd: &[u8]) -> Result<usize> {
+    Ok(1 + cmd_content_size(cmd)?.div_ceil(BLOCK_SIZE))
+}
+
+type SksaSplit<'a> = (&'a [u8], &'a [u8], Option<&'a [u8]>);
+
+// splits a flat SKSA into SK, SA1 and (if present) SA2, using the CmdHead sizes to find the seams
+pub(crate) fn split_sksa(sksa: &[u8]) -> Result<SksaSplit<'_>> {
+    let sk_size = SK_BLOCKS as usize * BLOCK_SIZE;
+
+    if !sksa.len().is_multiple_of(BLOCK_SIZE) {
+        return Err(LibBBRDBError::InvalidSKSA(format!(
+            "{:#X} bytes isn't a whole number of blocks",
+            sksa.len()
+        )));
+    }
+    if sksa.len() <= sk_size {
+        return Err(LibBBRDBError::InvalidSKSA(
+            "too small to hold an SK and an SA".to_string(),
+        ));
+    }
+
+    let (sk, rest) = sksa.split_at(sk_size);
+
+    let sa1_size = sa_blocks(rest)? * BLOCK_SIZE;
+    if sa1_size > rest.len() {
+        return Err(LibBBRDBError::InvalidSKSA(format!(
+            "SA1's CmdHead says {sa1_size:#X} bytes, but only {:#X} follow the SK",
+            rest.len()
+        )));
+    }
+    let (sa1, rest) = rest.split_at(sa1_size);
+
+    if rest.is_empty() {
+        return Ok((sk, sa1, None));
+    }
+
+    let sa2_size = sa_blocks(rest)? * BLOCK_SIZE;
+    if sa2_size != rest.len() {
+        return Err(LibBBRDBError::InvalidSKSA(format!(
+            "SA2's CmdHead says {sa2_size:#X} bytes, but {:#X} follow SA1",
+            rest.len()
+        )));
+    }
+
+    Ok((sk, sa1, Some(rest)))
+}
+
+// the SKSA as it sits on the card, each part padded out to whole blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sksa {
+    pub sk: Vec<u8>,
+    pub sa1: Vec<u8>,
+    pub sa2: Option<Vec<u8>>,
+    // blocks after the SK that the SA chains step over, as a writer does with bad ones
+    pub bad_blocks_skipped: Vec<u32>,
+}
+
+impl Sksa {
+    // SK, SA1 and SA2 back to back, as a .sksa file holds them
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rv = self.sk.clone();
+        rv.extend(&self.sa1);
+        if let Some(sa2) = &self.sa2 {
+            rv.extend(sa2);
+        }
+        rv
+    }
+}
+
+impl From<SksaParts> for Sksa {
+    fn from(parts: SksaParts) -> Self {
+        let chain = parts
+            .sa1
+            .blocks
+            .iter()
+            .chain(parts.sa2.iter().flat_map(|s| &s.blocks))
+            .copied()
+            .collect::<HashSet<_>>();
+        let last = chain.iter().copied().max().unwrap_or(SK_BLOCKS);
+
+        Self {
+            sk: parts.sk.data,
+            sa1: parts.sa1.data,
+            sa2: parts.sa2.map(|s| s.data),
+            bad_blocks_skipped: (SK_BLOCKS..last).filter(|b| !chain.contains(b)).collect(),
+        }
+    }
+}
+
+// an SA without the padding out to a whole block: its CmdHead block, then the content size it gives
+fn trim_sa(sa: &[u8]) -> Result<Vec<u8>> {
+    let len = BLOCK_SIZE + cmd_content_size(sa)?;
+    sa.get(..len).map(<[u8]>::to_vec).ok_or_else(|| {
+        LibBBRDBError::InvalidSKSA(format!(
+            "the CmdHead says {len:#X} bytes, but the SA is only {:#X}",
+            sa.len()
+        ))
+    })
+}
+
+// the SK and each SA on their own, in the shapes other iQue tools save them in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitSksa {
+    pub sk: Vec<u8>,
+    pub sa1: Vec<u8>,
+    pub sa2: Option<Vec<u8>>,
+}
+
+impl SplitSksa {
+    fn trimmed(sk: &[u8], sa1: &[u8], sa2: Option<&[u8]>) -> Result<Self> {
+        Ok(Self {
+            sk: sk.to_vec(),
+            sa1: trim_sa(sa1)?,
+            sa2: sa2.map(trim_sa).transpose()?,
+        })
+    }
+
+    // from a flat SKSA, as Sksa::to_bytes gives or a .sksa file holds
+    pub fn from_sksa(sksa: &[u8]) -> Result<Self> {
+        let (sk, sa1, sa2) = split_sksa(sksa)?;
+        Self::trimmed(sk, sa1, sa2)
+    }
+
+    pub fn write_to_dir<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        create_dir_all(dir)?;
+
+        write(dir.join("sk.bin"), &self.sk)?;
+        write(dir.join("sa1.bin"), &self.sa1)?;
+        if let Some(sa2) = &self.sa2 {
+            write(dir.join("sa2.bin"), sa2)?;
+        }
+
+        Ok(())
+    }
+}
+
+// follows the spare links from `start`; returns the SA and the link out of its last block
+pub(crate) fn read_sa_with(
+    start: u32,
+    read: &mut dyn FnMut(u32) -> Result<BlockWithSpare>,
+) -> Result<(SksaRegion, u8)> {
+    let mut region = SksaRegion::default();
+    let mut visited = HashSet::new();
+    let mut next = start;
+    let mut remaining = 1;
+
+    loop {
+        if !visited.insert(next) {
+            return Err(LibBBRDBError::InvalidSKSA(format!(
+                "SA chain loops at block {next}"
+            )));
+        }
+
+        let block = read(next).at_block("read SA", next)?;
+
+        if region.blocks.is_empty() {
+            remaining += cmd_content_size(block.data())?.div_ceil(BLOCK_SIZE);
+        }
+
+        let link = block.spare_data()?.sa_link();
+        region.blocks.push(next);
+        region.data.extend(block.data());
+        remaining -= 1;
+
+        if remaining > 0 {
+            if link == SA_LINK_END {
+                return Err(LibBBRDBError::InvalidSKSA(format!(
+                    "SA chain ends early at block {next}"
+                )));
+            }
+            next = link as u32;
+        } else {
+            return Ok((region, link));
+        }
+    }
+}
+
+// the same walk as on the card, through a dump that has its spare data
+pub(crate) fn sksa_from_image(image: &NandImage) -> Result<SksaParts> {
+    let mut read = |b: u32| match image.block(b as usize) {
+        Some((data, spare)) => BlockWithSpare::new(data.to_vec(), spare.to_vec()),
+        None => Err(LibBBRDBError::InvalidSKSA(format!(
+            "block {b} is past the end of the image"
+        ))),
+    };
+
+    let mut sk = SksaRegion::default();
+    for blk in 0..SK_BLOCKS {
+        sk.data.extend(read(blk)?.data());
+        sk.blocks.push(blk);
+    }
+
+    let (sa1, link) = read_sa_with(SK_BLOCKS, &mut read)?;
+    let sa2 = if link == SA_LINK_END {
+        None
+    } else {
+        Some(read_sa_with(link as u32, &mut read)?.0)
+    };
+
+    Ok(SksaParts { sk, sa1, sa2 })
+}
+
+pub(crate) fn write_sksa_files(parts: &SksaParts, dir: &Path) -> Result<()> {
+    create_dir_all(dir)?;
+
+    write(dir.join("sk.bin"), &parts.sk.data)?;
+    write(dir.join("sa1.bin"), &parts.sa1.data)?;
+    if let Some(sa2) = &parts.sa2 {
+        write(dir.join("sa2.bin"), &sa2.data)?;
+    }
+
+    let descriptor = json!({
+        "block_size": BLOCK_SIZE,
+        "sk": parts.sk.descriptor("sk.bin"),
+        "sa1": parts.sa1.descriptor("sa1.bin"),
+        "sa2": parts.sa2.as_ref().map(|s| s.descriptor("sa2.bin")),
+    });
+    let descriptor = serde_json::to_string_pretty(&descriptor).map_err(std::io::Error::from)?;
+    write(dir.join("sksa.json"), descriptor + "\n")?;
+
+    Ok(())
+}
+
+impl<C: UsbContext> Handle<C> {
+    fn read_sa(&self, start: u32) -> Result<(SksaRegion, u8)> {
+        read_sa_with(start, &mut |b| {
+            let rv = self.read_block_with_spare(b);
+            self.progress().advance(1);
+            rv
+        })
+    }
+
+    pub(crate) fn read_sksa_parts(&self) -> Result<SksaParts> {
+        self.check_initialised()?;
+
+        let progress = self.progress_task("Reading SKSA", None, ProgressUnit::Blocks);
+
+        let mut sk = SksaRegion::default();
+        for blk in 0..SK_BLOCKS {
+            sk.data
+                .extend(self.read_blocks(blk, 1).at_block("read SK", blk)?);
+            sk.blocks.push(blk);
+            progress.advance(1);
+        }
+
+        let (sa1, link) = self.read_sa(SK_BLOCKS)?;
+        let sa2 = if link == SA_LINK_END {
+            None
+        } else {
+            Some(self.read_sa(link as u32)?.0)
+        };
+
+        Ok(SksaParts { sk, sa1, sa2 })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReadSKSA(&self) -> Result<Sksa> {
+        self.guarded(|this| Ok(this.read_sksa_parts()?.into()))
+    }
+
+    // writes sk.bin, sa1.bin, sa2.bin (if there is one) and sksa.json describing them
+    #[allow(non_snake_case)]
+    pub fn DumpSKSAFiles<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        self.guarded(|this| write_sksa_files(&this.read_sksa_parts()?, dir.as_ref()))
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReadSplitSKSA(&self) -> Result<SplitSksa> {
+        self.guarded(|this| {
+            let parts = this.read_sksa_parts()?;
+            SplitSksa::trimmed(
+                &parts.sk.data,
+                &parts.sa1.data,
+                parts.sa2.as_ref().map(|s| s.data.as_slice()),
+            )
+        })
+    }
+
+    // the SK goes in blocks 0-3 and the SAs are chained through the reserved blocks after it;
+    // nothing outside the reserved area is touched
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn WriteSKSA(&mut self, sksa: &[u8]) -> Result<()> {
+        if self.audit_enabled() {
+            self.audit_hash("sksa", Sha256::digest(sksa).into());
+        }
+
+        let params = vec![("size", sksa.len().to_string())];
+        self.audited_mut("WriteSKSA", params, |this| {
+            this.check_writable()?;
+
+            let (sk, sa1, sa2) = split_sksa(sksa)?;
+            let sa_data = [Some(sa1), sa2];
+            let sa_data = sa_data.iter().flatten().collect::<Vec<_>>();
+
+            let num_sa_blocks = sa_data.iter().map(|sa| sa.len() / BLOCK_SIZE).sum();
+            let targets = require_fat!(this, player, fat { fat.sksa_blocks(num_sa_blocks) })?;
+            if let Some(&blk) = targets.iter().find(|&&b| b >= SA_LINK_END as u32) {
+                return Err(LibBBRDBError::InvalidSKSA(format!(
+                    "block {blk} is too far in to link an SA through"
+                )));
+            }
+
+            this.with_progress(
+                "Writing SKSA",
+                Some((SK_BLOCKS as usize + num_sa_blocks) as u64),
+                ProgressUnit::Blocks,
+                |this| {
+                    let blank = SpareData::blank().to_bytes();
+                    for (i, data) in sk.chunks(BLOCK_SIZE).enumerate() {
+                        this.write_blocks_spare(i as u32, &[(data, &blank)])
+                            .at_block("write SK", i as u32)?;
+                        this.progress().advance(1);
+                    }
+
+                    let blocks = sa_data.iter().flat_map(|sa| sa.chunks(BLOCK_SIZE));
+                    for (i, data) in blocks.enumerate() {
+                        let mut spare = SpareData::blank();
+                        let link = targets.get(i + 1).map_or(SA_LINK_END, |&b| b as u8);
+                        spare.set_sa_link(link);
+
+                        this.write_blocks_spare(targets[i], &[(data, &spare.to_bytes())])
+                            .at_block("write SA", targets[i])?;
+                        this.progress().advance(1);
+                    }
+                    Ok(())
+                },
+            )
+        })
+    }
+}
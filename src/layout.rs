@@ -0,0 +1,64 @@
+use std::ops::Range;
+
+use rusb::UsbContext;
+
+use crate::constants::{BLOCK_SIZE, NUM_FATS, SPARE_SIZE};
+use crate::Handle;
+
+// the card's geometry; every retail console uses the default, but a development board or a
+// later revision only needs a different one of these. the FAT's own on-card format is fixed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CardLayout {
+    pub block_size: usize,
+    pub spare_size: usize,
+    // FAT slots, counting back from the last block on the card
+    pub num_fats: u32,
+    // blocks before this hold the SKSA and are never allocated to files
+    pub reserved_blocks: u32,
+}
+
+impl Default for CardLayout {
+    fn default() -> Self {
+        Self::ique()
+    }
+}
+
+impl CardLayout {
+    pub fn ique() -> Self {
+        Self {
+            block_size: BLOCK_SIZE,
+            spare_size: SPARE_SIZE,
+            num_fats: NUM_FATS,
+            reserved_blocks: 0x40,
+        }
+    }
+
+    // blocks files may live in on a card of this many blocks
+    pub fn file_area(&self, cardsize: u32) -> Range<u32> {
+        self.reserved_blocks..cardsize.saturating_sub(self.num_fats)
+    }
+
+    pub fn fat_block(&self, cardsize: u32, slot: u32) -> u32 {
+        cardsize - slot - 1
+    }
+
+    // the SKSA, file and FAT areas, in card order
+    pub fn regions(&self, cardsize: u32) -> [(&'static str, Range<u32>); 3] {
+        let files = self.file_area(cardsize);
+        [
+            ("sksa", 0..files.start),
+            ("files", files.clone()),
+            ("fat", files.end..cardsize),
+        ]
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn card_layout(&self) -> &CardLayout {
+        &self.layout
+    }
+
+    pub fn set_card_layout(&mut self, layout: CardLayout) {
+        self.layout = layout;
+    }
+}
@@ -1,23 +1,127 @@
-use std::{iter::repeat, thread::sleep, time::Duration};
-
-use chrono::{DateTime, Datelike, TimeZone, Timelike};
-use commands::Command;
+use std::{
+    cell::{Cell, RefCell},
+    io::Write,
+    ops::Range,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+use builder::TEMP_FILE_NAME;
+use constants::{QUEUE_DEPTH, SCAN_TIMEOUT, TIMEOUT};
+
+pub use constants::{BLOCK_SIZE, SPARE_SIZE};
+use demux::Demux;
 use fs::Fat;
-use indicatif::ProgressIterator;
-use rdb::RDBCommand;
-use rusb::{Device, DeviceHandle, DeviceList, GlobalContext, UsbContext};
-
+use log::warn;
+use rusb::{Device, DeviceList, GlobalContext, UsbContext};
+
+mod audit;
+mod backup;
+mod badblocks;
+mod bench;
+mod boot;
+mod builder;
+mod capabilities;
+mod card_events;
+mod card_map;
 mod commands;
 mod constants;
+mod content_check;
+mod crl;
+mod demux;
+mod diff;
+mod emulator;
 mod error;
+mod fault;
+mod fragmentation;
 mod fs;
+mod guard;
+mod info;
+mod kernel;
+mod layout;
+mod lifecycle;
+mod listing;
+mod local_compare;
+mod loopback;
+mod manifest;
+mod nand;
+mod native;
+mod packet_trace;
+mod pipeline;
 mod player_comms;
+mod progress;
+mod queue;
 mod rdb;
+mod retry;
+mod saves;
+mod sigdb;
+mod sksa_info;
+mod spare;
+mod stats;
+mod sync;
+mod ticket;
+mod time;
+mod transport;
+mod undelete;
+mod uninstall;
 mod usb;
-
+#[cfg(test)]
+mod virtual_console;
+mod worker;
+
+pub use audit::{to_hex, AuditEntry, AuditLog};
+pub use backup::Backup;
+pub use badblocks::{BadBlockComparison, BadBlockDisagreement, BadBlockMap};
+pub use bench::{BenchOp, BenchOptions, BenchResult};
+pub use boot::{BootAreaReport, BootBlockIssue, SkMatch, SK_BLOCKS};
+pub use builder::HandleBuilder;
+pub use capabilities::{Capabilities, Support};
+pub use card_events::CardEvent;
+pub use card_map::{BlockUse, CardMap};
+pub use commands::{Command, CommandTable};
+pub use content_check::{ContentCheck, ContentDecryptor, ContentStatus};
+pub use crl::{Crl, CRL_FILE};
+pub use diff::{diff_nand, NandDiff, RegionDiff};
+pub use emulator::{export_emulator_layout, EmulatorExport};
 use error::*;
-pub use fs::CardStats;
+pub use error::{CardError, ErrorContext, ErrorKind, LibBBRDBError};
+pub use fault::{FaultReport, GPR_NAMES};
+pub use fragmentation::{FileFragments, FragmentationStats};
+pub use fs::{
+    file_checksum, BlockIndex, CardStats, Chain, ChainEnd, FATEntry, FatGeneration, FsIssue,
+    FsReport, FsSnapshot, FILE_SLOTS,
+};
+pub use info::ConsoleInfo;
+pub use kernel::{Sksa, SplitSksa};
+pub use layout::CardLayout;
+pub use listing::{FileCategory, ListedFile, Listing, ListingFormat};
+pub use local_compare::LocalComparison;
+pub use loopback::LoopbackReport;
+pub use manifest::{DumpManifest, DumpRegion};
+use nand::HashWriter;
+pub use nand::{
+    BlockHash, BlockOutcome, BlockWithSpare, DumpDigests, DumpReport, NandImage, WriteReport,
+};
+pub use native::FileBackend;
+pub use packet_trace::Direction;
+use packet_trace::PacketTrace;
+use pipeline::write_behind;
+pub use player_comms::{ConsoleMessage, ConsoleOutput};
+pub use progress::{BarProgress, NoProgress, ProgressSink, ProgressTask, ProgressUnit};
+pub use rdb::RDBCommand;
+pub use retry::RetryPolicy;
+pub use saves::TitleSaves;
+pub use sigdb::{SigDb, SigEntry, SIG_DB_FILE, SIG_ENTRY_SIZE};
+pub use sksa_info::{CmdHead, SaVersion, SksaVersion};
+pub use spare::SpareData;
+pub use stats::{BlockAccess, BlockAccessStats};
+pub use ticket::{Ticket, TicketSys, TICKET_FILE, TICKET_SIZE};
+pub use time::ConsoleTime;
+use transport::{Recorder, Transport};
+pub use undelete::DeletedFile;
+pub use uninstall::UninstallPlan;
 pub use usb::*;
+pub use worker::{BbClient, BbWorker};
 
 #[derive(Debug)]
 struct BBPlayer {
@@ -35,14 +139,49 @@ impl BBPlayer {
 
 #[derive(Debug)]
 pub struct Handle<C: UsbContext> {
-    handle: DeviceHandle<C>,
+    handle: Transport<C>,
     device: Option<BBPlayer>,
+    // the console's, not the card's, so it outlives Close
+    bbid: Cell<Option<u32>>,
+    card_state: Cell<Option<CardEvent>>,
+    card_events: RefCell<Vec<Sender<CardEvent>>>,
+    access: RefCell<BlockAccessStats>,
+    file_backend: FileBackend,
+    retry: RetryPolicy,
+    audit: RefCell<Option<AuditLog>>,
+    demux: RefCell<Demux>,
+    capabilities: RefCell<Capabilities>,
+    packet_trace: RefCell<Option<PacketTrace>>,
+    recorder: RefCell<Option<Recorder>>,
+    rx_buf: RefCell<Vec<u8>>,
+    command_table: CommandTable,
+    progress: Box<dyn ProgressSink>,
+    device_type: RDBType,
+    read_only: bool,
+    timeout: Duration,
+    queue_depth: usize,
+    dump_digests: bool,
+    verify_reads: bool,
+    auto_reinit: bool,
+    // card-writing commands sent so far, to tell whether a failed operation had started writing
+    card_writes: Cell<u64>,
+    // set when the card in the slot isn't the one the FAT was read from
+    card_stale: Cell<bool>,
+    // whether the firmware has ReadBlockAndSpare; None until there's been a card to try it on
+    spare_reads: Cell<Option<bool>>,
+    temp_file_name: String,
+    layout: CardLayout,
+    power_off_on_close: bool,
+    kernel_driver_detached: bool,
+    closed: bool,
 }
 
 #[macro_export]
 macro_rules! require_init {
     ($s:expr, $p:ident $c:block) => {
-        if let Some($p) = &$s.device {
+        if $s.card_stale.get() {
+            Err(LibBBRDBError::CardError($crate::CardError::Changed))
+        } else if let Some($p) = &$s.device {
             $c
         } else {
             Err(LibBBRDBError::NotInitialised)
@@ -53,7 +192,9 @@ macro_rules! require_init {
 #[macro_export]
 macro_rules! require_fat {
     ($s:expr, $p:ident, $f:ident $c:block) => {
-        if let Some($p) = &$s.device {
+        if $s.card_stale.get() {
+            Err(LibBBRDBError::CardError($crate::CardError::Changed))
+        } else if let Some($p) = &$s.device {
             if let Some($f) = &$p.fat {
                 $c
             } else {
@@ -64,7 +205,9 @@ macro_rules! require_fat {
         }
     };
     (mut $s:expr, $p:ident, $f:ident $c:block) => {
-        if let Some($p) = &mut $s.device {
+        if $s.card_stale.get() {
+            Err(LibBBRDBError::CardError($crate::CardError::Changed))
+        } else if let Some($p) = &mut $s.device {
             if let Some($f) = &mut $p.fat {
                 $c
             } else {
@@ -78,16 +221,71 @@ macro_rules! require_fat {
 
 impl<C: UsbContext> Handle<C> {
     pub fn new(device: &Device<C>) -> Result<Self> {
-        Ok(Self {
-            handle: open_device(device)?,
+        let (handle, detached) = open_device(device)?;
+        let mut rv = Self::with_transport(Transport::Usb(handle));
+        rv.kernel_driver_detached = detached;
+        rv.device_type = bbp_type(device)?;
+        Ok(rv)
+    }
+
+    fn with_transport(handle: Transport<C>) -> Self {
+        Self {
+            handle,
             device: None,
-        })
+            bbid: Default::default(),
+            card_state: Default::default(),
+            card_events: Default::default(),
+            access: Default::default(),
+            file_backend: FileBackend::Host,
+            retry: Default::default(),
+            audit: Default::default(),
+            demux: Default::default(),
+            capabilities: Default::default(),
+            packet_trace: Default::default(),
+            recorder: Default::default(),
+            rx_buf: Default::default(),
+            command_table: CommandTable::standard(),
+            progress: Box::new(BarProgress::default()),
+            device_type: RDBType::Unknown,
+            read_only: false,
+            timeout: TIMEOUT,
+            queue_depth: QUEUE_DEPTH,
+            dump_digests: false,
+            verify_reads: false,
+            auto_reinit: false,
+            card_writes: Default::default(),
+            card_stale: Default::default(),
+            spare_reads: Default::default(),
+            temp_file_name: TEMP_FILE_NAME.to_string(),
+            layout: CardLayout::default(),
+            power_off_on_close: false,
+            kernel_driver_detached: false,
+            closed: false,
+        }
     }
 
     pub fn initialised(&self) -> bool {
         self.device.is_some()
     }
 
+    pub fn device_type(&self) -> RDBType {
+        self.device_type
+    }
+
+    #[deprecated(note = "use device_type")]
+    pub fn rdb_type(&self) -> RDBType {
+        self.device_type()
+    }
+
+    // as of the last Init or GetBBID, without asking the console again
+    pub fn bbid(&self) -> Option<u32> {
+        self.bbid.get()
+    }
+
+    pub fn card_size(&self) -> Option<u32> {
+        self.device.as_ref().map(|p| p.cardsize)
+    }
+
     fn check_initialised(&self) -> Result<()> {
         if !self.initialised() {
             Err(LibBBRDBError::NotInitialised)
@@ -96,126 +294,521 @@ impl<C: UsbContext> Handle<C> {
         }
     }
 
+    // send_command refuses card writes on its own; this is so an operation fails before it starts
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(LibBBRDBError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
     #[allow(non_snake_case)]
     pub fn Init(&mut self) -> Result<()> {
-        if self.initialised() {
-            self.Close()?;
-        }
+        self.audited_mut("Init", vec![], |this| {
+            if this.initialised() {
+                this.Close()?;
+            }
 
-        self.device = BBPlayer::new(self)?;
+            this.device = BBPlayer::new(this)?;
+            this.card_stale.set(false);
+            this.observe_present();
+            this.GetBBID()?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    #[allow(non_snake_case)]
-    pub fn SetLED(&mut self, ledval: u32) -> Result<()> {
-        self.command_response(Command::SetLED, ledval, 1)?;
-        Ok(())
+    // BBPlayer::new only comes back empty when there's no card
+    fn observe_present(&self) {
+        self.observe_card(if self.initialised() {
+            CardEvent::Inserted
+        } else {
+            CardEvent::Removed
+        });
     }
 
-    #[allow(non_snake_case)]
-    pub fn SetTime<Tz: TimeZone>(&mut self, when: DateTime<Tz>) -> Result<()> {
-        let timedata = [
-            (when.year() % 100) as u8,
-            when.month() as u8,
-            when.day() as u8,
-            when.weekday() as u8,
-            0,
-            when.hour() as u8,
-            when.minute() as u8,
-            when.second() as u8,
-        ];
+    // a card that's been reseated or swapped answers Changed until SetCardSeqno runs again. Going
+    // again is only safe if nothing had been written yet, and only once the card has been checked
+    pub(crate) fn retryable_change<T>(
+        &self,
+        rv: &Result<T>,
+        writes_before: u64,
+        operation: &'static str,
+    ) -> bool {
+        self.auto_reinit
+            && self.initialised()
+            && operation != "Init"
+            && matches!(rv, Err(e) if is_card_changed(e))
+            && self.card_writes.get() == writes_before
+    }
 
-        let status = self.command_response(Command::SetTime, &timedata[..4], 1)?[0] as i32;
-        if status < 0 {
-            Err(LibBBRDBError::SetTime(status))
-        } else {
-            self.write_data(RDBCommand::HostData, &timedata[4..])?;
-            Ok(())
+    // the same card, reseated, comes back with the FAT we already have
+    fn is_same_card(&self, fresh: Option<&BBPlayer>) -> bool {
+        let (Some(old), Some(new)) = (&self.device, fresh) else {
+            return false;
+        };
+        match (&old.fat, &new.fat) {
+            (Some(a), Some(b)) => old.cardsize == new.cardsize && a.same_generation(b),
+            _ => false,
         }
     }
 
+    // takes on whatever card is there now; true if it's the one we had before
+    pub(crate) fn reinit_after_change(&mut self, operation: &'static str) -> bool {
+        warn!("the card changed during {operation}; re-initialising");
+        match BBPlayer::new(self) {
+            Ok(fresh) => {
+                let same = self.is_same_card(fresh.as_ref());
+                if !same {
+                    warn!("a different card is in now, so {operation} wasn't tried again");
+                }
+                self.device = fresh;
+                self.card_stale.set(false);
+                self.observe_present();
+                same
+            }
+            Err(e) => {
+                warn!("couldn't re-initialise after the card changed: {e}");
+                self.card_stale.set(true);
+                false
+            }
+        }
+    }
+
+    // for operations that can't replace the FAT: SetCardSeqno stops the console reporting
+    // Changed, so if the card turns out to be a different one, everything that needs the card
+    // has to report it instead until the next Init
+    pub(crate) fn recheck_after_change(&self, operation: &'static str) -> bool {
+        if self.card_stale.get() {
+            return false;
+        }
+
+        warn!("the card changed during {operation}; checking it's the same one");
+        let same = match BBPlayer::new(self) {
+            Ok(fresh) => self.is_same_card(fresh.as_ref()),
+            Err(e) => {
+                warn!("couldn't check the card after it changed: {e}");
+                false
+            }
+        };
+        if !same {
+            warn!("the card isn't the one that was initialised; call Init to use it");
+            self.card_stale.set(true);
+        }
+        same
+    }
+
+    #[allow(non_snake_case)]
+    pub fn SetLED(&mut self, ledval: u32) -> Result<()> {
+        self.audited("SetLED", vec![("value", ledval.to_string())], |this| {
+            this.command_response(Command::SetLED, ledval, 1)?;
+            Ok(())
+        })
+    }
+
     #[allow(non_snake_case)]
     pub fn GetBBID(&self) -> Result<u32> {
-        Ok(self.command_response(Command::GetBBID, 0, 1)?[0])
+        self.guarded(|this| {
+            let bbid = this.command_response(Command::GetBBID, 0, 1)?[0];
+            this.bbid.set(Some(bbid));
+            Ok(bbid)
+        })
     }
 
     #[allow(non_snake_case)]
-    pub fn ScanBadBlocks(&self) -> Result<Vec<bool>> {
-        let blocks = {
-            let this = &self;
+    pub fn ScanBadBlocks(&self) -> Result<BadBlockMap> {
+        self.audited("ScanBadBlocks", vec![], |this| {
             let command = Command::ScanBlocks;
             this.send_command(command, 0)?;
-            sleep(Duration::from_secs(10));
-            this.check_cmd_response(command, 1)
-        }?[0];
-        let blocklist = self.read_data(blocks as usize)?;
 
-        Ok(blocklist.into_iter().map(|b| b != 0).collect())
+            // each read gives up after the usual timeout, so keep asking until the scan finishes
+            let deadline = Instant::now() + SCAN_TIMEOUT;
+            let response = {
+                let progress =
+                    this.progress_task("Scanning for bad blocks", None, ProgressUnit::Blocks);
+                loop {
+                    match this.check_cmd_response(command, 1) {
+                        Err(e) if is_timeout(&e) && Instant::now() < deadline => {
+                            progress.advance(1)
+                        }
+                        rv => break rv,
+                    }
+                }
+            };
+
+            let blocks = response?[0];
+            let blocklist = this.read_data(blocks as usize)?;
+
+            Ok(BadBlockMap::new(
+                blocklist.into_iter().map(|b| b != 0).collect(),
+            ))
+        })
+        .in_operation("ScanBadBlocks")
     }
 
-    #[allow(non_snake_case)]
-    pub fn DumpNAND(&self) -> Result<Vec<u8>> {
-        require_init!(self, player {
-            let num_blocks = player.cardsize;
+    // start..start + count, as long as it's all on the card
+    fn block_range(&self, start: u32, count: u32) -> Result<Range<u32>> {
+        let num_blocks = self.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+        match start.checked_add(count) {
+            Some(end) if end <= num_blocks => Ok(start..end),
+            _ => Err(LibBBRDBError::BlockRangeOutOfBounds(
+                start, count, num_blocks,
+            )),
+        }
+    }
 
-            let mut nand = vec![];
+    fn hashing_dumps(&self) -> bool {
+        self.dump_digests || self.audit_enabled()
+    }
 
-            for i in (0..num_blocks).progress() {
-                let blk = self.read_blocks(i, 1);
-                match blk {
-                    Ok(b) => nand.extend(b),
-                    Err(e) => {
-                        nand.extend(repeat(0).take(0x4000));
-                        eprintln!("{e}");
+    fn dump_blocks_to<W: Write + Send>(
+        &self,
+        blocks: Range<u32>,
+        nand: &mut W,
+    ) -> Result<DumpReport> {
+        let (block_size, hashing) = (self.layout.block_size, self.hashing_dumps());
+        let first = blocks.start;
+
+        let (mut report, digests) = write_behind(
+            |tx| {
+                let mut report = DumpReport {
+                    start: first,
+                    ..Default::default()
+                };
+
+                let progress = self.progress_task(
+                    "Dumping NAND",
+                    Some(blocks.len() as u64),
+                    ProgressUnit::Blocks,
+                );
+                for i in blocks {
+                    let data = match self.read_blocks(i, 1) {
+                        Ok(b) => {
+                            report.blocks.push(BlockOutcome::Read);
+                            b
+                        }
+                        Err(e) => {
+                            warn!("block {i}: {e}");
+                            report.blocks.push(BlockOutcome::ZeroFilled(e.to_string()));
+                            vec![0; block_size]
+                        }
+                    };
+                    if tx.send((i, data)).is_err() {
+                        break;
                     }
+                    progress.advance(1);
                 }
-            }
 
-            Ok(nand)
+                Ok(report)
+            },
+            |rx| {
+                let mut nand = HashWriter::new(nand, hashing);
+                for (i, data) in rx {
+                    nand.write_all(&data)
+                        .map_err(LibBBRDBError::from)
+                        .at_offset("DumpNAND", (i - first) as u64 * block_size as u64)?;
+                }
+                Ok(nand.finish())
+            },
+        )?;
+
+        if let Some(d) = digests {
+            self.audit_hash("nand", d.sha256);
+        }
+        if self.dump_digests {
+            report.nand_digests = digests;
+        }
+
+        Ok(report)
+    }
+
+    fn dump_blocks_spare_to<N: Write + Send, S: Write + Send>(
+        &self,
+        blocks: Range<u32>,
+        nand: &mut N,
+        spare: &mut S,
+    ) -> Result<DumpReport> {
+        let (block_size, spare_size) = (self.layout.block_size, self.layout.spare_size);
+        let hashing = self.hashing_dumps();
+        let first = blocks.start;
+
+        let (mut report, (nand_digests, spare_digests)) = write_behind(
+            |tx| {
+                let mut report = DumpReport {
+                    start: first,
+                    ..Default::default()
+                };
+
+                let progress = self.progress_task(
+                    "Dumping NAND",
+                    Some(blocks.len() as u64),
+                    ProgressUnit::Blocks,
+                );
+                for i in blocks {
+                    let (n, s) = match self.read_block_with_spare(i) {
+                        Ok(b) => {
+                            if b.spare_synthesised() {
+                                report.synthesised_spare.push(i);
+                            }
+                            report.blocks.push(BlockOutcome::Read);
+                            b.into_parts()
+                        }
+                        Err(LibBBRDBError::CardError(CardError::BadBlock(n, s))) => {
+                            warn!("bad block: {i}");
+                            report.blocks.push(BlockOutcome::Bad);
+                            (n, s)
+                        }
+                        Err(e) => {
+                            warn!("block {i}: {e}");
+                            report.blocks.push(BlockOutcome::ZeroFilled(e.to_string()));
+                            (vec![0; block_size], vec![0; spare_size])
+                        }
+                    };
+                    if tx.send((i, n, s)).is_err() {
+                        break;
+                    }
+                    progress.advance(1);
+                }
+
+                Ok(report)
+            },
+            |rx| {
+                let mut nand = HashWriter::new(nand, hashing);
+                let mut spare = HashWriter::new(spare, hashing);
+                for (i, n, s) in rx {
+                    nand.write_all(&n)
+                        .and_then(|_| spare.write_all(&s))
+                        .map_err(LibBBRDBError::from)
+                        .at_block("DumpNANDSpare", i)?;
+                }
+                Ok((nand.finish(), spare.finish()))
+            },
+        )?;
+
+        if let Some(d) = nand_digests {
+            self.audit_hash("nand", d.sha256);
+        }
+        if let Some(d) = spare_digests {
+            self.audit_hash("spare", d.sha256);
+        }
+        if self.dump_digests {
+            report.nand_digests = nand_digests;
+            report.spare_digests = spare_digests;
+        }
+
+        Ok(report)
+    }
+
+    // the image counts from its own first block, the report from the card's
+    fn spare_image(nand: Vec<u8>, spare: Vec<u8>, report: &DumpReport) -> Result<NandImage> {
+        let mut image = NandImage::new(nand, spare)?;
+        image.set_synthesised_spare(
+            report
+                .synthesised_spare
+                .iter()
+                .map(|b| b - report.start)
+                .collect(),
+        );
+        Ok(image)
+    }
+
+    #[allow(non_snake_case)]
+    pub fn DumpNAND(&self) -> Result<(Vec<u8>, DumpReport)> {
+        let mut nand = vec![];
+
+        let report = self.DumpNANDTo(&mut nand)?;
+
+        Ok((nand, report))
+    }
+
+    #[allow(non_snake_case)]
+    pub fn DumpNANDTo<W: Write + Send>(&self, nand: &mut W) -> Result<DumpReport> {
+        self.audited("DumpNAND", vec![], |this| {
+            let blocks = this.block_range(0, this.card_size().unwrap_or_default())?;
+            this.dump_blocks_to(blocks, nand)
         })
     }
 
     #[allow(non_snake_case)]
-    pub fn DumpNANDSpare(&self) -> Result<(Vec<u8>, Vec<u8>)> {
-        require_init!(self, player {
-            let num_blocks = player.cardsize;
+    pub fn DumpRange(&self, start: u32, count: u32) -> Result<(Vec<u8>, DumpReport)> {
+        let mut nand = vec![];
 
-            let mut nand = vec![];
-            let mut spare = vec![];
+        let report = self.DumpRangeTo(start, count, &mut nand)?;
 
-            for i in (0..num_blocks).progress() {
-                let blk = self.read_blocks_spare(i, 1);
-                match blk {
-                    Ok((n, s)) => {
-                        nand.extend(n);
-                        spare.extend(s);
-                    }
-                    Err(LibBBRDBError::CardError(CardError::BadBlock(n, s))) => {
-                        nand.extend(n);
-                        spare.extend(s);
-                        eprintln!("bad block: {i}");
-                    }
-                    Err(e) => {
-                        nand.extend(repeat(0).take(0x4000));
-                        spare.extend(repeat(0).take(0x10));
-                        eprintln!("{e}");
-                    }
-                }
-            }
+        Ok((nand, report))
+    }
 
-            Ok((nand, spare))
+    #[allow(non_snake_case)]
+    pub fn DumpRangeTo<W: Write + Send>(
+        &self,
+        start: u32,
+        count: u32,
+        nand: &mut W,
+    ) -> Result<DumpReport> {
+        let params = vec![("start", start.to_string()), ("count", count.to_string())];
+        self.audited("DumpRange", params, |this| {
+            let blocks = this.block_range(start, count)?;
+            this.dump_blocks_to(blocks, nand)
         })
     }
 
     #[allow(non_snake_case)]
-    pub fn ReadSingleBlock(&self, block_num: u32) -> Result<(Vec<u8>, Vec<u8>)> {
-        self.read_blocks_spare(block_num, 1)
+    pub fn DumpNANDSpare(&self) -> Result<(NandImage, DumpReport)> {
+        let mut nand = vec![];
+        let mut spare = vec![];
+
+        let report = self.DumpNANDSpareTo(&mut nand, &mut spare)?;
+
+        Ok((Self::spare_image(nand, spare, &report)?, report))
     }
 
     #[allow(non_snake_case)]
-    pub fn WriteSingleBlock(&mut self, block_num: u32, data: &[u8], spare: &[u8]) -> Result<()> {
-        self.write_blocks_spare(block_num, &[(data, spare)])
+    pub fn DumpNANDSpareTo<N: Write + Send, S: Write + Send>(
+        &self,
+        nand: &mut N,
+        spare: &mut S,
+    ) -> Result<DumpReport> {
+        self.audited("DumpNANDSpare", vec![], |this| {
+            let blocks = this.block_range(0, this.card_size().unwrap_or_default())?;
+            this.dump_blocks_spare_to(blocks, nand, spare)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn DumpRangeSpare(&self, start: u32, count: u32) -> Result<(NandImage, DumpReport)> {
+        let mut nand = vec![];
+        let mut spare = vec![];
+
+        let report = self.DumpRangeSpareTo(start, count, &mut nand, &mut spare)?;
+
+        Ok((Self::spare_image(nand, spare, &report)?, report))
+    }
+
+    #[allow(non_snake_case)]
+    pub fn DumpRangeSpareTo<N: Write + Send, S: Write + Send>(
+        &self,
+        start: u32,
+        count: u32,
+        nand: &mut N,
+        spare: &mut S,
+    ) -> Result<DumpReport> {
+        let params = vec![("start", start.to_string()), ("count", count.to_string())];
+        self.audited("DumpRangeSpare", params, |this| {
+            let blocks = this.block_range(start, count)?;
+            this.dump_blocks_spare_to(blocks, nand, spare)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ReadSingleBlock(&self, block_num: u32) -> Result<BlockWithSpare> {
+        self.audited(
+            "ReadSingleBlock",
+            vec![("block", block_num.to_string())],
+            |this| {
+                let block = this
+                    .read_block_with_spare(block_num)
+                    .at_block("ReadSingleBlock", block_num)?;
+                this.audit_hash("block", block.hash());
+                Ok(block)
+            },
+        )
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteSingleBlock(&mut self, block_num: u32, block: &BlockWithSpare) -> Result<()> {
+        self.audit_hash("block", block.hash());
+        self.audited_mut(
+            "WriteSingleBlock",
+            vec![("block", block_num.to_string())],
+            |this| {
+                this.check_writable()?;
+                this.write_blocks_spare(block_num, &[(block.data(), block.spare())])
+            },
+        )
+        .at_block("WriteSingleBlock", block_num)
+    }
+
+    // with fail_fast the first bad write is returned as an error; otherwise every block is tried
+    // and the failures come back in the report
+    #[allow(non_snake_case)]
+    pub fn WriteNAND(&mut self, image: &NandImage, fail_fast: bool) -> Result<WriteReport> {
+        let params = vec![("fail_fast", fail_fast.to_string())];
+        self.audited_mut("WriteNAND", params, |this| {
+            this.check_writable()?;
+
+            let num_blocks = this.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+            if image.num_blocks() != num_blocks as usize {
+                return Err(LibBBRDBError::IncorrectNumBlocks(
+                    num_blocks as usize,
+                    image.num_blocks(),
+                ));
+            }
+
+            let mut report = WriteReport::default();
+
+            this.with_progress(
+                "Writing NAND",
+                Some(num_blocks as u64),
+                ProgressUnit::Blocks,
+                |this| {
+                    for (i, (data, spare)) in image.blocks().enumerate() {
+                        let i = i as u32;
+                        match this.write_blocks_spare(i, &[(data, spare)]) {
+                            Ok(()) => report.written += 1,
+                            Err(e) if fail_fast => return Err(e).at_block("WriteNAND", i),
+                            Err(e) => {
+                                warn!("block {i}: {e}");
+                                report.failed.push((i, e));
+                            }
+                        }
+                        this.progress().advance(1);
+                    }
+                    Ok(())
+                },
+            )?;
+
+            // the image brought its own FAT with it
+            if let Err(e) = this.reload_fat() {
+                warn!("the restored card's FAT couldn't be read: {e}");
+                report.fat_error = Some(e);
+            }
+
+            Ok(report)
+        })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn WriteSingleBlockIfUnchanged(
+        &mut self,
+        block_num: u32,
+        expected: &BlockHash,
+        block: &BlockWithSpare,
+    ) -> Result<()> {
+        self.audit_hash("expected", *expected);
+        self.audit_hash("block", block.hash());
+        self.audited_mut(
+            "WriteSingleBlockIfUnchanged",
+            vec![("block", block_num.to_string())],
+            |this| {
+                this.check_writable()?;
+
+                let current = match this.read_blocks_spare(block_num, 1) {
+                    Ok((n, s)) => BlockWithSpare::new(n, s)?,
+                    Err(LibBBRDBError::CardError(CardError::BadBlock(n, s))) => {
+                        BlockWithSpare::new(n, s)?
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                if &current.hash() != expected {
+                    return Err(LibBBRDBError::BlockChanged(block_num));
+                }
+
+                this.write_blocks_spare(block_num, &[(block.data(), block.spare())])
+            },
+        )
+        .at_block("WriteSingleBlockIfUnchanged", block_num)
     }
 
     #[allow(non_snake_case)]
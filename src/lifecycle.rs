@@ -0,0 +1,40 @@
+use log::warn;
+use rusb::UsbContext;
+
+use crate::commands::Command;
+use crate::error::*;
+use crate::Handle;
+
+impl<C: UsbContext> Handle<C> {
+    // only does anything the first time, so shutdown() and then Drop don't both try
+    fn release(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        // the console doesn't answer a PowerOff, so there's nothing to wait for
+        let powered_off = if self.power_off_on_close {
+            self.send_command(Command::PowerOff, 0)
+        } else {
+            Ok(())
+        };
+
+        self.release_transport().and(powered_off)
+    }
+
+    // gives the interface back to the system, and to whichever kernel driver had it before;
+    // dropping the handle does the same, but can only log what went wrong. Not to be confused
+    // with Close, which only forgets the card
+    pub fn shutdown(mut self) -> Result<()> {
+        self.release()
+    }
+}
+
+impl<C: UsbContext> Drop for Handle<C> {
+    fn drop(&mut self) {
+        if let Err(e) = self.release() {
+            warn!("couldn't close the console cleanly: {e}");
+        }
+    }
+}
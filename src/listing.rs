@@ -0,0 +1,175 @@
+use rusb::UsbContext;
+use serde_json::{json, Value};
+
+use crate::error::*;
+use crate::fs::{BlockIndex, ChainEnd, FATEntry};
+use crate::require_fat;
+use crate::saves::{content_file, CONTENT_EXTENSIONS, SAVE_EXTENSIONS};
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    // the file entries with their chains, and the whole FAT block by block
+    Json,
+    // one row per file; the block-by-block FAT only goes in the JSON
+    Csv,
+}
+
+// the system files the SA and the depot manage, as opposed to a title's own files
+pub(crate) const SYSTEM_FILES: [&str; 6] = [
+    "ticket.sys",
+    "sig.db",
+    "crl.sys",
+    "cert.sys",
+    "recrypt.sys",
+    "timer.sys",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileCategory {
+    System,
+    // a title's .app/.aes/.rec
+    Content,
+    Save,
+    Unknown,
+}
+
+impl FileCategory {
+    pub fn of(name: &str) -> Self {
+        let lower = name.to_ascii_lowercase();
+        if SYSTEM_FILES.contains(&lower.as_str()) || lower.ends_with(".sys") {
+            return Self::System;
+        }
+
+        match content_file(&lower) {
+            Some((_, ext)) if CONTENT_EXTENSIONS.contains(&ext.as_str()) => Self::Content,
+            Some((_, ext)) if SAVE_EXTENSIONS.contains(&ext.as_str()) => Self::Save,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::Content => "content",
+            Self::Save => "save",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedFile {
+    // position in the file table
+    pub slot: usize,
+    pub name: String,
+    pub category: FileCategory,
+    pub size: usize,
+    pub start: FATEntry,
+    pub blocks: Vec<BlockIndex>,
+    pub end: Option<ChainEnd>,
+}
+
+// a card's filesystem as data, so two of them can be diffed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub seqno: u32,
+    pub fat_block: u32,
+    pub files: Vec<ListedFile>,
+    pub fat: Vec<FATEntry>,
+}
+
+fn entry_json(entry: &FATEntry) -> Value {
+    match entry {
+        FATEntry::Free => json!("free"),
+        FATEntry::EndOfChain => json!("end"),
+        FATEntry::BadBlock => json!("bad"),
+        FATEntry::Reserved => json!("reserved"),
+        FATEntry::Chain(n) => json!(n),
+    }
+}
+
+fn end_name(end: Option<ChainEnd>) -> String {
+    match end {
+        Some(ChainEnd::End) => "end".to_string(),
+        Some(ChainEnd::Loop(b)) => format!("loop at {b}"),
+        Some(ChainEnd::OutOfRange(b)) => format!("out of range at {b}"),
+        Some(ChainEnd::Invalid(_, e)) => format!("invalid {e:?}"),
+        None => "unknown".to_string(),
+    }
+}
+
+// names on a corrupt card can hold anything, so quote them when they need it
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Listing {
+    pub fn in_category(&self, category: FileCategory) -> impl Iterator<Item = &ListedFile> {
+        self.files.iter().filter(move |f| f.category == category)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "seqno": self.seqno,
+            "fat_block": self.fat_block,
+            "files": self
+                .files
+                .iter()
+                .map(|f| json!({
+                    "slot": f.slot,
+                    "name": f.name,
+                    "category": f.category.name(),
+                    "size": f.size,
+                    "start": entry_json(&f.start),
+                    "blocks": f.blocks,
+                    "end": end_name(f.end),
+                }))
+                .collect::<Vec<_>>(),
+            "fat": self.fat.iter().map(entry_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut rv = "slot,name,category,size,blocks,end,chain\n".to_string();
+        for f in &self.files {
+            let chain = f
+                .blocks
+                .iter()
+                .map(|b| b.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            rv += &format!(
+                "{},{},{},{},{},{},{chain}\n",
+                f.slot,
+                csv_field(&f.name),
+                f.category.name(),
+                f.size,
+                f.blocks.len(),
+                csv_field(&end_name(f.end)),
+            );
+        }
+        rv
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn Listing(&self) -> Result<Listing> {
+        require_fat!(self, _p, fat { Ok(fat.listing()) })
+    }
+
+    #[allow(non_snake_case)]
+    pub fn ExportListing(&self, format: ListingFormat) -> Result<String> {
+        let listing = self.Listing()?;
+        Ok(match format {
+            ListingFormat::Json => listing.to_json().to_string(),
+            ListingFormat::Csv => listing.to_csv(),
+        })
+    }
+}
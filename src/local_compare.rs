@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::fs::file_checksum;
+use crate::Handle;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LocalComparison {
+    pub same: Vec<String>,
+    pub differ: Vec<String>,
+    // in the directory, not on the card
+    pub missing: Vec<String>,
+    // on the card, not in the directory
+    pub extra: Vec<String>,
+}
+
+impl LocalComparison {
+    pub fn in_sync(&self) -> bool {
+        self.differ.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // compares the files directly in `dir` with the card's by name; the console sums its own
+    // copies, so nothing but the sizes and sums crosses the USB link
+    #[allow(non_snake_case)]
+    pub fn CompareWithLocal<P: AsRef<Path>>(&self, dir: P) -> Result<LocalComparison> {
+        let dir = dir.as_ref();
+        let params = vec![("dir", dir.display().to_string())];
+        self.audited("CompareWithLocal", params, |this| {
+            let mut card = this
+                .ListFiles()?
+                .into_iter()
+                .filter(|(name, _)| *name != this.temp_file_name)
+                .collect::<BTreeMap<_, _>>();
+
+            let mut local = BTreeMap::new();
+            for entry in read_dir(dir)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                if let Some(name) = entry.file_name().to_str() {
+                    local.insert(name.to_string(), entry.path());
+                }
+            }
+
+            let mut rv = LocalComparison::default();
+            for (name, path) in local {
+                let Some(size) = card.remove(&name) else {
+                    rv.missing.push(name);
+                    continue;
+                };
+
+                let data = read(&path)?;
+                let same = data.len() == size
+                    && this
+                        .checksum_file(&name, file_checksum(&data), size as u32)
+                        .for_file("CompareWithLocal", &name)?;
+                if same {
+                    rv.same.push(name);
+                } else {
+                    rv.differ.push(name);
+                }
+            }
+            rv.extra = card.into_keys().collect();
+
+            Ok(rv)
+        })
+    }
+}
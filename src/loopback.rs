@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use rusb::UsbContext;
+
+use crate::demux::Channel;
+use crate::error::*;
+use crate::rdb::{to_u32, RDBCommand};
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopbackReport {
+    pub bytes: u64,
+    pub rounds: u32,
+    pub mismatched_rounds: u32,
+    pub elapsed: Duration,
+}
+
+impl LoopbackReport {
+    // bytes per second in each direction
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+fn test_pattern(round: u32, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u32).wrapping_mul(31).wrapping_add(round) as u8)
+        .collect()
+}
+
+impl<C: UsbContext> Handle<C> {
+    fn send_debug(&self, data: &[u8]) -> Result<()> {
+        let len = (data.len() as u32).to_be_bytes();
+        self.send_rdb_data(RDBCommand::HostDebugCT, &len[1..])?;
+        self.send_rdb_data(RDBCommand::HostDebug, data)
+    }
+
+    // the echo app reads a length, then that many bytes, and sends them straight back
+    fn echo_round(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.send_debug(&(data.len() as u32).to_be_bytes())?;
+        self.send_debug(data)?;
+
+        let mut rv = vec![];
+        loop {
+            let (cmd, data) = self.read_packet_for(Channel::Debug)?;
+            match cmd {
+                RDBCommand::DeviceDebug => rv.extend(data),
+                RDBCommand::DeviceDebugDone => break,
+                x => {
+                    return Err(LibBBRDBError::RDBUnexpected(
+                        x,
+                        vec![RDBCommand::DeviceDebug, RDBCommand::DeviceDebugDone],
+                    ))
+                }
+            }
+        }
+
+        self.send_rdb_signal(RDBCommand::HostDebugDone)?;
+
+        Ok(rv)
+    }
+
+    // needs the echo app from examples/console running on the console; `size` must fit in
+    // the 24-bit count of a HostDebugCT packet and in the app's buffer
+    #[allow(non_snake_case)]
+    pub fn LoopbackTest(&self, size: usize, rounds: u32) -> Result<LoopbackReport> {
+        self.guarded(|this| {
+            if to_u32(&(size as u32).to_be_bytes()[1..]) as usize != size {
+                return Err(LibBBRDBError::WrongDataLength);
+            }
+
+            let mut mismatched_rounds = 0;
+            let start = Instant::now();
+
+            for round in 0..rounds {
+                let data = test_pattern(round, size);
+                if this.echo_round(&data)? != data {
+                    mismatched_rounds += 1;
+                }
+            }
+
+            Ok(LoopbackReport {
+                bytes: size as u64 * rounds as u64,
+                rounds,
+                mismatched_rounds,
+                elapsed: start.elapsed(),
+            })
+        })
+    }
+}
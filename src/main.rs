@@ -1,60 +1,2306 @@
-use std::fs::{read, write};
+use std::cell::Cell;
+use std::fs::{read, write, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
 
-use anyhow::Result;
-use bbrdb::{scan_devices, Handle};
+#[cfg(feature = "tui")]
+mod browse;
 
-fn main() -> Result<()> {
-    let devices = scan_devices()?;
+use anyhow::{bail, Context, Result};
+use bbrdb::{
+    choose_device, diff_nand, export_emulator_layout, file_checksum, to_hex, Backup, BarProgress,
+    BenchOptions, BlockUse, BlockWithSpare, CardError, ConsoleMessage, ContentStatus, DeviceChoice,
+    DeviceStrategy, DumpDigests, FATEntry, FileCategory, FsIssue, GlobalHandle, Handle,
+    LibBBRDBError, ListingFormat, NandImage, NoProgress, ProgressSink, ProgressTask, ProgressUnit,
+    SaVersion, SksaVersion, WriteReport, BLOCK_SIZE, SPARE_SIZE,
+};
+use chrono::Local;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
 
-    println!("{devices:?}");
+const EXIT_CODES: &str = "\
+Exit status:
+  0 success, 1 other failure, 2 bad usage
+  3 USB error, 4 timeout, 5 no console, 6 not initialised, 7 protocol error
+  8 no card, 9 card error, 10 filesystem error, 11 file not found
+  12 invalid input, 13 I/O error, 14 verification failed, 15 unsupported, 16 internal error
+  17 read-only";
 
-    let device = &devices[0];
+#[derive(Parser)]
+#[command(version, about = "Talk to an iQue Player over USB", after_help = EXIT_CODES)]
+struct Cli {
+    /// Print results as JSON, and progress as JSON lines on stderr
+    #[arg(long, global = true)]
+    json: bool,
 
-    println!("{device:?}");
+    /// How to report progress on long operations (json if --json is given, a bar otherwise)
+    #[arg(long, global = true, value_enum)]
+    progress: Option<ProgressMode>,
 
-    let mut handle = Handle::new(device)?;
+    /// Don't report progress at all (the same as --progress=none)
+    #[arg(short, long, global = true, conflicts_with = "progress")]
+    quiet: bool,
 
-    println!("id: {:08X}", handle.GetBBID()?);
+    /// Refuse anything that would change the card
+    #[arg(long, global = true)]
+    read_only: bool,
 
-    handle.Init()?;
+    /// Have the console checksum each file read, to catch corruption on the way over USB
+    #[arg(long, global = true)]
+    verify_reads: bool,
 
-    println!("initialised: {}", handle.initialised());
+    /// Turn the console off when finished
+    #[arg(long, global = true)]
+    power_off: bool,
 
-    //let bad = handle.ScanBadBlocks()?;
-    //println!("{bad:?}");
+    /// Use the console with this BBID (in hex)
+    #[arg(long, global = true, value_parser = parse_bbid, group = "device")]
+    serial: Option<u32>,
 
-    //let nand = handle.DumpNAND()?;
+    /// Use the Nth console found, counting from 0
+    #[arg(long, global = true, group = "device")]
+    index: Option<usize>,
 
-    //write("nand.bin", nand)?;
+    /// Use the console at this USB bus and address, as BUS:ADDRESS
+    #[arg(long, global = true, value_parser = parse_bus_address, group = "device")]
+    bus_address: Option<(u8, u8)>,
 
-    println!("files: {:#?}", handle.ListFiles()?);
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// List the files on the card
+    Ls {
+        /// Print every file entry with its block chain (and, as JSON, the whole FAT) instead
+        #[arg(long, value_enum)]
+        export: Option<ExportFormat>,
+    },
+
+    /// Copy a file from the card
+    Get {
+        name: String,
+        /// Where to write it (defaults to the same name)
+        output: Option<PathBuf>,
+    },
+
+    /// Check a card file against a local copy, without reading it back
+    Cmp {
+        name: String,
+        /// The local copy (defaults to the same name)
+        local: Option<PathBuf>,
+    },
+
+    /// Compare a directory with the card, using the console's own checksums
+    Compare { dir: PathBuf },
+
+    /// Copy a file to the card
+    #[cfg(feature = "writing")]
+    Put {
+        input: PathBuf,
+        /// Name on the card (defaults to the input's file name)
+        name: Option<String>,
+    },
+
+    /// Delete a file from the card
+    #[cfg(feature = "writing")]
+    Rm { name: String },
+
+    /// Rename a file on the card
+    #[cfg(feature = "writing")]
+    Mv { from: String, to: String },
+
+    /// Delete everything except the system files and the content they have tickets for
+    #[cfg(feature = "writing")]
+    Wipe {
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// List every FAT generation still on the card
+    Fats,
+
+    /// List files that older FAT generations still have but the current one doesn't
+    Deleted,
+
+    /// Bring back a deleted file from an older FAT generation
+    #[cfg(feature = "writing")]
+    Undelete {
+        name: String,
+        /// The generation to take it from (defaults to the newest one it can be recovered from)
+        #[arg(long)]
+        seqno: Option<u32>,
+    },
+
+    /// Dump or restore the raw NAND
+    Nand {
+        #[command(subcommand)]
+        action: NandCmd,
+    },
+
+    /// Show card usage
+    Stats {
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Check the filesystem for problems
+    Check,
+
+    /// Draw what every block on the card is used for
+    Map,
+
+    /// Browse the card's files interactively
+    #[cfg(feature = "tui")]
+    Browse,
+
+    /// Run a script of commands, one per line, against the same console
+    ///
+    /// Each line is a command as it would be given on the command line, with words separated by
+    /// whitespace; blank lines and lines starting with # are skipped.
+    Run {
+        /// The script to run, or - for stdin
+        script: PathBuf,
+        /// Carry on with the next line when one fails
+        #[arg(long)]
+        keep_going: bool,
+    },
+
+    /// Print the console's debug output as it arrives
+    Monitor {
+        /// Also append everything to this file
+        #[arg(long)]
+        log: Option<PathBuf>,
+        /// Leave out the timestamps
+        #[arg(long)]
+        no_timestamps: bool,
+    },
+
+    /// Save the whole card, with its file listing and a hash manifest, to one archive
+    Backup { output: PathBuf },
+
+    /// Put a card back exactly as it was when a backup was made
+    Restore {
+        input: PathBuf,
+        /// Restore even if the backup came from a different console
+        #[arg(long)]
+        force: bool,
+        /// Carry on past blocks that fail to write, and list them all at the end
+        #[arg(long)]
+        keep_going: bool,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Compare the card against a NAND image, block by block
+    Verify {
+        image: PathBuf,
+        /// Spare data to compare alongside a plain image
+        #[arg(long)]
+        spare_file: Option<PathBuf>,
+    },
+
+    /// Scan the card for bad blocks and print a map of them
+    Badblocks {
+        /// Compare the scan against the FAT's bad block entries
+        #[arg(long)]
+        fat: bool,
+        /// Compare the scan against the bad block markers in each block's spare (reads the whole card)
+        #[arg(long)]
+        spare: bool,
+    },
+
+    /// Measure how fast blocks move between the host and the card
+    Bench {
+        /// Blocks to move at each queue depth
+        #[arg(long, default_value_t = 64)]
+        blocks: u32,
+        /// Queue depths to try; each keeps depth x 4KiB of reads in flight
+        #[arg(long = "depth", default_values_t = [1, 2, 4, 8])]
+        depths: Vec<usize>,
+        /// Also time writes, to blocks the FAT has free
+        #[arg(long)]
+        write: bool,
+    },
+
+    /// Resync with a console that's stopped responding, without replugging it
+    Reset,
+
+    /// Print the console's BBID
+    Bbid,
+
+    /// Show the console's BBID, type and card usage
+    Info,
+
+    /// Set the console's clock to the host's local time
+    SetTime,
+
+    /// Dump or flash the secure kernel and system apps
+    Sksa {
+        #[command(subcommand)]
+        action: SksaCmd,
+    },
+
+    /// List, back up or restore save files, by title
+    Saves {
+        #[command(subcommand)]
+        action: SavesCmd,
+    },
+
+    /// List the tickets in ticket.sys
+    Tickets,
+
+    /// List the certificate revocation lists in crl.sys
+    Crls,
+
+    /// Read every title's content and check it against its ticket
+    CheckContent,
+
+    /// Remove a title's content and its tickets, and optionally its saves
+    #[cfg(feature = "writing")]
+    Uninstall {
+        /// Content ID in hex
+        #[arg(value_parser = parse_content_id)]
+        content_id: u32,
+        /// Delete the title's save files too
+        #[arg(long)]
+        saves: bool,
+        /// Only show what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SavesCmd {
+    /// List each title's save files
+    List,
+
+    /// Copy save files from the card into a directory
+    Export {
+        dir: PathBuf,
+        /// Only this title's saves (content ID in hex)
+        #[arg(long, value_parser = parse_content_id)]
+        title: Option<u32>,
+    },
+
+    /// Copy save files from a directory back onto the card
+    #[cfg(feature = "writing")]
+    Import {
+        dir: PathBuf,
+        /// Only this title's saves (content ID in hex)
+        #[arg(long, value_parser = parse_content_id)]
+        title: Option<u32>,
+    },
+}
+
+#[derive(Subcommand)]
+enum NandCmd {
+    /// Dump the NAND, or just part of it
+    Dump {
+        output: PathBuf,
+        /// Include the spare data, interleaved after each block
+        #[arg(long, conflicts_with = "spare_file")]
+        spare: bool,
+        /// Write the spare data to this file instead of interleaving it
+        #[arg(long)]
+        spare_file: Option<PathBuf>,
+        /// First block to dump (decimal, or hex with 0x)
+        #[arg(long, value_parser = parse_block)]
+        start: Option<u32>,
+        /// How many blocks to dump (to the end of the card if not given)
+        #[arg(long, value_parser = parse_block)]
+        count: Option<u32>,
+        /// Print CRC32, SHA-1 and SHA-256 of the data (and spare) as it was dumped
+        #[arg(long)]
+        hash: bool,
+        /// Write a JSON manifest of the dump (BBID, bad blocks, per-region hashes) to this file
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+
+    /// Write a NAND image back to the card, block by block
+    ///
+    /// Plain and interleaved images are told apart by their size.
+    Restore {
+        input: PathBuf,
+        /// Spare data to write alongside a plain image (blank spares otherwise)
+        #[arg(long)]
+        spare_file: Option<PathBuf>,
+        /// Carry on past blocks that fail to write, and list them all at the end
+        #[arg(long)]
+        keep_going: bool,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Lay a dump out for an emulator: raw NAND and spare, split SKSA, the FS and every file
+    Export {
+        /// Directory to write everything to
+        output: PathBuf,
+        /// Export this dump, which needs its spare data, instead of dumping the card
+        #[arg(long)]
+        image: Option<PathBuf>,
+        /// Spare data to go with a plain image
+        #[arg(long, requires = "image")]
+        spare_file: Option<PathBuf>,
+    },
+
+    /// List the blocks that differ between two NAND images, or between one and the card
+    Diff {
+        old: PathBuf,
+        /// The image to compare against (the card itself if not given)
+        new: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SksaCmd {
+    /// Dump the SKSA to a file
+    Dump {
+        output: PathBuf,
+        /// Treat the output as a directory and write the SK and SAs to separate files in it
+        #[arg(long)]
+        split: bool,
+        /// Cut each SA down to the size its CmdHead gives, instead of whole blocks
+        #[arg(long, requires = "split")]
+        trim: bool,
+    },
+
+    /// Show which SK and SAs the card has, from their hashes and CmdHeads
+    Info {
+        /// Also say which parts flashing this SKSA would change
+        #[arg(long)]
+        against: Option<PathBuf>,
+    },
+
+    /// Write an SKSA to the card
+    #[cfg(feature = "writing")]
+    Flash {
+        input: PathBuf,
+        /// Flash even if the SK doesn't match the one already on the card
+        #[arg(long)]
+        force: bool,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NandFormat {
+    Plain,
+    Interleaved,
+}
+
+impl NandFormat {
+    fn block_size(self) -> usize {
+        match self {
+            Self::Plain => BLOCK_SIZE,
+            Self::Interleaved => BLOCK_SIZE + SPARE_SIZE,
+        }
+    }
+
+    fn detect(len: usize, card_blocks: usize) -> Result<Self> {
+        for format in [Self::Plain, Self::Interleaved] {
+            if len == card_blocks * format.block_size() {
+                return Ok(format);
+            }
+        }
+
+        // say what it looks like rather than just that it's wrong
+        if len == card_blocks * SPARE_SIZE {
+            bail!(
+                "that looks like a spare file; pass the NAND image and use --spare-file for this"
+            );
+        }
+        for format in [Self::Plain, Self::Interleaved] {
+            if len != 0
+                && len.is_multiple_of(format.block_size())
+                && (len / format.block_size()).is_multiple_of(4096)
+            {
+                bail!(
+                    "that's a {format:?} image of a {}-block card, but this card has {card_blocks} blocks",
+                    len / format.block_size()
+                );
+            }
+        }
+        bail!(
+            "{len:#X} bytes isn't a NAND image for this card (expected {:#X}, or {:#X} with spare)",
+            card_blocks * BLOCK_SIZE,
+            card_blocks * (BLOCK_SIZE + SPARE_SIZE)
+        );
+    }
+}
+
+fn parse_bbid(s: &str) -> std::result::Result<u32, String> {
+    let s = s.trim_start_matches("0x");
+    u32::from_str_radix(s, 16).map_err(|e| format!("not a hex BBID: {e}"))
+}
+
+fn parse_content_id(s: &str) -> std::result::Result<u32, String> {
+    let s = s.trim_start_matches("0x");
+    u32::from_str_radix(s, 16).map_err(|e| format!("not a hex content ID: {e}"))
+}
+
+fn parse_block(s: &str) -> std::result::Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{s:?}: {e}"))
+}
+
+fn parse_bus_address(s: &str) -> std::result::Result<(u8, u8), String> {
+    let (bus, address) = s
+        .split_once(':')
+        .ok_or_else(|| "expected BUS:ADDRESS".to_string())?;
+    let parse = |n: &str| n.parse::<u8>().map_err(|e| format!("{n:?}: {e}"));
+    Ok((parse(bus)?, parse(address)?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl From<ExportFormat> for ListingFormat {
+    fn from(format: ExportFormat) -> Self {
+        match format {
+            ExportFormat::Json => ListingFormat::Json,
+            ExportFormat::Csv => ListingFormat::Csv,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressMode {
+    Bar,
+    Json,
+    None,
+}
+
+impl ProgressMode {
+    fn sink(self) -> Box<dyn ProgressSink> {
+        match self {
+            Self::Bar => Box::new(BarProgress::default()),
+            Self::Json => Box::new(JsonProgress::default()),
+            Self::None => Box::new(NoProgress),
+        }
+    }
+
+    fn install(self, handle: &mut GlobalHandle) {
+        match self {
+            Self::Bar => handle.set_progress_sink(BarProgress::default()),
+            Self::Json => handle.set_progress_sink(JsonProgress::default()),
+            Self::None => handle.set_progress_sink(NoProgress),
+        }
+    }
+}
 
-    let temp = handle.ReadFile("temp.tmp")?;
-    if let Some(t) = temp {
-        write("temp.tmp", t)?;
+impl Cli {
+    fn progress_mode(&self) -> ProgressMode {
+        match (self.quiet, self.progress) {
+            (true, _) => ProgressMode::None,
+            (_, Some(mode)) => mode,
+            _ if self.json => ProgressMode::Json,
+            _ => ProgressMode::Bar,
+        }
     }
 
-    /*while handle.ListFiles()?.contains(&"test.bin".to_string()) {
-        handle.DeleteFile("test.bin")?;
-    }*/
+    fn device_strategy(&self) -> DeviceStrategy {
+        match (self.serial, self.index, self.bus_address) {
+            (Some(bbid), _, _) => DeviceStrategy::Bbid(bbid),
+            (_, Some(i), _) => DeviceStrategy::Index(i),
+            (_, _, Some((bus, address))) => DeviceStrategy::BusAddress(bus, address),
+            _ => DeviceStrategy::Single,
+        }
+    }
+}
+
+// one record per whole percent, so scripts aren't flooded on big dumps
+#[derive(Debug, Default)]
+struct JsonProgress {
+    task: Cell<&'static str>,
+    unit: Cell<Option<ProgressUnit>>,
+    total: Cell<Option<u64>>,
+    done: Cell<u64>,
+    percent: Cell<Option<u64>>,
+    started: Cell<Option<Instant>>,
+}
+
+impl JsonProgress {
+    fn emit(&self, event: &str) {
+        let (done, total) = (self.done.get(), self.total.get());
+
+        let elapsed = self
+            .started
+            .get()
+            .map_or(0.0, |s| s.elapsed().as_secs_f64());
+        let rate = (elapsed > 0.0).then(|| done as f64 / elapsed);
+        let eta = match (total, rate) {
+            (Some(t), Some(r)) if r > 0.0 => Some(t.saturating_sub(done) as f64 / r),
+            _ => None,
+        };
+
+        let line = json!({
+            "event": event,
+            "task": self.task.get(),
+            "unit": self.unit.get().map(|u| format!("{u:?}").to_lowercase()),
+            "done": done,
+            "total": total,
+            "elapsed_secs": elapsed,
+            "rate_per_sec": rate,
+            "eta_secs": eta,
+        });
+        eprintln!("{line}");
+    }
+}
+
+impl ProgressSink for JsonProgress {
+    fn begin(&self, task: &'static str, total: Option<u64>, unit: ProgressUnit) {
+        self.task.set(task);
+        self.unit.set(Some(unit));
+        self.total.set(total);
+        self.done.set(0);
+        self.percent.set(None);
+        self.started.set(Some(Instant::now()));
+        self.emit("begin");
+    }
+
+    fn advance(&self, amount: u64) {
+        self.done.set(self.done.get() + amount);
 
-    let sigs = handle.ReadFile("sig.db")?;
-    if let Some(s) = sigs {
-        write("sig.db", s)?;
+        let percent = self.total.get().map(|t| self.done.get() * 100 / t.max(1));
+        if percent != self.percent.get() {
+            self.percent.set(percent);
+            self.emit("progress");
+        }
     }
 
-    let config = handle.ReadFile("config.ini")?;
-    if let Some(c) = config {
-        write("config.ini", c)?;
+    fn finish(&self) {
+        self.emit("finish");
     }
+}
+
+fn open_device(strategy: DeviceStrategy) -> Result<GlobalHandle> {
+    let device = match choose_device(strategy)? {
+        DeviceChoice::Chosen(d) => d,
+        DeviceChoice::Ambiguous(candidates) => {
+            eprintln!("More than one console is connected:");
+            for (i, c) in candidates.iter().enumerate() {
+                eprintln!("  {i}: {}", c.label());
+            }
+            bail!("pick one with --serial, --index or --bus-address");
+        }
+    };
+
+    Handle::new(&device).context("couldn't open the console")
+}
 
-    let data = read("test.bin")?;
-    handle.WriteFile(&data, "test.bin")?;
+fn open(strategy: DeviceStrategy) -> Result<GlobalHandle> {
+    let mut handle = open_device(strategy)?;
+    handle.Init().context("couldn't initialise the console")?;
 
-    let test = handle.ReadFile("test.bin")?;
-    if let Some(t) = test {
-        write("test.bin.new", t)?;
+    if !handle.initialised() {
+        bail!("no card inserted");
+    }
+
+    Ok(handle)
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{prompt} [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+// reads an image and, for plain ones, an optional separate spare file, checking both against the card
+fn load_nand_image(
+    handle: &GlobalHandle,
+    path: &Path,
+    spare_file: Option<PathBuf>,
+) -> Result<(Vec<u8>, NandFormat, Option<Vec<u8>>)> {
+    let image = read(path).with_context(|| format!("couldn't read {}", path.display()))?;
+
+    let card_blocks = handle.card_size().unwrap_or_default() as usize;
+    let format = NandFormat::detect(image.len(), card_blocks)?;
+
+    let spare = match (format, spare_file) {
+        (NandFormat::Interleaved, Some(_)) => {
+            bail!("{} already has spare data interleaved", path.display())
+        }
+        (_, Some(s)) => {
+            let spare = read(&s).with_context(|| format!("couldn't read {}", s.display()))?;
+            if spare.len() != card_blocks * SPARE_SIZE {
+                bail!(
+                    "{} is {:#X} bytes, expected {:#X}",
+                    s.display(),
+                    spare.len(),
+                    card_blocks * SPARE_SIZE
+                );
+            }
+            Some(spare)
+        }
+        (_, None) => None,
+    };
+
+    Ok((image, format, spare))
+}
+
+fn restore(
+    handle: &mut GlobalHandle,
+    input: PathBuf,
+    spare_file: Option<PathBuf>,
+    keep_going: bool,
+    yes: bool,
+    json: bool,
+) -> Result<()> {
+    let (image, format, spare) = load_nand_image(handle, &input, spare_file)
+        .with_context(|| format!("can't restore {}", input.display()))?;
+
+    if !yes && !confirm("This overwrites the entire card. Continue?")? {
+        bail!("cancelled");
+    }
+
+    let mut blocks = NandImage::with_capacity(image.len() / format.block_size());
+    for (i, chunk) in image.chunks(format.block_size()).enumerate() {
+        let data = chunk[..BLOCK_SIZE].to_vec();
+        blocks.push(match (format, &spare) {
+            (NandFormat::Interleaved, _) => {
+                BlockWithSpare::new(data, chunk[BLOCK_SIZE..].to_vec())?
+            }
+            (_, Some(s)) => {
+                BlockWithSpare::new(data, s[i * SPARE_SIZE..(i + 1) * SPARE_SIZE].to_vec())?
+            }
+            (_, None) => BlockWithSpare::with_blank_spare(data)?,
+        });
+    }
+
+    let report = handle.WriteNAND(&blocks, !keep_going)?;
+    check_write_report(&report, blocks.num_blocks(), json)
+}
+
+fn check_write_report(report: &WriteReport, num_blocks: usize, json: bool) -> Result<()> {
+    if json {
+        println!(
+            "{}",
+            json!({
+                "written": report.written,
+                "failed": report
+                    .failed
+                    .iter()
+                    .map(|(b, e)| json!({ "block": b, "error": e.to_string() }))
+                    .collect::<Vec<_>>(),
+                "fat_error": report.fat_error.as_ref().map(|e| e.to_string()),
+                "complete": report.is_complete(),
+            })
+        );
+    } else if let Some(e) = &report.fat_error {
+        eprintln!("the restored card's FAT couldn't be read: {e}");
+    }
+    if !report.is_complete() {
+        if !json {
+            for (block, e) in &report.failed {
+                eprintln!("block {block}: {e}");
+            }
+        }
+        bail!(
+            "{} of {num_blocks} blocks failed to write; the card is only partly restored",
+            report.failed.len(),
+        );
     }
 
     Ok(())
 }
+
+fn digests_json(d: DumpDigests) -> serde_json::Value {
+    json!({
+        "crc32": format!("{:08x}", d.crc32),
+        "sha1": to_hex(&d.sha1),
+        "sha256": to_hex(&d.sha256),
+    })
+}
+
+fn dump(
+    handle: &GlobalHandle,
+    output: PathBuf,
+    spare: bool,
+    spare_file: Option<PathBuf>,
+    range: Option<(u32, u32)>,
+    manifest: Option<PathBuf>,
+    json: bool,
+) -> Result<()> {
+    // the manifest hashes regions of the plain data, which an interleaved dump only has in memory
+    let mut plain = None;
+
+    let mut nand = BufWriter::new(
+        File::create(&output).with_context(|| format!("couldn't create {}", output.display()))?,
+    );
+
+    let report = if let Some(spare_file) = spare_file {
+        let mut s = BufWriter::new(
+            File::create(&spare_file)
+                .with_context(|| format!("couldn't create {}", spare_file.display()))?,
+        );
+        let report = match range {
+            Some((start, count)) => handle.DumpRangeSpareTo(start, count, &mut nand, &mut s)?,
+            None => handle.DumpNANDSpareTo(&mut nand, &mut s)?,
+        };
+        s.flush()?;
+        report
+    } else if spare {
+        let (image, report) = match range {
+            Some((start, count)) => handle.DumpRangeSpare(start, count)?,
+            None => handle.DumpNANDSpare()?,
+        };
+        for (data, spare) in image.blocks() {
+            nand.write_all(data)?;
+            nand.write_all(spare)?;
+        }
+        plain = Some(image.into_parts().0);
+        report
+    } else {
+        match range {
+            Some((start, count)) => handle.DumpRangeTo(start, count, &mut nand)?,
+            None => handle.DumpNANDTo(&mut nand)?,
+        }
+    };
+
+    nand.flush()?;
+    drop(nand);
+
+    if let Some(path) = manifest {
+        let data = match plain {
+            Some(d) => d,
+            None => read(&output).with_context(|| format!("couldn't read {}", output.display()))?,
+        };
+        let file =
+            File::create(&path).with_context(|| format!("couldn't create {}", path.display()))?;
+        handle
+            .DumpManifest(&data, &report)?
+            .write_to(BufWriter::new(file))?;
+    }
+
+    if json {
+        println!(
+            "{}",
+            json!({
+                "start": report.start,
+                "blocks": report.blocks.len(),
+                "bad": report.bad().collect::<Vec<_>>(),
+                "zero_filled": report
+                    .zero_filled()
+                    .map(|(b, e)| json!({ "block": b, "error": e }))
+                    .collect::<Vec<_>>(),
+                "synthesised_spare": report.synthesised_spare,
+                "complete": report.is_complete(),
+                "nand_digests": report.nand_digests.map(digests_json),
+                "spare_digests": report.spare_digests.map(digests_json),
+            })
+        );
+        return Ok(());
+    }
+
+    for (label, digests) in [
+        ("nand", report.nand_digests),
+        ("spare", report.spare_digests),
+    ] {
+        if let Some(d) = digests {
+            println!("{label} crc32:  {:08x}", d.crc32);
+            println!("{label} sha1:   {}", to_hex(&d.sha1));
+            println!("{label} sha256: {}", to_hex(&d.sha256));
+        }
+    }
+
+    let bad = report.bad().count();
+    if bad > 0 {
+        eprintln!("{bad} bad blocks were dumped as they are");
+    }
+    for (block, error) in report.zero_filled() {
+        eprintln!("warning: block {block} couldn't be read ({error}); wrote zeroes instead");
+    }
+    if !report.synthesised_spare.is_empty() {
+        eprintln!(
+            "warning: couldn't read the spare for {} blocks; wrote blank spares instead",
+            report.synthesised_spare.len()
+        );
+    }
+
+    Ok(())
+}
+
+// a different SK means a different console family or a hand-built image; either way, ask for --force
+#[cfg(feature = "writing")]
+fn flash_sksa(
+    handle: &mut GlobalHandle,
+    input: PathBuf,
+    force: bool,
+    yes: bool,
+    json: bool,
+) -> Result<()> {
+    let sksa = read(&input).with_context(|| format!("couldn't read {}", input.display()))?;
+
+    let sk_size = bbrdb::SK_BLOCKS as usize * BLOCK_SIZE;
+    if sksa.len() <= sk_size {
+        bail!("{} is too small to be an SKSA", input.display());
+    }
+
+    let current = handle
+        .ReadSKSA()
+        .context("couldn't read the current SKSA")?;
+    let sk_replaced = current.sk != sksa[..sk_size];
+    if sk_replaced {
+        if !force {
+            bail!(
+                "the SK in {} doesn't match the one on the card; use --force to flash it anyway",
+                input.display()
+            );
+        }
+        eprintln!("warning: replacing the SK on the card");
+    }
+
+    if !yes && !confirm("A bad SKSA can stop the console booting. Continue?")? {
+        bail!("cancelled");
+    }
+
+    handle.WriteSKSA(&sksa)?;
+
+    if json {
+        println!(
+            "{}",
+            json!({ "input": input, "size": sksa.len(), "sk_replaced": sk_replaced })
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mismatch {
+    Data,
+    Spare,
+    Both,
+}
+
+fn verify(
+    handle: &GlobalHandle,
+    image: PathBuf,
+    spare_file: Option<PathBuf>,
+    progress: ProgressMode,
+    json: bool,
+) -> Result<()> {
+    let (data, format, spare) = load_nand_image(handle, &image, spare_file)
+        .with_context(|| format!("can't verify against {}", image.display()))?;
+    let card_blocks = data.len() / format.block_size();
+
+    let sink = progress.sink();
+    let progress = ProgressTask::begin(
+        sink.as_ref(),
+        "Verifying",
+        Some(card_blocks as u64),
+        ProgressUnit::Blocks,
+    );
+
+    let mut mismatches = vec![];
+    let mut unreadable = vec![];
+    for (i, chunk) in data.chunks(format.block_size()).enumerate() {
+        let expected_spare = match (format, &spare) {
+            (NandFormat::Interleaved, _) => Some(&chunk[BLOCK_SIZE..]),
+            (_, Some(s)) => Some(&s[i * SPARE_SIZE..(i + 1) * SPARE_SIZE]),
+            (_, None) => None,
+        };
+
+        let (card_data, card_spare) = match handle.ReadSingleBlock(i as u32) {
+            Ok(b) => {
+                let synthesised = b.spare_synthesised();
+                let (d, s) = b.into_parts();
+                (d, (!synthesised).then_some(s))
+            }
+            Err(e) => match e.into_root() {
+                LibBBRDBError::CardError(CardError::BadBlock(d, s)) => (d, Some(s)),
+                e => {
+                    unreadable.push((i, e.to_string()));
+                    progress.advance(1);
+                    continue;
+                }
+            },
+        };
+
+        let data_differs = card_data != chunk[..BLOCK_SIZE];
+        let spare_differs = match (expected_spare, &card_spare) {
+            (Some(e), Some(c)) => e != c.as_slice(),
+            _ => false,
+        };
+        match (data_differs, spare_differs) {
+            (true, true) => mismatches.push((i, Mismatch::Both)),
+            (true, false) => mismatches.push((i, Mismatch::Data)),
+            (false, true) => mismatches.push((i, Mismatch::Spare)),
+            (false, false) => {}
+        }
+
+        progress.advance(1);
+    }
+    drop(progress);
+
+    if json {
+        let out = json!({
+            "blocks": card_blocks,
+            "mismatches": mismatches
+                .iter()
+                .map(|(b, m)| json!({ "block": b, "differs": format!("{m:?}").to_lowercase() }))
+                .collect::<Vec<_>>(),
+            "unreadable": unreadable
+                .iter()
+                .map(|(b, e)| json!({ "block": b, "error": e }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{out}");
+    } else {
+        for (block, mismatch) in &mismatches {
+            let what = match mismatch {
+                Mismatch::Data => "data differs",
+                Mismatch::Spare => "spare differs",
+                Mismatch::Both => "data and spare differ",
+            };
+            println!("block {block}: {what}");
+        }
+        for (block, e) in &unreadable {
+            println!("block {block}: couldn't read it: {e}");
+        }
+    }
+
+    if !mismatches.is_empty() || !unreadable.is_empty() {
+        bail!(
+            "{} block(s) differ and {} couldn't be read",
+            mismatches.len(),
+            unreadable.len()
+        );
+    }
+    if !json {
+        println!("all {card_blocks} blocks match");
+    }
+
+    Ok(())
+}
+
+const MAP_WIDTH: usize = 64;
+
+fn badblocks(handle: &GlobalHandle, fat: bool, spare: bool, json: bool) -> Result<()> {
+    let comparison = handle.CompareBadBlocks(fat, spare)?;
+    let scan = &comparison.scan;
+    let disagreements = comparison.disagreements();
+
+    let bad = scan.indices().collect::<Vec<_>>();
+
+    if json {
+        let out = json!({
+            "blocks": scan.len(),
+            "bad": bad,
+            "disagreements": disagreements
+                .iter()
+                .map(|d| json!({
+                    "block": d.block,
+                    "scan": d.scan,
+                    "fat": d.fat,
+                    "spare": d.spare,
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{out}");
+        return Ok(());
+    }
+
+    let disagrees = |i: usize| disagreements.iter().any(|d| d.block as usize == i);
+
+    for (row, chunk) in scan.as_slice().chunks(MAP_WIDTH).enumerate() {
+        let line = chunk
+            .iter()
+            .enumerate()
+            .map(|(col, &b)| match (b, disagrees(row * MAP_WIDTH + col)) {
+                (_, true) => '?',
+                (true, false) => 'X',
+                (false, false) => '.',
+            })
+            .collect::<String>();
+        println!("{:04X}  {line}", row * MAP_WIDTH);
+    }
+    println!();
+
+    println!("{} of {} blocks bad", bad.len(), scan.len());
+    if !bad.is_empty() {
+        let list = bad.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+        println!("bad: {}", list.join(" "));
+    }
+
+    let says = |bad: bool| if bad { "bad" } else { "good" };
+    for d in &disagreements {
+        let mut sources = vec![format!("scan says {}", says(d.scan))];
+        if let Some(b) = d.fat {
+            sources.push(format!("FAT says {}", says(b)));
+        }
+        if let Some(b) = d.spare {
+            sources.push(format!("spare says {}", says(b)));
+        }
+        println!("block {}: {}", d.block, sources.join(", "));
+    }
+
+    Ok(())
+}
+
+fn export(
+    handle: &GlobalHandle,
+    output: PathBuf,
+    image: Option<PathBuf>,
+    spare_file: Option<PathBuf>,
+    json: bool,
+) -> Result<()> {
+    let export = match image {
+        Some(path) => {
+            let (data, format, spare) = load_nand_image(handle, &path, spare_file)
+                .with_context(|| format!("can't export {}", path.display()))?;
+            let image = match (format, spare) {
+                (NandFormat::Interleaved, _) => {
+                    let mut image = NandImage::with_capacity(data.len() / format.block_size());
+                    for chunk in data.chunks(format.block_size()) {
+                        let (d, s) = chunk.split_at(BLOCK_SIZE);
+                        image.push(BlockWithSpare::new(d.to_vec(), s.to_vec())?);
+                    }
+                    image
+                }
+                (NandFormat::Plain, Some(spare)) => NandImage::new(data, spare)?,
+                (NandFormat::Plain, None) => bail!(
+                    "{} has no spare data, which the SA chain needs; pass --spare-file",
+                    path.display()
+                ),
+            };
+            export_emulator_layout(&image, handle.card_layout(), &output)?
+        }
+        None => handle.ExportEmulatorLayout(&output)?,
+    };
+
+    if json {
+        let out = json!({
+            "output": output.display().to_string(),
+            "seqno": export.seqno,
+            "sa2": export.sa2,
+            "files": export
+                .files
+                .iter()
+                .map(|(name, size)| json!({ "name": name, "size": size }))
+                .collect::<Vec<_>>(),
+            "skipped": export.skipped,
+        });
+        println!("{out}");
+        return Ok(());
+    }
+
+    println!(
+        "exported {} files from FAT generation {} to {}",
+        export.files.len(),
+        export.seqno,
+        output.display()
+    );
+    for name in &export.skipped {
+        eprintln!("skipped {name}: its chain is broken or its name can't be used here");
+    }
+
+    Ok(())
+}
+
+// just the data, whichever format the image is in
+fn load_plain_nand(handle: &GlobalHandle, path: &Path) -> Result<Vec<u8>> {
+    let (image, format, _) = load_nand_image(handle, path, None)?;
+    Ok(match format {
+        NandFormat::Plain => image,
+        NandFormat::Interleaved => image
+            .chunks(format.block_size())
+            .flat_map(|c| &c[..BLOCK_SIZE])
+            .copied()
+            .collect(),
+    })
+}
+
+fn diff(handle: &GlobalHandle, old: PathBuf, new: Option<PathBuf>, json: bool) -> Result<()> {
+    let old_data = load_plain_nand(handle, &old)
+        .with_context(|| format!("can't compare {}", old.display()))?;
+
+    let diff = match &new {
+        Some(new) => {
+            let new_data = load_plain_nand(handle, new)
+                .with_context(|| format!("can't compare {}", new.display()))?;
+            diff_nand(handle.card_layout(), &old_data, &new_data)?
+        }
+        None => handle.DiffWithCard(&old_data)?,
+    };
+
+    if json {
+        let out = json!({
+            "regions": diff
+                .regions
+                .iter()
+                .map(|r| json!({
+                    "name": r.name,
+                    "start": r.blocks.start,
+                    "blocks": r.blocks.len(),
+                    "changed": r.changed,
+                }))
+                .collect::<Vec<_>>(),
+            "unreadable": diff.unreadable,
+        });
+        println!("{out}");
+        return Ok(());
+    }
+
+    for r in &diff.regions {
+        println!(
+            "{:<6} {:>5} of {:>5} blocks changed",
+            r.name,
+            r.changed.len(),
+            r.blocks.len()
+        );
+        if !r.changed.is_empty() {
+            let list = r.changed.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+            println!("       {}", list.join(" "));
+        }
+    }
+    if !diff.unreadable.is_empty() {
+        let list = diff
+            .unreadable
+            .iter()
+            .map(|b| b.to_string())
+            .collect::<Vec<_>>();
+        println!("unreadable: {}", list.join(" "));
+    }
+
+    Ok(())
+}
+
+fn entry_json(entry: FATEntry) -> serde_json::Value {
+    match entry {
+        FATEntry::Free => json!("free"),
+        FATEntry::EndOfChain => json!("end"),
+        FATEntry::BadBlock => json!("bad"),
+        FATEntry::Reserved => json!("reserved"),
+        FATEntry::Chain(n) => json!(n),
+    }
+}
+
+fn issue_json(issue: &FsIssue) -> serde_json::Value {
+    match issue {
+        FsIssue::DanglingChain { file, block } => {
+            json!({ "kind": "dangling_chain", "file": file, "block": block })
+        }
+        FsIssue::Loop { file, block } => json!({ "kind": "loop", "file": file, "block": block }),
+        FsIssue::CrossLink { file, other, block } => {
+            json!({ "kind": "cross_link", "file": file, "other": other, "block": block })
+        }
+        FsIssue::InvalidEntry { file, block, entry } => json!({
+            "kind": "invalid_entry",
+            "file": file,
+            "block": block,
+            "entry": entry_json(*entry),
+        }),
+        FsIssue::SizeMismatch {
+            file,
+            blocks,
+            expected,
+        } => json!({
+            "kind": "size_mismatch",
+            "file": file,
+            "blocks": blocks,
+            "expected": expected,
+        }),
+    }
+}
+
+fn content_status_json(status: &ContentStatus) -> serde_json::Value {
+    match status {
+        ContentStatus::Ok => json!({ "kind": "ok" }),
+        ContentStatus::Unverified => json!({ "kind": "unverified" }),
+        ContentStatus::NoTicket => json!({ "kind": "no_ticket" }),
+        ContentStatus::Unreadable(e) => json!({ "kind": "unreadable", "error": e }),
+        ContentStatus::Truncated { expected, actual } => {
+            json!({ "kind": "truncated", "expected": expected, "actual": actual })
+        }
+        ContentStatus::HashMismatch => json!({ "kind": "hash_mismatch" }),
+    }
+}
+
+fn sa_json(sa: &SaVersion) -> serde_json::Value {
+    json!({
+        "content_id": sa.head.content_id,
+        "size": sa.head.size,
+        "issuer": sa.head.issuer,
+        "ca_crl_version": sa.head.ca_crl_version,
+        "cp_crl_version": sa.head.cp_crl_version,
+        "sha256": to_hex(&sa.sha256),
+    })
+}
+
+fn sksa_info(handle: &GlobalHandle, against: Option<PathBuf>, json: bool) -> Result<()> {
+    let version = handle.IdentifySKSA(&[])?;
+
+    let changes = match &against {
+        Some(path) => {
+            let sksa = read(path).with_context(|| format!("couldn't read {}", path.display()))?;
+            let other = SksaVersion::from_sksa(&sksa, &[])
+                .with_context(|| format!("can't identify {}", path.display()))?;
+            Some(version.differences(&other))
+        }
+        None => None,
+    };
+
+    if json {
+        let out = json!({
+            "sk_sha256": to_hex(&version.sk_sha256),
+            "sa1": sa_json(&version.sa1),
+            "sa2": version.sa2.as_ref().map(sa_json),
+            "changes": changes,
+        });
+        println!("{out}");
+        return Ok(());
+    }
+
+    println!("sk   sha256 {}", to_hex(&version.sk_sha256));
+    for (name, sa) in [("sa1", Some(&version.sa1)), ("sa2", version.sa2.as_ref())] {
+        match sa {
+            Some(sa) => println!(
+                "{name}  content {:08X}, {:#X} bytes, CRLs {}/{}, issuer {}",
+                sa.head.content_id,
+                sa.head.size,
+                sa.head.ca_crl_version,
+                sa.head.cp_crl_version,
+                sa.head.issuer
+            ),
+            None => println!("{name}  none"),
+        }
+    }
+
+    match changes {
+        Some(c) if c.is_empty() => println!("flashing it would change nothing"),
+        Some(c) => println!("flashing it would replace: {}", c.join(", ")),
+        None => {}
+    }
+
+    Ok(())
+}
+
+// runs until it's interrupted; the console keeps running homebrew, so there's no card to Init
+fn monitor(
+    strategy: DeviceStrategy,
+    log: Option<PathBuf>,
+    no_timestamps: bool,
+    json: bool,
+) -> Result<()> {
+    let handle = open_device(strategy)?;
+
+    let mut log = log
+        .map(|path| {
+            File::options()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("couldn't open {}", path.display()))
+        })
+        .transpose()?;
+
+    let mut output = handle.subscribe_console_output();
+    loop {
+        let message = match output.poll() {
+            Ok(Some(m)) => m,
+            Ok(None) => continue,
+            Err(LibBBRDBError::ConsoleResynced) => {
+                eprintln!("(console resynced)");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = Local::now();
+        let line = if json {
+            let (kind, text) = match &message {
+                ConsoleMessage::Print(s) => ("print", s.clone()),
+                ConsoleMessage::Log(data) => ("log", to_hex(data)),
+                ConsoleMessage::Fault(f) => ("fault", f.to_string()),
+            };
+            json!({ "time": now.to_rfc3339(), "kind": kind, "text": text }).to_string()
+        } else {
+            let text = match &message {
+                ConsoleMessage::Print(s) => s.clone(),
+                ConsoleMessage::Log(data) => format!("[log] {}", to_hex(data)),
+                ConsoleMessage::Fault(f) => format!("[fault]\n{f}"),
+            };
+            if no_timestamps {
+                text
+            } else {
+                format!("{} {text}", now.format("%H:%M:%S%.3f"))
+            }
+        };
+
+        println!("{line}");
+        if let Some(log) = &mut log {
+            writeln!(log, "{line}")?;
+        }
+    }
+}
+
+// a line of a script is parsed like the command line, minus the program name and global flags
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct ScriptLine {
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+fn run_script(
+    handle: &mut GlobalHandle,
+    script: PathBuf,
+    keep_going: bool,
+    progress: ProgressMode,
+    json: bool,
+) -> Result<()> {
+    let text = if script.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(&script)
+            .with_context(|| format!("couldn't read {}", script.display()))?
+    };
+
+    let mut failures = 0;
+    for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let rv = ScriptLine::try_parse_from(line.split_whitespace())
+            .map_err(anyhow::Error::from)
+            .and_then(|l| match l.command {
+                Cmd::Run { .. } | Cmd::Monitor { .. } => {
+                    bail!("{line:?} can't be used in a script")
+                }
+                command => execute(handle, command, progress, json),
+            })
+            .with_context(|| format!("line {number}"));
+
+        if let Err(e) = rv {
+            if !keep_going {
+                return Err(e);
+            }
+            report_error(&e, json);
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        bail!("{failures} line(s) failed");
+    }
+
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let json = cli.json;
+    let strategy = cli.device_strategy();
+    let progress = cli.progress_mode();
+
+    if let Cmd::Monitor { log, no_timestamps } = cli.command {
+        return monitor(strategy, log, no_timestamps, json);
+    }
+
+    // a console that needs resetting may not get through Init first
+    let mut handle = if let Cmd::Reset = cli.command {
+        open_device(strategy)?
+    } else {
+        open(strategy)?
+    };
+    progress.install(&mut handle);
+    handle.set_read_only(cli.read_only);
+    handle.set_verify_reads(cli.verify_reads);
+    handle.set_power_off_on_close(cli.power_off);
+
+    match cli.command {
+        Cmd::Run { script, keep_going } => {
+            run_script(&mut handle, script, keep_going, progress, json)
+        }
+        command => execute(&mut handle, command, progress, json),
+    }
+}
+
+fn execute(
+    handle: &mut GlobalHandle,
+    command: Cmd,
+    progress: ProgressMode,
+    json: bool,
+) -> Result<()> {
+    match command {
+        Cmd::Ls {
+            export: Some(format),
+        } => {
+            let listing = handle.ExportListing(format.into())?;
+            print!("{listing}");
+            if !listing.ends_with('\n') {
+                println!();
+            }
+        }
+
+        Cmd::Ls { export: None } => {
+            let files = handle.ListFiles()?;
+            if json {
+                let files = files
+                    .iter()
+                    .map(|(name, size)| {
+                        json!({
+                            "name": name,
+                            "category": FileCategory::of(name).name(),
+                            "size": size,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!(files));
+            } else {
+                for (name, size) in files {
+                    println!("{size:>10}  {name}");
+                }
+            }
+        }
+
+        Cmd::Get { name, output } => {
+            let data = handle
+                .ReadFile(&name)?
+                .with_context(|| format!("{name} isn't on the card"))?;
+            let output = output.unwrap_or_else(|| name.clone().into());
+            write(&output, &data)
+                .with_context(|| format!("couldn't write {}", output.display()))?;
+            if json {
+                println!(
+                    "{}",
+                    json!({ "name": name, "output": output, "size": data.len() })
+                );
+            }
+        }
+
+        Cmd::Cmp { name, local } => {
+            let local = local.unwrap_or_else(|| name.clone().into());
+            let data =
+                read(&local).with_context(|| format!("couldn't read {}", local.display()))?;
+            let size = u32::try_from(data.len()).context("the local file is too large")?;
+            let matches = handle.VerifyFileChecksum(&name, file_checksum(&data), size)?;
+            if json {
+                println!(
+                    "{}",
+                    json!({ "name": name, "local": local, "matches": matches })
+                );
+            } else if matches {
+                println!("{name} matches {}", local.display());
+            }
+            if !matches {
+                bail!("{name} differs from {}", local.display());
+            }
+        }
+
+        Cmd::Compare { dir } => {
+            let cmp = handle.CompareWithLocal(&dir)?;
+            if json {
+                println!(
+                    "{}",
+                    json!({
+                        "same": cmp.same,
+                        "differ": cmp.differ,
+                        "missing": cmp.missing,
+                        "extra": cmp.extra,
+                    })
+                );
+            } else {
+                for name in &cmp.differ {
+                    println!("differs   {name}");
+                }
+                for name in &cmp.missing {
+                    println!("missing   {name}");
+                }
+                for name in &cmp.extra {
+                    println!("extra     {name}");
+                }
+                println!("{} file(s) match", cmp.same.len());
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        Cmd::Put { input, name } => {
+            let name = match name {
+                Some(n) => n,
+                None => input
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .context("can't work out a name for the file on the card")?
+                    .to_string(),
+            };
+            let data =
+                read(&input).with_context(|| format!("couldn't read {}", input.display()))?;
+            handle.WriteFile(&data, &name)?;
+            if json {
+                println!("{}", json!({ "name": name, "size": data.len() }));
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        Cmd::Rm { name } => {
+            handle.DeleteFile(&name)?;
+            if json {
+                println!("{}", json!({ "deleted": name }));
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        Cmd::Mv { from, to } => {
+            handle.RenameFile(&from, &to)?;
+            if json {
+                println!("{}", json!({ "from": from, "to": to }));
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        Cmd::Wipe { yes } => {
+            if !yes
+                && !confirm(
+                    "This deletes all saves, user files and games without a ticket. Continue?",
+                )?
+            {
+                bail!("cancelled");
+            }
+            let deleted = handle.WipeUserData()?;
+            if json {
+                println!("{}", json!({ "deleted": deleted }));
+            } else {
+                for name in deleted {
+                    println!("deleted {name}");
+                }
+            }
+        }
+
+        Cmd::Fats => {
+            let generations = handle.ReadAllFats()?;
+            if json {
+                let generations = generations
+                    .iter()
+                    .map(|g| {
+                        json!({
+                            "seqno": g.seqno,
+                            "slot": g.slot,
+                            "locations": g.locations,
+                            "current": g.current,
+                            "files": g.listing.files.len(),
+                            "free": g.stats.free,
+                            "used": g.stats.used,
+                            "bad": g.stats.bad,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!(generations));
+            } else {
+                for g in generations {
+                    let current = if g.current { "  (current)" } else { "" };
+                    println!(
+                        "seqno {:>6}  slot {:>2}  {:>3} files, {} free, {} used, {} bad{current}",
+                        g.seqno,
+                        g.slot,
+                        g.listing.files.len(),
+                        g.stats.free,
+                        g.stats.used,
+                        g.stats.bad
+                    );
+                }
+            }
+        }
+
+        Cmd::Deleted => {
+            let files = handle.ListDeletedFiles()?;
+            if json {
+                let files = files
+                    .iter()
+                    .map(|f| {
+                        json!({
+                            "name": f.name,
+                            "size": f.size,
+                            "seqno": f.seqno,
+                            "blocks": f.blocks,
+                            "recoverable": f.recoverable,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!(files));
+            } else {
+                for f in files {
+                    let state = if f.recoverable {
+                        ""
+                    } else {
+                        "  (not recoverable)"
+                    };
+                    println!("{:>10}  {:>6}  {}{state}", f.size, f.seqno, f.name);
+                }
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        Cmd::Undelete { name, seqno } => {
+            let seqno = match seqno {
+                Some(s) => s,
+                None => handle
+                    .ListDeletedFiles()?
+                    .into_iter()
+                    .find(|f| f.name == name && f.recoverable)
+                    .map(|f| f.seqno)
+                    .with_context(|| format!("no recoverable copy of {name} was found"))?,
+            };
+            handle.RecoverFile(&name, seqno)?;
+            if json {
+                println!("{}", json!({ "name": name, "seqno": seqno }));
+            }
+        }
+
+        Cmd::Nand { action } => match action {
+            NandCmd::Dump {
+                output,
+                spare,
+                spare_file,
+                start,
+                count,
+                hash,
+                manifest,
+            } => {
+                handle.set_dump_digests(hash || manifest.is_some());
+                let range = match (start, count) {
+                    (None, None) => None,
+                    (start, count) => {
+                        let start = start.unwrap_or_default();
+                        let card_blocks = handle.card_size().unwrap_or_default();
+                        Some((start, count.unwrap_or(card_blocks.saturating_sub(start))))
+                    }
+                };
+                dump(handle, output, spare, spare_file, range, manifest, json)?
+            }
+            NandCmd::Restore {
+                input,
+                spare_file,
+                keep_going,
+                yes,
+            } => restore(handle, input, spare_file, keep_going, yes, json)?,
+
+            NandCmd::Export {
+                output,
+                image,
+                spare_file,
+            } => export(handle, output, image, spare_file, json)?,
+
+            NandCmd::Diff { old, new } => diff(handle, old, new, json)?,
+        },
+
+        Cmd::Stats { verbose } => {
+            let report = handle.FsReport()?;
+            let stats = report.stats;
+
+            if json {
+                let mut out = json!({
+                    "free": stats.free,
+                    "used": stats.used,
+                    "bad": stats.bad,
+                    "free_bytes": stats.free_bytes(),
+                    "used_bytes": stats.used_bytes(),
+                    "bad_bytes": stats.bad_bytes(),
+                    "total_bytes": stats.total_bytes(),
+                });
+                if verbose {
+                    let detailed = handle.DetailedCardStats()?;
+                    out["sksa_area"] = json!(detailed.sksa_area);
+                    out["sksa_reserved"] = json!(detailed.sksa_reserved);
+                    out["fat_slots"] = json!(detailed.fat_slots);
+                    out["fat_slots_readable"] = json!(detailed.fat_slots_readable);
+                    let frag = handle.FragmentationStats()?;
+                    out["fragmentation"] = json!(frag.score);
+                    out["fragmented_files"] = json!(frag
+                        .fragmented()
+                        .map(|f| json!({ "name": f.name, "fragments": f.fragments }))
+                        .collect::<Vec<_>>());
+                    out["seqno"] = json!(stats.seqno);
+                    out["fat_block"] = json!(report.fat_block);
+                    out["reserved"] = json!(report.reserved);
+                    out["file_slots_used"] = json!(report.file_slots_used);
+                    out["file_slots_free"] = json!(report.file_slots_free);
+                }
+                println!("{out}");
+                return Ok(());
+            }
+
+            let kib = |b: u64| b / 1024;
+            println!(
+                "free: {} blocks ({} KiB)",
+                stats.free,
+                kib(stats.free_bytes())
+            );
+            println!(
+                "used: {} blocks ({} KiB)",
+                stats.used,
+                kib(stats.used_bytes())
+            );
+            println!(
+                "bad:  {} blocks ({} KiB)",
+                stats.bad,
+                kib(stats.bad_bytes())
+            );
+
+            if verbose {
+                println!("seqno:      {}", stats.seqno);
+                println!("FAT block:  {:#06X}", report.fat_block);
+                println!("reserved:   {} blocks", report.reserved);
+
+                let detailed = handle.DetailedCardStats()?;
+                println!(
+                    "SKSA area:  {} of {} blocks reserved",
+                    detailed.sksa_reserved, detailed.sksa_area
+                );
+                println!(
+                    "FAT slots:  {} of {} readable",
+                    detailed.fat_slots_readable.unwrap_or_default(),
+                    detailed.fat_slots
+                );
+                println!(
+                    "file slots: {} used, {} free",
+                    report.file_slots_used, report.file_slots_free
+                );
+
+                let frag = handle.FragmentationStats()?;
+                println!("fragmentation: {:.1}%", frag.score * 100.0);
+                for f in frag.fragmented() {
+                    println!("  {:>4} pieces  {}", f.fragments, f.name);
+                }
+            }
+        }
+
+        Cmd::Check => {
+            let issues = handle.CheckFS()?;
+            if json {
+                let list = issues.iter().map(issue_json).collect::<Vec<_>>();
+                println!("{}", json!({ "issues": list }));
+            } else if issues.is_empty() {
+                println!("no problems found");
+            } else {
+                for issue in &issues {
+                    println!("{issue:?}");
+                }
+            }
+            if !issues.is_empty() {
+                bail!("{} problem(s) found", issues.len());
+            }
+        }
+
+        #[cfg(feature = "tui")]
+        Cmd::Browse => browse::browse(handle)?,
+
+        Cmd::Run { .. } | Cmd::Monitor { .. } => {
+            unreachable!("handled before the card is opened")
+        }
+
+        Cmd::Reset => {
+            handle.reset()?;
+            if json {
+                println!("{}", json!({ "card": handle.initialised() }));
+            } else if handle.initialised() {
+                println!("console reset");
+            } else {
+                println!("console reset; no card inserted");
+            }
+        }
+
+        Cmd::Backup { output } => {
+            let backup = handle.Backup()?;
+            let file = File::create(&output)
+                .with_context(|| format!("couldn't create {}", output.display()))?;
+            backup.write_to(BufWriter::new(file))?;
+
+            if json {
+                println!(
+                    "{}",
+                    json!({
+                        "output": output,
+                        "bbid": format!("{:08X}", backup.bbid),
+                        "created": backup.created.to_rfc3339(),
+                        "files": backup.files.len(),
+                        "blocks": backup.image.num_blocks(),
+                    })
+                );
+            } else {
+                println!(
+                    "backed up {} files from {:08X} to {}",
+                    backup.files.len(),
+                    backup.bbid,
+                    output.display()
+                );
+            }
+        }
+
+        Cmd::Restore {
+            input,
+            force,
+            keep_going,
+            yes,
+        } => {
+            let file =
+                File::open(&input).with_context(|| format!("couldn't open {}", input.display()))?;
+            let backup = Backup::read_from(BufReader::new(file))
+                .with_context(|| format!("can't restore {}", input.display()))?;
+
+            let bbid = handle.GetBBID()?;
+            if backup.bbid != bbid && !force {
+                bail!(
+                    "the backup is from {:08X} but this console is {bbid:08X}; use --force to restore it anyway",
+                    backup.bbid
+                );
+            }
+
+            if !yes && !confirm("This overwrites the entire card. Continue?")? {
+                bail!("cancelled");
+            }
+
+            let report = handle.RestoreBackup(&backup, !keep_going)?;
+            check_write_report(&report, backup.image.num_blocks(), json)?;
+        }
+
+        Cmd::Verify { image, spare_file } => verify(handle, image, spare_file, progress, json)?,
+
+        Cmd::Badblocks { fat, spare } => badblocks(handle, fat, spare, json)?,
+
+        Cmd::Bench {
+            blocks,
+            depths,
+            write,
+        } => {
+            let options = BenchOptions {
+                blocks,
+                queue_depths: depths,
+                write,
+            };
+
+            for r in handle.benchmark(&options)? {
+                if json {
+                    let out = json!({
+                        "op": format!("{:?}", r.op),
+                        "queue_depth": r.queue_depth,
+                        "chunk_size": r.chunk_size(),
+                        "blocks": r.blocks,
+                        "seconds": r.elapsed.as_secs_f64(),
+                        "bytes_per_second": r.throughput(),
+                    });
+                    println!("{out}");
+                } else {
+                    println!(
+                        "{:<5} depth {:>2} ({:>3} KiB): {:>5} blocks in {:>7.2?}, {:>7.1} KiB/s, {:.1} blocks/s",
+                        format!("{:?}", r.op),
+                        r.queue_depth,
+                        r.chunk_size() / 1024,
+                        r.blocks,
+                        r.elapsed,
+                        r.throughput() / 1024.0,
+                        r.blocks_per_second()
+                    );
+                }
+            }
+        }
+
+        Cmd::Bbid => {
+            let bbid = handle.GetBBID()?;
+            if json {
+                println!("{}", json!({ "bbid": format!("{bbid:08X}") }));
+            } else {
+                println!("{bbid:08X}");
+            }
+        }
+
+        Cmd::Info => {
+            let info = handle.ConsoleInfo()?;
+
+            if json {
+                let out = json!({
+                    "bbid": format!("{:08X}", info.bbid),
+                    "device_type": format!("{:?}", info.device_type),
+                    "card_blocks": info.card_blocks,
+                    "free": info.stats.map(|s| s.free),
+                    "used": info.stats.map(|s| s.used),
+                    "bad": info.stats.map(|s| s.bad),
+                    "seqno": info.stats.map(|s| s.seqno),
+                });
+                println!("{out}");
+            } else {
+                println!("BBID:   {:08X}", info.bbid);
+                println!("type:   {:?}", info.device_type);
+                println!(
+                    "card:   {} blocks ({} MiB)",
+                    info.card_blocks,
+                    info.card_blocks as usize * BLOCK_SIZE / (1024 * 1024)
+                );
+                match info.stats {
+                    Some(s) => {
+                        println!("free:   {} blocks", s.free);
+                        println!("used:   {} blocks", s.used);
+                        println!("bad:    {} blocks", s.bad);
+                        println!("seqno:  {}", s.seqno);
+                    }
+                    None => println!("no valid FAT on the card"),
+                }
+            }
+        }
+
+        Cmd::SetTime => {
+            let time = handle.SetTime(Local::now())?;
+            let set = format!(
+                "20{:02}-{:02}-{:02} {:02}:{:02}:{:02}",
+                time.year, time.month, time.day, time.hour, time.minute, time.second
+            );
+            if json {
+                println!("{}", json!({ "time": set, "weekday": time.weekday }));
+            } else {
+                println!("set to {set}");
+            }
+        }
+
+        Cmd::Saves { action } => match action {
+            SavesCmd::List => {
+                let titles = handle.ListSaves()?;
+                if json {
+                    let titles = titles
+                        .iter()
+                        .map(|t| {
+                            json!({
+                                "content_id": format!("{:08x}", t.content_id),
+                                "installed": t.installed,
+                                "files": t
+                                    .files
+                                    .iter()
+                                    .map(|(name, size)| json!({ "name": name, "size": size }))
+                                    .collect::<Vec<_>>(),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    println!("{}", json!(titles));
+                } else {
+                    for t in titles {
+                        let state = if t.installed { "" } else { "  (not installed)" };
+                        println!("{:08x}{state}", t.content_id);
+                        for (name, size) in t.files {
+                            println!("  {size:>10}  {name}");
+                        }
+                    }
+                }
+            }
+            SavesCmd::Export { dir, title } => {
+                let exported = handle.ExportSaves(&dir, title)?;
+                if json {
+                    println!("{}", json!({ "exported": exported }));
+                } else {
+                    for name in exported {
+                        println!("exported {name}");
+                    }
+                }
+            }
+            #[cfg(feature = "writing")]
+            SavesCmd::Import { dir, title } => {
+                let imported = handle.ImportSaves(&dir, title)?;
+                if json {
+                    println!("{}", json!({ "imported": imported }));
+                } else {
+                    for name in imported {
+                        println!("imported {name}");
+                    }
+                }
+            }
+        },
+
+        Cmd::Map => {
+            let map = handle.CardMap()?;
+            if json {
+                let blocks = map
+                    .blocks
+                    .iter()
+                    .map(|u| match u {
+                        BlockUse::Free => json!("free"),
+                        BlockUse::Reserved => json!("reserved"),
+                        BlockUse::Bad => json!("bad"),
+                        BlockUse::Orphan => json!("orphan"),
+                        BlockUse::File(slot) => json!(slot),
+                    })
+                    .collect::<Vec<_>>();
+                let files = map
+                    .files
+                    .iter()
+                    .map(|f| json!({ "slot": f.slot, "name": f.name, "blocks": f.blocks }))
+                    .collect::<Vec<_>>();
+                println!("{}", json!({ "blocks": blocks, "files": files }));
+            } else {
+                for (row, chunk) in map.blocks.chunks(64).enumerate() {
+                    let line = chunk
+                        .iter()
+                        .map(|u| match u {
+                            BlockUse::Free => '.',
+                            BlockUse::Reserved => 'R',
+                            BlockUse::Bad => 'X',
+                            BlockUse::Orphan => '?',
+                            BlockUse::File(_) => '#',
+                        })
+                        .collect::<String>();
+                    println!("{:5}  {line}", row * 64);
+                }
+                println!(
+                    "{} free, {} in files, {} orphaned, {} bad, {} reserved",
+                    map.count(BlockUse::Free),
+                    map.blocks
+                        .iter()
+                        .filter(|u| matches!(u, BlockUse::File(_)))
+                        .count(),
+                    map.count(BlockUse::Orphan),
+                    map.count(BlockUse::Bad),
+                    map.count(BlockUse::Reserved),
+                );
+            }
+        }
+
+        Cmd::Tickets => {
+            let tickets = handle
+                .ReadTickets()?
+                .context("the card has no ticket.sys")?
+                .tickets;
+            if json {
+                let tickets = tickets
+                    .iter()
+                    .map(|t| {
+                        json!({
+                            "content_id": format!("{:08x}", t.content_id()),
+                            "bbid": format!("{:08X}", t.bbid),
+                            "tid": t.tid,
+                            "code": t.code,
+                            "limit": t.limit,
+                            "size": t.head.size,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!(tickets));
+            } else {
+                for t in tickets {
+                    let limit = match t.code {
+                        0 => "permanent".to_string(),
+                        code => format!("limit {} (code {code})", t.limit),
+                    };
+                    println!(
+                        "{:08x}  tid {:04X}  BBID {:08X}  {:#X} bytes  {limit}",
+                        t.content_id(),
+                        t.tid,
+                        t.bbid,
+                        t.head.size
+                    );
+                }
+            }
+        }
+
+        Cmd::Crls => {
+            let crls = handle.ReadCrls()?.unwrap_or_default();
+            if json {
+                let crls = crls
+                    .iter()
+                    .map(|c| {
+                        json!({
+                            "kind": c.kind,
+                            "version": c.version,
+                            "date": c.date,
+                            "issuer": c.issuer,
+                            "revoked": c.revoked,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!(crls));
+            } else if crls.is_empty() {
+                println!("no CRLs");
+            } else {
+                for c in crls {
+                    println!(
+                        "{}  version {}  type {}  {} revoked",
+                        c.issuer,
+                        c.version,
+                        c.kind,
+                        c.revoked.len()
+                    );
+                    for name in c.revoked {
+                        println!("  {name}");
+                    }
+                }
+            }
+        }
+
+        Cmd::CheckContent => {
+            let checks = handle.VerifyContent(None)?;
+            if json {
+                let checks = checks
+                    .iter()
+                    .map(|c| {
+                        json!({
+                            "content_id": format!("{:08x}", c.content_id),
+                            "name": c.name,
+                            "status": content_status_json(&c.status),
+                            "corrupt": c.status.is_corrupt(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", json!(checks));
+            } else {
+                for c in &checks {
+                    let status = match &c.status {
+                        ContentStatus::Ok => "ok".to_string(),
+                        ContentStatus::Unverified => "ok (hash not checked)".to_string(),
+                        ContentStatus::NoTicket => "no ticket".to_string(),
+                        ContentStatus::Unreadable(e) => format!("unreadable: {e}"),
+                        ContentStatus::Truncated { expected, actual } => {
+                            format!("truncated: {actual:#X} of {expected:#X} bytes")
+                        }
+                        ContentStatus::HashMismatch => "hash mismatch".to_string(),
+                    };
+                    println!("{}  {status}", c.name);
+                }
+            }
+
+            let corrupt = checks.iter().filter(|c| c.status.is_corrupt()).count();
+            if corrupt > 0 {
+                bail!("{corrupt} content file(s) are corrupt");
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        Cmd::Uninstall {
+            content_id,
+            saves,
+            dry_run,
+        } => {
+            let plan = if dry_run {
+                handle.PlanUninstall(content_id, saves)?
+            } else {
+                handle.UninstallTitle(content_id, saves)?
+            };
+
+            if json {
+                println!(
+                    "{}",
+                    json!({
+                        "content_id": format!("{:08x}", plan.content_id),
+                        "dry_run": dry_run,
+                        "files": plan.files,
+                        "tickets": plan.tickets,
+                        "kept_saves": plan.kept_saves,
+                    })
+                );
+            } else {
+                let verb = if dry_run { "would remove" } else { "removed" };
+                for name in &plan.files {
+                    println!("{verb} {name}");
+                }
+                println!("{verb} {} ticket(s)", plan.tickets);
+                for name in &plan.kept_saves {
+                    println!("kept {name}");
+                }
+            }
+        }
+
+        Cmd::Sksa { action } => match action {
+            SksaCmd::Dump {
+                output,
+                split: true,
+                trim,
+            } => {
+                if trim {
+                    handle.ReadSplitSKSA()?.write_to_dir(&output)?;
+                } else {
+                    handle.DumpSKSAFiles(&output)?;
+                }
+                if json {
+                    println!(
+                        "{}",
+                        json!({ "output": output, "split": true, "trimmed": trim })
+                    );
+                }
+            }
+            SksaCmd::Dump {
+                output,
+                split: false,
+                ..
+            } => {
+                let sksa = handle.ReadSKSA()?;
+                let bytes = sksa.to_bytes();
+                write(&output, &bytes)
+                    .with_context(|| format!("couldn't write {}", output.display()))?;
+                if json {
+                    println!(
+                        "{}",
+                        json!({
+                            "output": output,
+                            "split": false,
+                            "size": bytes.len(),
+                            "sa2": sksa.sa2.is_some(),
+                            "bad_blocks_skipped": sksa.bad_blocks_skipped,
+                        })
+                    );
+                } else if !sksa.bad_blocks_skipped.is_empty() {
+                    let list = sksa
+                        .bad_blocks_skipped
+                        .iter()
+                        .map(|b| b.to_string())
+                        .collect::<Vec<_>>();
+                    eprintln!("the SAs skip over blocks {}", list.join(" "));
+                }
+            }
+            SksaCmd::Info { against } => sksa_info(handle, against, json)?,
+            #[cfg(feature = "writing")]
+            SksaCmd::Flash { input, force, yes } => flash_sksa(handle, input, force, yes, json)?,
+        },
+    }
+
+    Ok(())
+}
+
+// library errors exit with their ErrorKind number; anything else is a plain failure
+fn report_error(e: &anyhow::Error, json: bool) -> ExitCode {
+    let error = e.chain().find_map(|c| c.downcast_ref::<LibBBRDBError>());
+
+    if json {
+        eprintln!(
+            "{}",
+            json!({
+                "event": "error",
+                "code": error.map(LibBBRDBError::code),
+                "kind": error.map(|e| format!("{:?}", e.kind())),
+                "message": format!("{e:#}"),
+            })
+        );
+    } else {
+        eprintln!("Error: {e:#}");
+    }
+
+    match error {
+        Some(e) => ExitCode::from(e.kind().number()),
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(&e, json),
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::Write;
+use std::ops::Range;
+
+use chrono::{DateTime, Utc};
+use rusb::UsbContext;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::audit::to_hex;
+use crate::error::*;
+use crate::nand::{BlockHash, DumpDigests, DumpReport};
+use crate::Handle;
+
+const MANIFEST_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpRegion {
+    pub name: &'static str,
+    // card blocks, clipped to what the dump covers
+    pub blocks: Range<u32>,
+    pub sha256: BlockHash,
+}
+
+// what an archivist would otherwise write down by hand next to a dump
+#[derive(Debug, Clone)]
+pub struct DumpManifest {
+    pub bbid: u32,
+    pub created: DateTime<Utc>,
+    pub card_blocks: u32,
+    pub start: u32,
+    pub blocks: u32,
+    pub bad_blocks: Vec<u32>,
+    pub zero_filled: Vec<u32>,
+    pub synthesised_spare: Vec<u32>,
+    pub regions: Vec<DumpRegion>,
+    pub nand_digests: Option<DumpDigests>,
+    pub spare_digests: Option<DumpDigests>,
+    pub tool_version: &'static str,
+}
+
+fn digests_json(d: &DumpDigests) -> Value {
+    json!({
+        "crc32": format!("{:08x}", d.crc32),
+        "sha1": to_hex(&d.sha1),
+        "sha256": to_hex(&d.sha256),
+    })
+}
+
+impl DumpManifest {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": MANIFEST_VERSION,
+            "tool": format!("bbrdb {}", self.tool_version),
+            "created": self.created.to_rfc3339(),
+            "bbid": format!("{:08X}", self.bbid),
+            "card_blocks": self.card_blocks,
+            "start": self.start,
+            "blocks": self.blocks,
+            "bad_blocks": self.bad_blocks,
+            "zero_filled": self.zero_filled,
+            "synthesised_spare": self.synthesised_spare,
+            "regions": self
+                .regions
+                .iter()
+                .map(|r| json!({
+                    "name": r.name,
+                    "start": r.blocks.start,
+                    "blocks": r.blocks.len(),
+                    "sha256": to_hex(&r.sha256),
+                }))
+                .collect::<Vec<_>>(),
+            "nand_digests": self.nand_digests.as_ref().map(digests_json),
+            "spare_digests": self.spare_digests.as_ref().map(digests_json),
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(&mut writer, &self.to_json()).map_err(std::io::Error::from)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // the card's regions as the layout sees them, clipped to `blocks`
+    fn card_regions(&self, cardsize: u32, blocks: Range<u32>) -> Vec<(&'static str, Range<u32>)> {
+        self.layout
+            .regions(cardsize)
+            .into_iter()
+            .map(|(name, r)| (name, r.start.max(blocks.start)..r.end.min(blocks.end)))
+            .filter(|(_, r)| !r.is_empty())
+            .collect()
+    }
+
+    // `nand` is the data a dump returned along with `report`, without any spare
+    #[allow(non_snake_case)]
+    pub fn DumpManifest(&self, nand: &[u8], report: &DumpReport) -> Result<DumpManifest> {
+        let cardsize = self.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+        let block_size = self.layout.block_size;
+
+        let count = report.blocks.len() as u32;
+        if nand.len() != count as usize * block_size {
+            return Err(LibBBRDBError::WrongDataLength);
+        }
+
+        let regions = self
+            .card_regions(cardsize, report.start..report.start + count)
+            .into_iter()
+            .map(|(name, blocks)| {
+                let offset = |b: u32| (b - report.start) as usize * block_size;
+                DumpRegion {
+                    name,
+                    sha256: Sha256::digest(&nand[offset(blocks.start)..offset(blocks.end)]).into(),
+                    blocks,
+                }
+            })
+            .collect();
+
+        Ok(DumpManifest {
+            bbid: self.GetBBID()?,
+            created: Utc::now(),
+            card_blocks: cardsize,
+            start: report.start,
+            blocks: count,
+            bad_blocks: report.bad().collect(),
+            zero_filled: report.zero_filled().map(|(b, _)| b).collect(),
+            synthesised_spare: report.synthesised_spare.clone(),
+            regions,
+            nand_digests: report.nand_digests,
+            spare_digests: report.spare_digests,
+            tool_version: env!("CARGO_PKG_VERSION"),
+        })
+    }
+}
@@ -0,0 +1,285 @@
+use std::io::Write;
+
+use crc32fast::Hasher as Crc32;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::constants::{BLOCK_SIZE, SPARE_SIZE};
+use crate::error::*;
+use crate::spare::SpareData;
+
+pub type BlockHash = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockWithSpare {
+    data: Vec<u8>,
+    spare: Vec<u8>,
+    spare_synthesised: bool,
+}
+
+impl BlockWithSpare {
+    pub fn new(data: Vec<u8>, spare: Vec<u8>) -> Result<Self> {
+        if data.len() != BLOCK_SIZE || spare.len() != SPARE_SIZE {
+            return Err(LibBBRDBError::InvalidBlockLayout(data.len(), spare.len()));
+        }
+
+        Ok(Self {
+            data,
+            spare,
+            spare_synthesised: false,
+        })
+    }
+
+    // blank spare, as the write paths use for ordinary data blocks
+    pub fn with_blank_spare(data: Vec<u8>) -> Result<Self> {
+        Self::new(data, SpareData::blank().to_bytes().to_vec())
+    }
+
+    // the console couldn't read the real spare, so a blank one stands in for it
+    pub(crate) fn synthesised(data: Vec<u8>) -> Result<Self> {
+        let mut rv = Self::with_blank_spare(data)?;
+        rv.spare_synthesised = true;
+        Ok(rv)
+    }
+
+    pub fn spare_synthesised(&self) -> bool {
+        self.spare_synthesised
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn spare(&self) -> &[u8] {
+        &self.spare
+    }
+
+    pub fn spare_data(&self) -> Result<SpareData> {
+        SpareData::parse(&self.spare)
+    }
+
+    pub fn hash(&self) -> BlockHash {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.data);
+        hasher.update(&self.spare);
+        hasher.finalize().into()
+    }
+
+    pub fn into_parts(self) -> (Vec<u8>, Vec<u8>) {
+        (self.data, self.spare)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NandImage {
+    nand: Vec<u8>,
+    spare: Vec<u8>,
+    synthesised_spare: Vec<u32>,
+}
+
+impl NandImage {
+    pub fn new(nand: Vec<u8>, spare: Vec<u8>) -> Result<Self> {
+        if !nand.len().is_multiple_of(BLOCK_SIZE)
+            || spare.len() != (nand.len() / BLOCK_SIZE) * SPARE_SIZE
+        {
+            return Err(LibBBRDBError::InvalidBlockLayout(nand.len(), spare.len()));
+        }
+
+        Ok(Self {
+            nand,
+            spare,
+            synthesised_spare: vec![],
+        })
+    }
+
+    pub fn with_capacity(num_blocks: usize) -> Self {
+        Self {
+            nand: Vec::with_capacity(num_blocks * BLOCK_SIZE),
+            spare: Vec::with_capacity(num_blocks * SPARE_SIZE),
+            synthesised_spare: vec![],
+        }
+    }
+
+    pub fn push(&mut self, block: BlockWithSpare) {
+        if block.spare_synthesised {
+            self.synthesised_spare.push(self.num_blocks() as u32);
+        }
+        self.nand.extend(block.data);
+        self.spare.extend(block.spare);
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.nand.len() / BLOCK_SIZE
+    }
+
+    pub fn block(&self, index: usize) -> Option<(&[u8], &[u8])> {
+        if index >= self.num_blocks() {
+            return None;
+        }
+
+        Some((
+            &self.nand[index * BLOCK_SIZE..(index + 1) * BLOCK_SIZE],
+            &self.spare[index * SPARE_SIZE..(index + 1) * SPARE_SIZE],
+        ))
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.nand
+            .chunks(BLOCK_SIZE)
+            .zip(self.spare.chunks(SPARE_SIZE))
+    }
+
+    pub fn nand(&self) -> &[u8] {
+        &self.nand
+    }
+
+    pub fn spare(&self) -> &[u8] {
+        &self.spare
+    }
+
+    // blocks whose spare is a blank placeholder rather than what's on the card
+    pub fn synthesised_spare(&self) -> &[u32] {
+        &self.synthesised_spare
+    }
+
+    pub(crate) fn set_synthesised_spare(&mut self, blocks: Vec<u32>) {
+        self.synthesised_spare = blocks;
+    }
+
+    pub fn into_parts(self) -> (Vec<u8>, Vec<u8>) {
+        (self.nand, self.spare)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockOutcome {
+    Read,
+    // marked bad in its spare; whatever the card returned was kept
+    Bad,
+    // the read failed, so the block was written out as zeroes
+    ZeroFilled(String),
+}
+
+// how faithful a dump is, block by block, so an archive can say what it's missing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DumpReport {
+    // the card block that blocks[0] came from
+    pub start: u32,
+    pub blocks: Vec<BlockOutcome>,
+    pub synthesised_spare: Vec<u32>,
+    // only filled in when the handle has dump digests turned on
+    pub nand_digests: Option<DumpDigests>,
+    pub spare_digests: Option<DumpDigests>,
+}
+
+// checksums of a dump stream, taken as it was written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DumpDigests {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    pub sha256: BlockHash,
+}
+
+impl DumpReport {
+    fn with(&self, outcome: BlockOutcome) -> impl Iterator<Item = u32> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(move |&(_, o)| *o == outcome)
+            .map(|(i, _)| self.start + i as u32)
+    }
+
+    pub fn succeeded(&self) -> impl Iterator<Item = u32> + '_ {
+        self.with(BlockOutcome::Read)
+    }
+
+    pub fn bad(&self) -> impl Iterator<Item = u32> + '_ {
+        self.with(BlockOutcome::Bad)
+    }
+
+    pub fn zero_filled(&self) -> impl Iterator<Item = (u32, &str)> + '_ {
+        self.blocks.iter().enumerate().filter_map(|(i, o)| match o {
+            BlockOutcome::ZeroFilled(e) => Some((self.start + i as u32, e.as_str())),
+            _ => None,
+        })
+    }
+
+    // every block came back as it is on the card
+    pub fn is_complete(&self) -> bool {
+        self.zero_filled().next().is_none() && self.synthesised_spare.is_empty()
+    }
+}
+
+// blocks that couldn't be written, so a partial restore can't pass for a whole one
+#[derive(Debug, Default)]
+pub struct WriteReport {
+    pub written: usize,
+    pub failed: Vec<(u32, LibBBRDBError)>,
+    // why the FAT couldn't be read back from the new contents, if it couldn't; the blocks are
+    // written either way, but the handle has no FAT until the card is read again
+    pub fat_error: Option<LibBBRDBError>,
+}
+
+impl WriteReport {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct Digester {
+    crc32: Crc32,
+    sha1: Sha1,
+    sha256: Sha256,
+}
+
+impl Digester {
+    fn update(&mut self, data: &[u8]) {
+        self.crc32.update(data);
+        self.sha1.update(data);
+        self.sha256.update(data);
+    }
+
+    fn finish(self) -> DumpDigests {
+        DumpDigests {
+            crc32: self.crc32.finalize(),
+            sha1: self.sha1.finalize().into(),
+            sha256: self.sha256.finalize().into(),
+        }
+    }
+}
+
+pub(crate) struct HashWriter<'a, W: Write> {
+    inner: &'a mut W,
+    digester: Option<Digester>,
+}
+
+impl<'a, W: Write> HashWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W, enabled: bool) -> Self {
+        Self {
+            inner,
+            digester: enabled.then(Digester::default),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Option<DumpDigests> {
+        self.digester.map(Digester::finish)
+    }
+}
+
+impl<W: Write> Write for HashWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(d) = &mut self.digester {
+            d.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,162 @@
+use rusb::UsbContext;
+
+#[cfg(all(feature = "writing", feature = "native_fs"))]
+use crate::capabilities::Capabilities;
+#[cfg(feature = "native_fs")]
+use crate::capabilities::Support;
+use crate::capabilities::PROBE_FILE;
+use crate::commands::Command;
+use crate::error::*;
+use crate::rdb::RDBCommand;
+use crate::Handle;
+
+// Host: the crate reads blocks and edits the FAT itself.
+// Device: the console's own file commands do the work, and the FAT is reloaded afterwards.
+// Their argument layout is modelled on ChksumFile's (name length, then the padded name) and
+// hasn't been checked against a capture from real firmware, so Device only exists with the
+// experimental native_fs feature, and even then each command is only used once the console
+// has answered a probe of it; anything else falls back to Host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileBackend {
+    #[default]
+    Host,
+    #[cfg(feature = "native_fs")]
+    Device,
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn file_backend(&self) -> FileBackend {
+        self.file_backend
+    }
+
+    pub fn set_file_backend(&mut self, backend: FileBackend) {
+        self.file_backend = backend;
+    }
+
+    // sends `command` for PROBE_FILE and reads whatever answer comes back, whatever its status;
+    // Ok means the console knows the command
+    pub(crate) fn native_probe(&self, command: Command) -> Result<()> {
+        self.send_filename(command, PROBE_FILE)?;
+
+        match command {
+            Command::ReadFile => {
+                let resp = self.check_cmd_response(command, 2)?;
+                if resp[0] == 0 {
+                    self.read_data(resp[1] as usize)?;
+                }
+            }
+            Command::RenameFile => {
+                let (len, name) = Self::encode_filename(PROBE_FILE)?;
+                self.write_data(RDBCommand::HostData, len.to_be_bytes())?;
+                self.write_data(RDBCommand::HostData, name)?;
+                self.check_cmd_response(command, 1)?;
+            }
+            _ => {
+                self.check_cmd_response(command, 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // with the Device backend selected, whether `command` is known to work
+    #[cfg(feature = "native_fs")]
+    pub(crate) fn use_native(&self, command: Command) -> bool {
+        self.file_backend == FileBackend::Device
+            && self.probe_command(command) == Support::Supported
+    }
+
+    #[cfg(not(feature = "native_fs"))]
+    pub(crate) fn use_native(&self, _command: Command) -> bool {
+        false
+    }
+
+    pub(crate) fn native_read_file(&self, filename: &str) -> Result<Vec<u8>> {
+        self.send_filename(Command::ReadFile, filename)?;
+
+        let resp = self.check_cmd_response(Command::ReadFile, 2)?;
+        let (status, size) = (resp[0], resp[1]);
+        if status != 0 {
+            return Err(CardError::from_u32(status).into());
+        }
+
+        let mut data = self.read_data(size as usize)?;
+        data.truncate(size as usize);
+
+        Ok(data)
+    }
+
+    // the file commands that change the card are only tried when asked, and only on PROBE_FILE:
+    // DeleteFile and RenameFile while it isn't there, then WriteFile to create it, after which
+    // it's deleted again. Nothing is tried if PROBE_FILE is really on the card
+    #[cfg(all(feature = "writing", feature = "native_fs"))]
+    pub fn probe_native_changes(&mut self) -> Result<Capabilities> {
+        self.check_writable()?;
+        if self.ListFiles()?.iter().any(|(name, _)| name == PROBE_FILE) {
+            return Ok(self.capabilities.borrow().clone());
+        }
+
+        for command in [Command::DeleteFile, Command::RenameFile] {
+            if self.capabilities.borrow().supports(command) == Support::Unknown {
+                let rv = self.native_probe(command);
+                self.record_probe(command, rv);
+            }
+        }
+
+        if self.capabilities.borrow().supports(Command::WriteFile) == Support::Unknown {
+            let rv = match self.native_write_file(&[], PROBE_FILE) {
+                Err(LibBBRDBError::CardError(_)) => Ok(()),
+                rv => rv,
+            };
+            self.record_probe(Command::WriteFile, rv);
+
+            self.reload_fat()?;
+            if self.ListFiles()?.iter().any(|(name, _)| name == PROBE_FILE) {
+                self.DeleteFile(PROBE_FILE)?;
+            }
+        }
+
+        Ok(self.capabilities.borrow().clone())
+    }
+
+    #[cfg(feature = "writing")]
+    pub(crate) fn native_write_file(&mut self, data: &[u8], filename: &str) -> Result<()> {
+        self.send_filename(Command::WriteFile, filename)?;
+        self.send_data(data)?;
+
+        let status = self.check_cmd_response(Command::WriteFile, 1)?[0];
+        if status != 0 {
+            return Err(CardError::from_u32(status).into());
+        }
+
+        self.reload_fat()
+    }
+
+    #[cfg(feature = "writing")]
+    pub(crate) fn native_delete_file(&mut self, filename: &str) -> Result<()> {
+        self.send_filename(Command::DeleteFile, filename)?;
+
+        let status = self.check_cmd_response(Command::DeleteFile, 1)?[0];
+        if status != 0 {
+            return Err(CardError::from_u32(status).into());
+        }
+
+        self.reload_fat()
+    }
+
+    #[cfg(feature = "writing")]
+    pub(crate) fn native_rename_file(&mut self, from: &str, to: &str) -> Result<()> {
+        let (to_len, to_name) = Self::encode_filename(to)?;
+
+        self.send_filename(Command::RenameFile, from)?;
+        self.write_data(RDBCommand::HostData, to_len.to_be_bytes())?;
+        self.write_data(RDBCommand::HostData, to_name)?;
+
+        let status = self.check_cmd_response(Command::RenameFile, 1)?[0];
+        if status != 0 {
+            return Err(CardError::from_u32(status).into());
+        }
+
+        self.reload_fat()
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use log::warn;
+use rusb::UsbContext;
+use serde_json::json;
+
+use crate::audit::to_hex;
+use crate::error::*;
+use crate::rdb::RDBCommand;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    HostToDevice,
+    DeviceToHost,
+}
+
+// one JSON object per line: {"t_us", "dir", "cmd", "code", "data"}
+pub(crate) struct PacketTrace {
+    start: Instant,
+    out: Box<dyn Write + Send>,
+}
+
+impl Debug for PacketTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketTrace")
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PacketTrace {
+    fn record(&mut self, dir: Direction, cmd: RDBCommand, data: &[u8]) -> std::io::Result<()> {
+        let line = json!({
+            "t_us": self.start.elapsed().as_micros() as u64,
+            "dir": match dir {
+                Direction::HostToDevice => "host",
+                Direction::DeviceToHost => "device",
+            },
+            "cmd": format!("{cmd:?}"),
+            "code": cmd as u8,
+            "data": to_hex(data),
+        });
+        writeln!(self.out, "{line}")
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn trace_packets_to<W: Write + Send + 'static>(&self, out: W) {
+        *self.packet_trace.borrow_mut() = Some(PacketTrace {
+            start: Instant::now(),
+            out: Box::new(out),
+        });
+    }
+
+    pub fn trace_packets_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.trace_packets_to(BufWriter::new(File::create(path)?));
+        Ok(())
+    }
+
+    pub fn stop_packet_trace(&self) -> Result<()> {
+        if let Some(mut trace) = self.packet_trace.borrow_mut().take() {
+            trace.out.flush()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn record_packet(&self, dir: Direction, cmd: RDBCommand, data: &[u8]) {
+        let mut trace = self.packet_trace.borrow_mut();
+        if let Some(t) = trace.as_mut() {
+            if let Err(e) = t.record(dir, cmd, data) {
+                warn!("packet trace stopped: {e}");
+                *trace = None;
+            }
+        }
+    }
+}
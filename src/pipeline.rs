@@ -0,0 +1,30 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use crate::error::*;
+
+// how many blocks the reader can get ahead of the writer before it waits for it to catch up
+pub(crate) const WRITE_AHEAD: usize = 8;
+
+// runs `write` on its own thread, fed by `read` through a short queue, so whatever the host
+// does with a block (hashing, the disk) overlaps with the console sending the next one.
+// `read` should stop once a send fails; that means `write` has given up, and its error wins
+pub(crate) fn write_behind<T: Send, R, W: Send>(
+    read: impl FnOnce(&SyncSender<T>) -> Result<R>,
+    write: impl FnOnce(Receiver<T>) -> Result<W> + Send,
+) -> Result<(R, W)> {
+    thread::scope(|s| {
+        let (tx, rx) = sync_channel(WRITE_AHEAD);
+        let writer = s.spawn(move || write(rx));
+
+        let rv = read(&tx);
+        drop(tx);
+
+        let written = writer
+            .join()
+            .map_err(|_| LibBBRDBError::InternalError("the dump writer panicked".to_string()))?;
+
+        let written = written?;
+        Ok((rv?, written))
+    })
+}
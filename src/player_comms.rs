@@ -1,6 +1,132 @@
 use rusb::UsbContext;
 
+use crate::demux::Channel;
+use crate::error::*;
+use crate::fault::{FaultReport, FAULT_SIZE};
+use crate::rdb::{to_u32, RDBCommand};
 use crate::Handle;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleMessage {
+    Print(String),
+    Log(Vec<u8>),
+    Fault(Box<FaultReport>),
+}
+
+pub struct ConsoleOutput<'a, C: UsbContext> {
+    handle: &'a Handle<C>,
+    print: Vec<u8>,
+    log: Vec<u8>,
+    log_len: Option<usize>,
+    fault: Vec<u8>,
+}
+
+impl<C: UsbContext> ConsoleOutput<'_, C> {
+    // reads at most one packet; Ok(None) means nothing complete arrived yet
+    pub fn poll(&mut self) -> Result<Option<ConsoleMessage>> {
+        let (cmd, data) = match self.handle.read_packet_for(Channel::Console) {
+            Ok(p) => p,
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        match cmd {
+            RDBCommand::DevicePrint => {
+                self.print.extend(data);
+
+                match self.print.iter().position(|&b| b == b'\n') {
+                    Some(end) => {
+                        let line = self.print.drain(..=end).collect::<Vec<_>>();
+                        Ok(Some(ConsoleMessage::Print(
+                            String::from_utf8_lossy(&line[..end]).into_owned(),
+                        )))
+                    }
+                    None => Ok(None),
+                }
+            }
+
+            RDBCommand::DeviceLogCT => {
+                self.log.clear();
+                self.log_len = Some(to_u32(&data) as usize);
+                Ok(None)
+            }
+
+            RDBCommand::DeviceLog => {
+                self.log.extend(data);
+
+                match self.log_len {
+                    Some(len) if self.log.len() >= len => {
+                        self.log_len = None;
+                        self.handle.send_rdb_signal(RDBCommand::HostLogDone)?;
+
+                        let mut log = std::mem::take(&mut self.log);
+                        log.truncate(len);
+                        Ok(Some(ConsoleMessage::Log(log)))
+                    }
+                    _ => Ok(None),
+                }
+            }
+
+            RDBCommand::DeviceFault => {
+                self.fault.extend(data);
+
+                if self.fault.len() >= FAULT_SIZE {
+                    let fault = self.fault.drain(..FAULT_SIZE).collect::<Vec<_>>();
+                    Ok(Some(ConsoleMessage::Fault(Box::new(FaultReport::parse(
+                        &fault,
+                    )?))))
+                } else {
+                    Ok(None)
+                }
+            }
+
+            x => Err(LibBBRDBError::RDBUnexpected(
+                x,
+                vec![
+                    RDBCommand::DevicePrint,
+                    RDBCommand::DeviceLogCT,
+                    RDBCommand::DeviceLog,
+                    RDBCommand::DeviceFault,
+                ],
+            )),
+        }
+    }
+
+    // whatever has been printed without a trailing newline so far
+    pub fn flush_print(&mut self) -> Option<ConsoleMessage> {
+        if self.print.is_empty() {
+            None
+        } else {
+            let line = std::mem::take(&mut self.print);
+            Some(ConsoleMessage::Print(
+                String::from_utf8_lossy(&line).into_owned(),
+            ))
+        }
+    }
+}
+
+impl<C: UsbContext> Iterator for ConsoleOutput<'_, C> {
+    type Item = Result<ConsoleMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.poll() {
+                Ok(Some(m)) => return Some(Ok(m)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
 impl<C: UsbContext> Handle<C> {
-}
\ No newline at end of file
+    pub fn subscribe_console_output(&self) -> ConsoleOutput<'_, C> {
+        ConsoleOutput {
+            handle: self,
+            print: vec![],
+            log: vec![],
+            log_len: None,
+            fault: vec![],
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::fmt::Debug;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressUnit {
+    Blocks,
+    Bytes,
+}
+
+// long operations report through this; the default draws an indicatif bar on stderr
+pub trait ProgressSink: Send + Debug {
+    fn begin(&self, task: &'static str, total: Option<u64>, unit: ProgressUnit);
+    fn advance(&self, amount: u64);
+    fn finish(&self);
+}
+
+#[derive(Debug, Default)]
+pub struct BarProgress {
+    bar: RefCell<Option<ProgressBar>>,
+}
+
+impl ProgressSink for BarProgress {
+    fn begin(&self, task: &'static str, total: Option<u64>, unit: ProgressUnit) {
+        let template = match unit {
+            ProgressUnit::Blocks => "{msg} {wide_bar} {pos}/{len}, eta {eta}",
+            ProgressUnit::Bytes => {
+                "{msg} {wide_bar} {bytes}/{total_bytes}, eta {eta} ({binary_bytes_per_sec})"
+            }
+        };
+
+        let bar = match total {
+            Some(t) => ProgressBar::new(t).with_style(
+                ProgressStyle::with_template(template)
+                    .unwrap_or_else(|_| ProgressStyle::default_bar()),
+            ),
+            None => ProgressBar::new_spinner(),
+        };
+        *self.bar.borrow_mut() = Some(bar.with_message(task));
+    }
+
+    fn advance(&self, amount: u64) {
+        if let Some(bar) = self.bar.borrow().as_ref() {
+            bar.inc(amount);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = self.bar.borrow_mut().take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn begin(&self, _task: &'static str, _total: Option<u64>, _unit: ProgressUnit) {}
+    fn advance(&self, _amount: u64) {}
+    fn finish(&self) {}
+}
+
+// a task begun on a sink, finished when this goes out of scope so an early return can't leave
+// a bar behind
+#[must_use]
+#[derive(Debug)]
+pub struct ProgressTask<'a> {
+    sink: &'a dyn ProgressSink,
+}
+
+impl<'a> ProgressTask<'a> {
+    pub fn begin(
+        sink: &'a dyn ProgressSink,
+        task: &'static str,
+        total: Option<u64>,
+        unit: ProgressUnit,
+    ) -> Self {
+        sink.begin(task, total, unit);
+        Self { sink }
+    }
+
+    pub fn advance(&self, amount: u64) {
+        self.sink.advance(amount);
+    }
+}
+
+impl Drop for ProgressTask<'_> {
+    fn drop(&mut self) {
+        self.sink.finish();
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn set_progress_sink<P: ProgressSink + 'static>(&mut self, sink: P) {
+        self.progress = Box::new(sink);
+    }
+
+    pub(crate) fn progress(&self) -> &dyn ProgressSink {
+        self.progress.as_ref()
+    }
+
+    pub(crate) fn progress_task(
+        &self,
+        task: &'static str,
+        total: Option<u64>,
+        unit: ProgressUnit,
+    ) -> ProgressTask<'_> {
+        ProgressTask::begin(self.progress(), task, total, unit)
+    }
+
+    // for work that needs the handle mutably, which a ProgressTask can't be held across
+    pub(crate) fn with_progress<T>(
+        &mut self,
+        task: &'static str,
+        total: Option<u64>,
+        unit: ProgressUnit,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        self.progress().begin(task, total, unit);
+        let rv = f(self);
+        self.progress().finish();
+        rv
+    }
+}
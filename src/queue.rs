@@ -0,0 +1,287 @@
+use std::collections::VecDeque;
+use std::ffi::{c_int, c_void};
+use std::time::Duration;
+
+use rusb::constants::*;
+use rusb::ffi::{self, libusb_transfer};
+use rusb::{DeviceHandle, UsbContext};
+
+// each queued IN buffer; a multiple of any bulk max packet size, so only the last can come up short
+pub(crate) const QUEUE_TRANSFER_SIZE: usize = 0x1000;
+
+// how many times in a row the event loop may fail before a transfer is given up on, and then how
+// many more it gets to deliver the cancellation
+const EVENT_ERRORS: usize = 3;
+
+// transfers libusb has been given but hasn't handed back yet
+struct Queued {
+    transfer: *mut libusb_transfer,
+    offset: usize,
+    len: usize,
+    // set by the completion callback; boxed so it stays put while libusb holds a pointer to it
+    done: Box<c_int>,
+}
+
+extern "system" fn transfer_done(transfer: *mut libusb_transfer) {
+    // SAFETY: user_data is the `done` flag of a live Queued, which outlives the transfer
+    unsafe { *((*transfer).user_data as *mut c_int) = 1 };
+}
+
+fn error_from_code(code: c_int) -> rusb::Error {
+    match code {
+        LIBUSB_ERROR_IO => rusb::Error::Io,
+        LIBUSB_ERROR_INVALID_PARAM => rusb::Error::InvalidParam,
+        LIBUSB_ERROR_ACCESS => rusb::Error::Access,
+        LIBUSB_ERROR_NO_DEVICE => rusb::Error::NoDevice,
+        LIBUSB_ERROR_NOT_FOUND => rusb::Error::NotFound,
+        LIBUSB_ERROR_BUSY => rusb::Error::Busy,
+        LIBUSB_ERROR_TIMEOUT => rusb::Error::Timeout,
+        LIBUSB_ERROR_OVERFLOW => rusb::Error::Overflow,
+        LIBUSB_ERROR_PIPE => rusb::Error::Pipe,
+        LIBUSB_ERROR_INTERRUPTED => rusb::Error::Interrupted,
+        LIBUSB_ERROR_NO_MEM => rusb::Error::NoMem,
+        LIBUSB_ERROR_NOT_SUPPORTED => rusb::Error::NotSupported,
+        _ => rusb::Error::Other,
+    }
+}
+
+fn error_from_status(status: c_int) -> rusb::Error {
+    match status {
+        LIBUSB_TRANSFER_TIMED_OUT => rusb::Error::Timeout,
+        LIBUSB_TRANSFER_STALL => rusb::Error::Pipe,
+        LIBUSB_TRANSFER_NO_DEVICE => rusb::Error::NoDevice,
+        LIBUSB_TRANSFER_OVERFLOW => rusb::Error::Overflow,
+        LIBUSB_TRANSFER_CANCELLED => rusb::Error::Interrupted,
+        _ => rusb::Error::Io,
+    }
+}
+
+struct InQueue<'a, C: UsbContext> {
+    handle: &'a DeviceHandle<C>,
+    endpoint: u8,
+    timeout: Duration,
+    pending: VecDeque<Queued>,
+}
+
+impl<C: UsbContext> InQueue<'_, C> {
+    fn submit(&mut self, buf: *mut u8, offset: usize, len: usize) -> rusb::Result<()> {
+        // SAFETY: the transfer points into `buf`, which read_bulk_queued keeps borrowed, and
+        // doesn't touch, until every transfer has been reaped in Drop
+        unsafe {
+            let transfer = ffi::libusb_alloc_transfer(0);
+            if transfer.is_null() {
+                return Err(rusb::Error::NoMem);
+            }
+
+            let mut done = Box::new(0);
+            ffi::libusb_fill_bulk_transfer(
+                transfer,
+                self.handle.as_raw(),
+                self.endpoint,
+                buf.add(offset),
+                len as c_int,
+                transfer_done,
+                &mut *done as *mut c_int as *mut c_void,
+                self.timeout.as_millis().min(u32::MAX as u128) as u32,
+            );
+
+            match ffi::libusb_submit_transfer(transfer) {
+                0 => {
+                    self.pending.push_back(Queued {
+                        transfer,
+                        offset,
+                        len,
+                        done,
+                    });
+                    Ok(())
+                }
+                e => {
+                    ffi::libusb_free_transfer(transfer);
+                    Err(error_from_code(e))
+                }
+            }
+        }
+    }
+
+    // waits for the oldest transfer; completions on one endpoint always arrive in order. If the
+    // event loop keeps failing the transfer is cancelled, and if even that can't be delivered it's
+    // leaked rather than freed under libusb
+    fn reap(&mut self) -> Option<rusb::Result<(Queued, c_int, usize)>> {
+        let mut queued = self.pending.pop_front()?;
+        let mut errors = 0;
+        let mut broken = None;
+
+        // SAFETY: the transfer is live until it's freed below, after its callback has run
+        unsafe {
+            while *queued.done == 0 {
+                let e = ffi::libusb_handle_events_completed(
+                    self.handle.context().as_raw(),
+                    &mut *queued.done,
+                );
+                if e >= 0 || e == LIBUSB_ERROR_INTERRUPTED {
+                    errors = 0;
+                    continue;
+                }
+
+                errors += 1;
+                if broken.is_none() && errors >= EVENT_ERRORS {
+                    // the event loop itself is broken, so give up on the transfer
+                    ffi::libusb_cancel_transfer(queued.transfer);
+                    broken = Some(error_from_code(e));
+                    errors = 0;
+                } else if let Some(e) = broken.filter(|_| errors >= EVENT_ERRORS) {
+                    std::mem::forget(queued);
+                    return Some(Err(e));
+                }
+            }
+
+            if let Some(e) = broken {
+                ffi::libusb_free_transfer(queued.transfer);
+                return Some(Err(e));
+            }
+
+            let status = (*queued.transfer).status;
+            let actual = (*queued.transfer).actual_length.max(0) as usize;
+            ffi::libusb_free_transfer(queued.transfer);
+
+            Some(Ok((queued, status, actual)))
+        }
+    }
+
+    fn cancel_all(&mut self) {
+        for q in &self.pending {
+            // SAFETY: every transfer still in `pending` has been submitted and not freed
+            unsafe { ffi::libusb_cancel_transfer(q.transfer) };
+        }
+    }
+}
+
+impl<C: UsbContext> Drop for InQueue<'_, C> {
+    fn drop(&mut self) {
+        self.cancel_all();
+        while self.reap().is_some() {}
+    }
+}
+
+// fills `buf` from `endpoint` with up to `depth` transfers in flight at once, so the endpoint
+// always has somewhere to put the next packet. like a single read, it stops early on a short
+// transfer, and only fails if nothing at all arrived
+pub(crate) fn read_bulk_queued<C: UsbContext>(
+    handle: &DeviceHandle<C>,
+    endpoint: u8,
+    buf: &mut [u8],
+    depth: usize,
+    timeout: Duration,
+) -> rusb::Result<usize> {
+    if depth <= 1 || buf.len() <= QUEUE_TRANSFER_SIZE {
+        return handle.read_bulk(endpoint, buf, timeout);
+    }
+
+    let mut queue = InQueue {
+        handle,
+        endpoint,
+        timeout,
+        pending: VecDeque::with_capacity(depth),
+    };
+
+    let (base, size) = (buf.as_mut_ptr(), buf.len());
+    let mut next = 0;
+    let mut landed = vec![];
+    let mut stopped = false;
+    let mut error = None;
+
+    loop {
+        while !stopped && queue.pending.len() < depth && next < size {
+            let len = QUEUE_TRANSFER_SIZE.min(size - next);
+            if let Err(e) = queue.submit(base, next, len) {
+                error.get_or_insert(e);
+                stopped = true;
+                break;
+            }
+            next += len;
+        }
+
+        let (queued, status, actual) = match queue.reap() {
+            Some(Ok(r)) => r,
+            Some(Err(e)) => {
+                error.get_or_insert(e);
+                break;
+            }
+            None => break,
+        };
+        landed.push((queued.offset, actual));
+
+        let ok = status == LIBUSB_TRANSFER_COMPLETED;
+        if !ok && status != LIBUSB_TRANSFER_CANCELLED {
+            error.get_or_insert(error_from_status(status));
+        }
+        if !stopped && (!ok || actual < queued.len) {
+            stopped = true;
+            queue.cancel_all();
+        }
+    }
+
+    // nothing may move in `buf` until libusb has let go of all of it
+    drop(queue);
+    let filled = compact(buf, &landed);
+
+    match error {
+        Some(e) if filled == 0 => Err(e),
+        _ => Ok(filled),
+    }
+}
+
+// closes the gaps short transfers left: anything that landed after one belongs straight after it.
+// `landed` is each transfer's (offset, length received), in the order they were queued
+fn compact(buf: &mut [u8], landed: &[(usize, usize)]) -> usize {
+    let mut filled = 0;
+    for &(offset, actual) in landed {
+        if actual > 0 && offset != filled {
+            buf.copy_within(offset..offset + actual, filled);
+        }
+        filled += actual;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_transfers_are_closed_up() {
+        let mut buf = vec![0; 4 * QUEUE_TRANSFER_SIZE];
+        let mut expected = vec![];
+        // full, short, empty, then a transfer that had already landed before the cancel
+        let landed = [
+            (0, QUEUE_TRANSFER_SIZE),
+            (QUEUE_TRANSFER_SIZE, 0x123),
+            (2 * QUEUE_TRANSFER_SIZE, 0),
+            (3 * QUEUE_TRANSFER_SIZE, 0x40),
+        ];
+        for (n, &(offset, actual)) in landed.iter().enumerate() {
+            let data = vec![n as u8 + 1; actual];
+            buf[offset..offset + actual].copy_from_slice(&data);
+            expected.extend(data);
+        }
+
+        let filled = compact(&mut buf, &landed);
+        assert_eq!(filled, QUEUE_TRANSFER_SIZE + 0x123 + 0x40);
+        assert_eq!(&buf[..filled], expected.as_slice());
+    }
+
+    #[test]
+    fn full_transfers_stay_put() {
+        let mut buf = (0..2 * QUEUE_TRANSFER_SIZE)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let before = buf.clone();
+
+        let landed = [
+            (0, QUEUE_TRANSFER_SIZE),
+            (QUEUE_TRANSFER_SIZE, QUEUE_TRANSFER_SIZE),
+        ];
+        assert_eq!(compact(&mut buf, &landed), buf.len());
+        assert_eq!(buf, before);
+    }
+}
@@ -1,10 +1,17 @@
 use std::collections::VecDeque;
 use std::mem::size_of;
+use std::time::{Duration, Instant};
 
+use log::{debug, trace, warn};
 use rusb::UsbContext;
 
-use crate::constants::{RDB_BLOCKS_PER_CHUNK, RDB_BLOCK_SIZE, TIMEOUT};
+use crate::constants::{
+    DRAIN_TIMEOUT, RAMROM_MAX_TRANSFER, RAMROM_REQUEST_SIZE, RDB_BLOCKS_PER_CHUNK, RDB_BLOCK_SIZE,
+    RDB_BULK_EP_IN, READ_DEADLINE, READ_MIN_RATE, READ_STALL_TIMEOUT,
+};
+use crate::demux::Channel;
 use crate::error::*;
+use crate::packet_trace::Direction;
 use crate::Handle;
 use crate::LibBBRDBError;
 
@@ -84,21 +91,25 @@ fn encode_rdb_hdr(cmd: RDBCommand, len: usize) -> u8 {
     ((cmd as u8) << 2) | (len as u8)
 }
 
-fn encode_rdb_packet(cmd: RDBCommand, data: &[u8]) -> Vec<u8> {
+fn encode_rdb_packet(cmd: RDBCommand, data: &[u8]) -> Result<Vec<u8>> {
     let len = data.len();
-    assert!(len < 4);
+    if len >= 4 {
+        return Err(LibBBRDBError::DataTooLarge(len));
+    }
 
     let mut rv = vec![];
 
     rv.push(encode_rdb_hdr(cmd, len));
     rv.extend(data);
 
-    rv
+    Ok(rv)
 }
 
-fn encode_rdb_block_packet(cmd: RDBCommand, data: &[u8]) -> Vec<u8> {
+fn encode_rdb_block_packet(cmd: RDBCommand, data: &[u8]) -> Result<Vec<u8>> {
     let len = data.len();
-    assert!(len <= RDB_BLOCK_SIZE);
+    if len > RDB_BLOCK_SIZE {
+        return Err(LibBBRDBError::DataTooLarge(len));
+    }
 
     let mut rv = vec![];
 
@@ -106,7 +117,7 @@ fn encode_rdb_block_packet(cmd: RDBCommand, data: &[u8]) -> Vec<u8> {
     rv.push(len as u8);
     rv.extend(data);
 
-    rv
+    Ok(rv)
 }
 
 fn decode_rdb_cmd_len(byte: u8) -> Result<(RDBCommand, u8)> {
@@ -115,25 +126,79 @@ fn decode_rdb_cmd_len(byte: u8) -> Result<(RDBCommand, u8)> {
         .map_err(LibBBRDBError::RDBUnknown)
 }
 
-fn to_u32(data: &[u8]) -> u32 {
-    let mut v = vec![0; size_of::<u32>()];
-    v.extend(data);
-    u32::from_be_bytes(v[v.len() - 4..].try_into().unwrap())
+// a RAMROM request is a big-endian offset followed by a big-endian length, spread over
+// as many DeviceRamRom packets as it takes; a zero length means the console is done
+fn decode_ramrom_request(data: &[u8]) -> (u32, u32) {
+    (to_u32(&data[..4]), to_u32(&data[4..8]))
+}
+
+// big-endian, from the last four bytes (or fewer, zero-extended)
+pub(crate) fn to_u32(data: &[u8]) -> u32 {
+    let mut v = [0; size_of::<u32>()];
+    let n = data.len().min(v.len());
+    let start = v.len() - n;
+    v[start..].copy_from_slice(&data[data.len() - n..]);
+    u32::from_be_bytes(v)
+}
+
+// gives up on a read that's taken too long overall, or stopped getting anywhere; each transfer
+// has its own timeout, but a console that keeps sending something other than the data (or
+// nothing, between retries) could otherwise hold a read up forever
+pub(crate) struct ReadWatch {
+    // where the read started in its output buffer
+    base: usize,
+    expected: usize,
+    deadline: Instant,
+    last_progress: Instant,
+    got: usize,
+}
+
+impl ReadWatch {
+    fn new(base: usize, expected: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            base,
+            expected,
+            deadline: now + READ_DEADLINE + Duration::from_secs((expected / READ_MIN_RATE) as u64),
+            last_progress: now,
+            got: 0,
+        }
+    }
+
+    // `out_len` is the length of the buffer the read is filling
+    fn check(&mut self, out_len: usize) -> Result<()> {
+        let now = Instant::now();
+        let got = out_len - self.base;
+        if got > self.got {
+            self.got = got;
+            self.last_progress = now;
+        }
+
+        let why = if now >= self.deadline {
+            "deadline passed"
+        } else if now - self.last_progress >= READ_STALL_TIMEOUT {
+            "stalled"
+        } else {
+            return Ok(());
+        };
+        Err(LibBBRDBError::ReadStalled(self.got, self.expected, why))
+    }
 }
 
 impl<C: UsbContext> Handle<C> {
     fn send_rdb_block_data(&self, data: &[u8]) -> Result<()> {
         let cmd = RDBCommand::HostDataB;
 
-        //println!("block send: {data:02X?}");
+        trace!("block send: {data:02X?}");
 
         for chunk in data.chunks(RDB_BLOCK_SIZE * RDB_BLOCKS_PER_CHUNK) {
             let mut buf = Vec::with_capacity(RDB_BLOCK_SIZE * RDB_BLOCKS_PER_CHUNK);
             for block in chunk.chunks(RDB_BLOCK_SIZE) {
-                buf.extend(encode_rdb_block_packet(cmd, block));
+                self.record_packet(Direction::HostToDevice, cmd, block);
+                buf.extend(encode_rdb_block_packet(cmd, block)?);
             }
 
-            if self.bulk_transfer_send(&buf, TIMEOUT)? != buf.len() {
+            if self.bulk_transfer_send(&buf, self.timeout)? != buf.len() {
                 return Err(LibBBRDBError::WrongDataLength);
             }
         }
@@ -141,16 +206,17 @@ impl<C: UsbContext> Handle<C> {
         Ok(())
     }
 
-    fn send_rdb_data(&self, cmd: RDBCommand, data: &[u8]) -> Result<()> {
-        //println!("send: {data:02X?}");
+    pub(crate) fn send_rdb_data(&self, cmd: RDBCommand, data: &[u8]) -> Result<()> {
+        trace!("send: {data:02X?}");
 
         for chunk in data.chunks(RDB_BLOCKS_PER_CHUNK) {
             let mut buf = Vec::with_capacity((chunk.len() * 4) / 3);
             for block in chunk.chunks(3) {
-                buf.extend(encode_rdb_packet(cmd, block));
+                self.record_packet(Direction::HostToDevice, cmd, block);
+                buf.extend(encode_rdb_packet(cmd, block)?);
             }
 
-            if self.bulk_transfer_send(&buf, TIMEOUT)? != buf.len() {
+            if self.bulk_transfer_send(&buf, self.timeout)? != buf.len() {
                 return Err(LibBBRDBError::WrongDataLength);
             }
         }
@@ -170,38 +236,112 @@ impl<C: UsbContext> Handle<C> {
         }
     }
 
-    pub(crate) fn read_rdb_packet(&self) -> Result<(RDBCommand, Vec<u8>)> {
-        let data = self.bulk_transfer_receive(1, TIMEOUT)?[0];
-        //println!("rdb packet: {:02X} {}", data >> 2, data & 3);
-        let (cmd, len) = decode_rdb_cmd_len(data)?;
+    // a byte that isn't a command means we've lost our place in the stream, and everything
+    // after it would be misread too; throw away what's queued so the next exchange starts on a
+    // packet boundary, and let the caller decide whether to try again
+    fn decode_cmd_len(&self, byte: u8) -> Result<(RDBCommand, u8)> {
+        decode_rdb_cmd_len(byte).map_err(|_| {
+            let discarded = self.drain_input(DRAIN_TIMEOUT);
+            let e = LibBBRDBError::Desynced(byte, discarded);
+            warn!("{e}");
+            e
+        })
+    }
+
+    pub(crate) fn read_raw_rdb_packet(&self) -> Result<(RDBCommand, Vec<u8>)> {
+        let mut head = [0; 1];
+        self.receive_exact(&mut head)?;
+        let data = head[0];
+        trace!("rdb packet: {:02X} {}", data >> 2, data & 3);
+        let (cmd, len) = self.decode_cmd_len(data)?;
         if cmd == RDBCommand::DeviceDataB {
-            let len = self.bulk_transfer_receive(1, TIMEOUT)?[0];
+            self.receive_exact(&mut head)?;
+            let data = self.bulk_transfer_receive(head[0] as usize, self.timeout)?;
+            self.record_packet(Direction::DeviceToHost, cmd, &data);
 
-            Ok((cmd, self.bulk_transfer_receive(len as usize, TIMEOUT)?))
+            Ok((cmd, data))
         } else {
-            let mut data = self.bulk_transfer_receive(3, TIMEOUT)?;
-
-            data.truncate(len as usize);
+            let mut body = [0; 3];
+            let n = self.bulk_transfer_receive_into(&mut body, self.timeout)?;
+            let data = body[..n.min(len as usize)].to_vec();
+            self.record_packet(Direction::DeviceToHost, cmd, &data);
 
             Ok((cmd, data))
         }
     }
 
-    pub(crate) fn read_rdb_bulk(&self, len: usize) -> Result<Vec<u8>> {
-        let amount_to_read = ((len + 2) / 3) * 4;
+    pub(crate) fn read_rdb_packet(&self) -> Result<(RDBCommand, Vec<u8>)> {
+        self.read_packet_for(Channel::Command)
+    }
 
-        let data = self.bulk_transfer_receive(amount_to_read, TIMEOUT)?;
+    // appends `len` bytes of DeviceData to `out`, decoding them out of a receive buffer that's
+    // kept between calls rather than allocated for every transfer
+    pub(crate) fn read_rdb_bulk_into(
+        &self,
+        len: usize,
+        out: &mut Vec<u8>,
+        watch: &mut ReadWatch,
+    ) -> Result<()> {
+        // the buffer goes back however the read ends, so the next one still has it
+        let mut buf = self.rx_buf.take();
+        let rv = self.read_rdb_bulk_with(&mut buf, len, out, watch);
+        self.rx_buf.replace(buf);
+        rv
+    }
 
-        let mut rv = vec![];
+    fn read_rdb_bulk_with(
+        &self,
+        buf: &mut Vec<u8>,
+        len: usize,
+        out: &mut Vec<u8>,
+        watch: &mut ReadWatch,
+    ) -> Result<()> {
+        let target = out.len() + len;
+
+        // anything that isn't DeviceData gets routed elsewhere, so keep going until we have it all
+        while out.len() < target {
+            watch.check(out.len())?;
+            let amount_to_read = ((target - out.len() + 2) / 3) * 4;
+
+            buf.resize(amount_to_read, 0);
+            let n = self.bulk_transfer_receive_into(buf, self.timeout)?;
+
+            for chunk in buf[..n].chunks(4) {
+                let (cmd, len) = self.decode_cmd_len(chunk[0])?;
+                let data = &chunk[1..(len as usize + 1).min(chunk.len())];
+                self.record_packet(Direction::DeviceToHost, cmd, data);
+
+                match cmd {
+                    RDBCommand::DeviceData => out.extend_from_slice(data),
+                    RDBCommand::DeviceSync => {
+                        self.acknowledge_sync()?;
+                        return Err(LibBBRDBError::ConsoleResynced);
+                    }
+                    RDBCommand::DeviceDataB => {
+                        return Err(LibBBRDBError::RDBUnexpected(
+                            cmd,
+                            vec![RDBCommand::DeviceData],
+                        ))
+                    }
+                    x if x.channel() == Channel::Command => {
+                        return Err(LibBBRDBError::RDBUnexpected(
+                            x,
+                            vec![RDBCommand::DeviceData],
+                        ))
+                    }
+                    x => self.route_packet(x, data.to_vec()),
+                }
+            }
+        }
 
-        for chunk in data.chunks(4) {
-            let (cmd, len) = decode_rdb_cmd_len(chunk[0])?;
-            assert_eq!(cmd, RDBCommand::DeviceData);
+        Ok(())
+    }
 
-            rv.extend(&chunk[1..len as usize + 1]);
+    fn receive_exact(&self, buf: &mut [u8]) -> Result<()> {
+        if self.bulk_transfer_receive_into(buf, self.timeout)? != buf.len() {
+            return Err(LibBBRDBError::WrongDataLength);
         }
-
-        Ok(rv)
+        Ok(())
     }
 
     pub(crate) fn check_player_ready(&self) -> Result<bool> {
@@ -209,13 +349,18 @@ impl<C: UsbContext> Handle<C> {
             .map(|d| d.0 == RDBCommand::DeviceReadyForData)
     }
 
-    fn send_ack(&self) -> Result<()> {
-        self.bulk_transfer_send(&encode_rdb_packet(RDBCommand::HostDataDone, &[]), TIMEOUT)?;
+    pub(crate) fn send_rdb_signal(&self, cmd: RDBCommand) -> Result<()> {
+        self.record_packet(Direction::HostToDevice, cmd, &[]);
+        self.bulk_transfer_send(&encode_rdb_packet(cmd, &[])?, self.timeout)?;
         Ok(())
     }
 
-    pub(crate) fn read_chunk(&self) -> Result<Vec<u8>> {
-        let mut rv = vec![];
+    fn send_ack(&self) -> Result<()> {
+        self.send_rdb_signal(RDBCommand::HostDataDone)
+    }
+
+    pub(crate) fn read_chunk_into(&self, out: &mut Vec<u8>, watch: &mut ReadWatch) -> Result<()> {
+        let start = out.len();
 
         let (cmd, data) = self.read_rdb_packet()?;
         if cmd != RDBCommand::DeviceDataCT {
@@ -226,7 +371,7 @@ impl<C: UsbContext> Handle<C> {
         }
 
         let count = to_u32(&data);
-        //println!("count: {count:08X}");
+        trace!("count: {count:08X}");
 
         /*while rv.len() < count as usize {
             let (cmd, data) = self.read_rdb_packet()?;
@@ -238,22 +383,114 @@ impl<C: UsbContext> Handle<C> {
             }
         }*/
 
-        rv = self.read_rdb_bulk(count as usize)?;
+        self.read_rdb_bulk_into(count as usize, out, watch)?;
 
         self.send_ack()?;
 
-        //println!("recv {rv:02X?}");
+        trace!("recv {:02X?}", &out[start..]);
 
-        Ok(rv)
+        Ok(())
     }
 
     pub(crate) fn read_data(&self, len: usize) -> Result<Vec<u8>> {
-        let mut rv = vec![];
+        let mut rv = Vec::with_capacity(len);
+        self.read_data_into(len, &mut rv)?;
+        Ok(rv)
+    }
 
-        while rv.len() < len {
-            rv.extend(self.read_chunk()?);
+    // appends at least `len` bytes to `out`, so a caller gathering several reads can keep
+    // them all in one allocation
+    pub(crate) fn read_data_into(&self, len: usize, out: &mut Vec<u8>) -> Result<()> {
+        let target = out.len() + len;
+        let mut watch = ReadWatch::new(out.len(), len);
+
+        while out.len() < target {
+            let rv = watch
+                .check(out.len())
+                .and_then(|_| self.read_chunk_into(out, &mut watch));
+            if let Err(e @ LibBBRDBError::ReadStalled(..)) = rv {
+                warn!("{e}");
+                self.drain_input(DRAIN_TIMEOUT);
+                return Err(e);
+            }
+            rv?;
         }
 
-        Ok(rv)
+        Ok(())
+    }
+
+    // throws away whatever the console has sent that nobody's read, both what's been queued here
+    // and what's still on the way, until it's been quiet for `quiet`, so the next command starts
+    // on a packet boundary; for after an interrupted operation, or before starting afresh.
+    // Returns how many bytes came off the wire. A console that never stops talking is given up
+    // on after READ_STALL_TIMEOUT
+    pub fn drain_input(&self, quiet: Duration) -> usize {
+        for (cmd, data) in self.demux.borrow_mut().take_all() {
+            debug!("discarding queued {cmd:?} {data:02X?}");
+        }
+        self.rx_buf.borrow_mut().clear();
+
+        let give_up = Instant::now() + READ_STALL_TIMEOUT;
+        let mut buf = vec![0; RDB_BLOCK_SIZE * RDB_BLOCKS_PER_CHUNK];
+        let mut discarded = 0;
+        while Instant::now() < give_up {
+            let Ok(n @ 1..) = self.transport_read(RDB_BULK_EP_IN, &mut buf, quiet) else {
+                break;
+            };
+            debug!("discarding {:02X?}", &buf[..n]);
+            discarded += n;
+        }
+        discarded
+    }
+
+    // hosts `rom` for the console until it releases the RAMROM or `keep_going` returns false;
+    // returns the number of bytes served
+    #[allow(non_snake_case)]
+    pub fn ServeRamRom(&self, rom: &[u8], mut keep_going: impl FnMut() -> bool) -> Result<u64> {
+        self.guarded(|this| {
+            this.send_rdb_signal(RDBCommand::HostReqRamRom)?;
+
+            let mut request = vec![];
+            let mut served = 0;
+
+            while keep_going() {
+                let (_, data) = match this.read_packet_for(Channel::RamRom) {
+                    Ok(p) => p,
+                    Err(e) if is_timeout(&e) => continue,
+                    Err(e) => return Err(e),
+                };
+
+                request.extend(data);
+                if request.len() < RAMROM_REQUEST_SIZE {
+                    continue;
+                }
+
+                let (offset, len) = decode_ramrom_request(&request);
+                request.drain(..RAMROM_REQUEST_SIZE);
+                trace!("ramrom request: {len:#X} bytes at {offset:#X}");
+
+                if len == 0 {
+                    break;
+                }
+                if len > RAMROM_MAX_TRANSFER {
+                    this.send_rdb_signal(RDBCommand::HostFreeRamRom)?;
+                    return Err(LibBBRDBError::RamRomRequestTooLarge(len, offset));
+                }
+
+                // anything past the end of the ROM reads as open bus
+                let mut chunk = vec![0xFF; len as usize];
+                let start = (offset as usize).min(rom.len());
+                let end = (offset as usize + len as usize).min(rom.len());
+                chunk[..end - start].copy_from_slice(&rom[start..end]);
+
+                // write_data waits for DeviceReadyForData, so the console sets the pace
+                this.write_data(RDBCommand::HostData, &chunk)?;
+                served += len as u64;
+            }
+
+            this.send_rdb_signal(RDBCommand::HostFreeRamRom)?;
+
+            Ok(served)
+        })
     }
 }
@@ -0,0 +1,86 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::debug;
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::Handle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub backoff_multiplier: u32,
+    pub retryable: Vec<rusb::Error>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(10),
+            backoff_multiplier: 2,
+            retryable: vec![
+                rusb::Error::Timeout,
+                rusb::Error::Io,
+                rusb::Error::Busy,
+                rusb::Error::Interrupted,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_retryable(&self, error: &LibBBRDBError) -> bool {
+        match error {
+            LibBBRDBError::LibUSBError(e) => self.retryable.contains(e),
+            LibBBRDBError::RetriesExhausted(_, e) => self.is_retryable(e),
+            _ => false,
+        }
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    // only command_response uses this; retrying individual transfers as well would multiply the
+    // attempts, and an OUT transfer that timed out may have got partway
+    pub(crate) fn with_retries<T>(&self, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut delay = self.retry.backoff;
+        let mut attempt = 1;
+
+        loop {
+            match f() {
+                Err(e) if self.retry.is_retryable(&e) => {
+                    if attempt >= self.retry.attempts {
+                        return Err(if attempt > 1 {
+                            LibBBRDBError::RetriesExhausted(attempt, Box::new(e))
+                        } else {
+                            e
+                        });
+                    }
+
+                    debug!("attempt {attempt} failed ({e}), retrying in {delay:?}");
+                    sleep(delay);
+                    delay *= self.retry.backoff_multiplier;
+                    attempt += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+}
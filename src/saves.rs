@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, write};
+#[cfg(feature = "writing")]
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::Handle;
+
+// what a title keeps its save state in, next to its content; named <content id>.<ext>
+pub(crate) const SAVE_EXTENSIONS: [&str; 5] = ["sta", "pak", "eep", "fla", "sra"];
+
+// what the title's content itself goes by
+pub(crate) const CONTENT_EXTENSIONS: [&str; 3] = ["app", "aes", "rec"];
+
+// "0012abcd.sta" -> (0x0012ABCD, "sta")
+pub(crate) fn content_file(name: &str) -> Option<(u32, String)> {
+    let (stem, ext) = name.split_once('.')?;
+    if stem.len() != 8 {
+        return None;
+    }
+
+    let id = u32::from_str_radix(stem, 16).ok()?;
+    Some((id, ext.to_ascii_lowercase()))
+}
+
+fn save_of(name: &str) -> Option<u32> {
+    content_file(name)
+        .filter(|(_, ext)| SAVE_EXTENSIONS.contains(&ext.as_str()))
+        .map(|(id, _)| id)
+}
+
+fn wanted(name: &str, content_id: Option<u32>) -> bool {
+    save_of(name).is_some_and(|id| content_id.is_none_or(|c| c == id))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TitleSaves {
+    pub content_id: u32,
+    // whether the title's content is on the card too, or only its saves are left
+    pub installed: bool,
+    // (name, size)
+    pub files: Vec<(String, usize)>,
+}
+
+impl<C: UsbContext> Handle<C> {
+    // every title with save files on the card, by content id
+    #[allow(non_snake_case)]
+    pub fn ListSaves(&self) -> Result<Vec<TitleSaves>> {
+        let files = self.ListFiles()?;
+
+        let installed = files
+            .iter()
+            .filter_map(|(name, _)| content_file(name))
+            .filter(|(_, ext)| CONTENT_EXTENSIONS.contains(&ext.as_str()))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+
+        let mut titles = BTreeMap::new();
+        for (name, size) in files {
+            if let Some(id) = save_of(&name) {
+                titles.entry(id).or_insert_with(Vec::new).push((name, size));
+            }
+        }
+
+        Ok(titles
+            .into_iter()
+            .map(|(content_id, files)| TitleSaves {
+                content_id,
+                installed: installed.contains(&content_id),
+                files,
+            })
+            .collect())
+    }
+
+    // copies every save file (or just one title's) into `dir` under its card name; returns the
+    // names written
+    #[allow(non_snake_case)]
+    pub fn ExportSaves<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        content_id: Option<u32>,
+    ) -> Result<Vec<String>> {
+        let dir = dir.as_ref();
+        create_dir_all(dir)?;
+
+        let mut rv = vec![];
+        for (name, _) in self.ListFiles()? {
+            if !wanted(&name, content_id) {
+                continue;
+            }
+
+            let data = self
+                .ReadFile(&name)?
+                .ok_or_else(|| LibBBRDBError::FileNotFound(name.clone()))?;
+            write(dir.join(&name), data)?;
+            rv.push(name);
+        }
+
+        Ok(rv)
+    }
+
+    // the other way round: writes every save-named file in `dir` (or just one title's) to the
+    // card, replacing what's there; returns the names written
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn ImportSaves<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        content_id: Option<u32>,
+    ) -> Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in read_dir(dir.as_ref())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && wanted(&name, content_id) {
+                names.push((name, entry.path()));
+            }
+        }
+        names.sort();
+
+        let mut rv = vec![];
+        for (name, path) in names {
+            self.WriteFile(&read(path)?, &name)?;
+            rv.push(name);
+        }
+
+        Ok(rv)
+    }
+}
@@ -0,0 +1,122 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::Handle;
+
+pub const SIG_DB_FILE: &str = "sig.db";
+
+// fixed-size records, each keyed by a content's SHA-1 (as in its CmdHead); unused records are
+// blank. the rest of a record is kept as it is, so a round trip doesn't lose anything
+pub const SIG_ENTRY_SIZE: usize = 0x40;
+const HASH_SIZE: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigEntry {
+    pub hash: [u8; HASH_SIZE],
+    pub data: Vec<u8>,
+}
+
+impl SigEntry {
+    // `data` is padded or cut to fit the record
+    pub fn new(hash: [u8; HASH_SIZE], data: &[u8]) -> Self {
+        let mut data = data.to_vec();
+        data.resize(SIG_ENTRY_SIZE - HASH_SIZE, 0);
+        Self { hash, data }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        [&self.hash[..], &self.data].concat()
+    }
+}
+
+fn blank(record: &[u8]) -> bool {
+    record.iter().all(|&b| b == 0) || record.iter().all(|&b| b == 0xFF)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigDb {
+    pub entries: Vec<SigEntry>,
+    // in records; the file keeps its size when it's written back
+    pub capacity: usize,
+}
+
+impl SigDb {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !data.len().is_multiple_of(SIG_ENTRY_SIZE) {
+            return Err(LibBBRDBError::InvalidSigDb(format!(
+                "{:#X} bytes isn't a whole number of {SIG_ENTRY_SIZE:#X}-byte records",
+                data.len()
+            )));
+        }
+
+        let entries = data
+            .chunks_exact(SIG_ENTRY_SIZE)
+            .filter(|r| !blank(r))
+            .map(|r| SigEntry {
+                hash: r[..HASH_SIZE].try_into().unwrap(),
+                data: r[HASH_SIZE..].to_vec(),
+            })
+            .collect();
+
+        Ok(Self {
+            entries,
+            capacity: data.len() / SIG_ENTRY_SIZE,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rv = self
+            .entries
+            .iter()
+            .flat_map(SigEntry::to_bytes)
+            .collect::<Vec<_>>();
+        rv.resize(self.capacity.max(self.entries.len()) * SIG_ENTRY_SIZE, 0);
+        rv
+    }
+
+    pub fn contains(&self, hash: &[u8; HASH_SIZE]) -> bool {
+        self.entries.iter().any(|e| &e.hash == hash)
+    }
+
+    // false if the hash was already there, in which case nothing changes
+    pub fn append(&mut self, entry: SigEntry) -> Result<bool> {
+        if self.contains(&entry.hash) {
+            return Ok(false);
+        }
+        if self.entries.len() >= self.capacity {
+            return Err(LibBBRDBError::InvalidSigDb(format!(
+                "all {} records are in use",
+                self.capacity
+            )));
+        }
+
+        self.entries.push(entry);
+        Ok(true)
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // None if the card has no sig.db at all
+    #[allow(non_snake_case)]
+    pub fn ReadSigDb(&self) -> Result<Option<SigDb>> {
+        self.ReadFile(SIG_DB_FILE)?
+            .map(|d| SigDb::parse(&d))
+            .transpose()
+            .for_file("ReadSigDb", SIG_DB_FILE)
+    }
+
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn WriteSigDb(&mut self, db: &SigDb) -> Result<()> {
+        let params = vec![("entries", db.entries.len().to_string())];
+        self.audited_mut("WriteSigDb", params, |this| {
+            this.check_writable()?;
+
+            this.transaction(|this| {
+                this.stage_file(&db.to_bytes(), SIG_DB_FILE)?;
+                this.update_fs()
+            })
+        })
+        .for_file("WriteSigDb", SIG_DB_FILE)
+    }
+}
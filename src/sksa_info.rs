@@ -0,0 +1,125 @@
+use std::io::Cursor;
+
+use binrw::{binread, BinRead};
+use rusb::UsbContext;
+use sha2::{Digest, Sha256};
+
+use crate::error::*;
+use crate::kernel::{SplitSksa, CMD_HEAD_OFFSET};
+use crate::nand::BlockHash;
+use crate::Handle;
+
+pub(crate) fn c_string(bytes: [u8; 64]) -> String {
+    let s = bytes.split(|&b| b == 0).next().unwrap_or_default();
+    String::from_utf8_lossy(s).into_owned()
+}
+
+// the fields of an SA's content metadata head that tell one release from another
+#[binread]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[br(big)]
+pub struct CmdHead {
+    #[br(pad_before = 4)]
+    pub ca_crl_version: u32,
+    pub cp_crl_version: u32,
+    pub size: u32,
+    pub desc_flags: u32,
+    // SHA-1 of the content
+    #[br(pad_before = 16)]
+    pub hash: [u8; 20],
+    #[br(pad_before = 16)]
+    pub exec_flags: u32,
+    pub hw_access_rights: u32,
+    pub secure_kernel_rights: u32,
+    pub bbid: u32,
+    #[br(map = c_string)]
+    pub issuer: String,
+    pub content_id: u32,
+}
+
+impl CmdHead {
+    pub fn parse(sa: &[u8]) -> Result<Self> {
+        let head = sa
+            .get(CMD_HEAD_OFFSET..)
+            .ok_or_else(|| LibBBRDBError::InvalidSKSA("CmdHead is truncated".to_string()))?;
+        Ok(Self::read(&mut Cursor::new(head))?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaVersion {
+    pub head: CmdHead,
+    // of the SA trimmed to its CmdHead size, as SplitSksa holds it
+    pub sha256: BlockHash,
+    pub known: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SksaVersion {
+    pub sk_sha256: BlockHash,
+    pub sk_known: Option<String>,
+    pub sa1: SaVersion,
+    pub sa2: Option<SaVersion>,
+}
+
+fn lookup(known: &[(&str, BlockHash)], hash: &BlockHash) -> Option<String> {
+    known
+        .iter()
+        .find(|(_, h)| h == hash)
+        .map(|(name, _)| name.to_string())
+}
+
+fn sa_version(sa: &[u8], known: &[(&str, BlockHash)]) -> Result<SaVersion> {
+    let sha256 = Sha256::digest(sa).into();
+    Ok(SaVersion {
+        head: CmdHead::parse(sa)?,
+        known: lookup(known, &sha256),
+        sha256,
+    })
+}
+
+impl SksaVersion {
+    // `known` is a table of (name, SHA-256) covering SKs and trimmed SAs; nothing is built in,
+    // so pass an empty one to go by the CmdHead fields alone
+    pub fn identify(sksa: &SplitSksa, known: &[(&str, BlockHash)]) -> Result<Self> {
+        let sk_sha256 = Sha256::digest(&sksa.sk).into();
+
+        Ok(Self {
+            sk_known: lookup(known, &sk_sha256),
+            sk_sha256,
+            sa1: sa_version(&sksa.sa1, known)?,
+            sa2: sksa
+                .sa2
+                .as_deref()
+                .map(|sa| sa_version(sa, known))
+                .transpose()?,
+        })
+    }
+
+    pub fn from_sksa(sksa: &[u8], known: &[(&str, BlockHash)]) -> Result<Self> {
+        Self::identify(&SplitSksa::from_sksa(sksa)?, known)
+    }
+
+    // the parts that flashing `other` would replace
+    pub fn differences(&self, other: &Self) -> Vec<&'static str> {
+        let mut rv = vec![];
+        if self.sk_sha256 != other.sk_sha256 {
+            rv.push("sk");
+        }
+        if self.sa1.sha256 != other.sa1.sha256 {
+            rv.push("sa1");
+        }
+        if self.sa2.as_ref().map(|s| s.sha256) != other.sa2.as_ref().map(|s| s.sha256) {
+            rv.push("sa2");
+        }
+        rv
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn IdentifySKSA(&self, known: &[(&str, BlockHash)]) -> Result<SksaVersion> {
+        self.guarded(|this| SksaVersion::identify(&this.ReadSplitSKSA()?, known))
+    }
+}
@@ -0,0 +1,76 @@
+use std::io::Cursor;
+
+use binrw::{binrw, BinRead, BinWrite};
+
+use crate::constants::SPARE_SIZE;
+use crate::error::*;
+
+#[binrw]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpareData {
+    pub sa_link: [u8; 3],
+    pad0: [u8; 2],
+    pub bad_block: u8,
+    pad1: [u8; 2],
+    pub ecc1: [u8; 3],
+    pad2: [u8; 2],
+    pub ecc0: [u8; 3],
+}
+
+impl Default for SpareData {
+    fn default() -> Self {
+        Self::blank()
+    }
+}
+
+impl SpareData {
+    pub fn blank() -> Self {
+        Self {
+            sa_link: [0xFF; 3],
+            pad0: [0xFF; 2],
+            bad_block: 0xFF,
+            pad1: [0xFF; 2],
+            ecc1: [0xFF; 3],
+            pad2: [0xFF; 2],
+            ecc0: [0xFF; 3],
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() != SPARE_SIZE {
+            return Err(LibBBRDBError::WrongDataLength);
+        }
+
+        Ok(Self::read_be(&mut Cursor::new(data))?)
+    }
+
+    pub fn to_bytes(&self) -> [u8; SPARE_SIZE] {
+        let mut data = Cursor::new([0; SPARE_SIZE]);
+        self.write_be(&mut data)
+            .expect("writing a fixed-size struct to a fixed-size buffer can't fail");
+        data.into_inner()
+    }
+
+    // the marker is 0xFF on good blocks; tolerate a single flipped bit
+    pub fn is_bad(&self) -> bool {
+        self.bad_block.count_zeros() > 1
+    }
+
+    pub fn set_bad(&mut self, bad: bool) {
+        self.bad_block = if bad { 0x00 } else { 0xFF };
+    }
+
+    // SA blocks store the index of the next block in the chain, repeated for redundancy
+    pub fn sa_link(&self) -> u8 {
+        let [a, b, c] = self.sa_link;
+        if a == b || a == c {
+            a
+        } else {
+            b
+        }
+    }
+
+    pub fn set_sa_link(&mut self, link: u8) {
+        self.sa_link = [link; 3];
+    }
+}
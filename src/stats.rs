@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use rusb::UsbContext;
+
+use crate::Handle;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockAccess {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BlockAccessStats {
+    blocks: BTreeMap<u32, BlockAccess>,
+}
+
+impl BlockAccessStats {
+    pub(crate) fn record_read(&mut self, block: u32) {
+        self.blocks.entry(block).or_default().reads += 1;
+    }
+
+    pub(crate) fn record_write(&mut self, block: u32) {
+        self.blocks.entry(block).or_default().writes += 1;
+    }
+
+    pub fn get(&self, block: u32) -> BlockAccess {
+        self.blocks.get(&block).copied().unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, BlockAccess)> + '_ {
+        self.blocks.iter().map(|(&b, &a)| (b, a))
+    }
+
+    pub fn total_reads(&self) -> u64 {
+        self.blocks.values().map(|a| a.reads).sum()
+    }
+
+    pub fn total_writes(&self) -> u64 {
+        self.blocks.values().map(|a| a.writes).sum()
+    }
+
+    // blocks touched more than once, most accessed first
+    pub fn repeated(&self) -> Vec<(u32, BlockAccess)> {
+        let mut rv = self
+            .iter()
+            .filter(|(_, a)| a.reads + a.writes > 1)
+            .collect::<Vec<_>>();
+        rv.sort_by_key(|(b, a)| (std::cmp::Reverse(a.reads + a.writes), *b));
+        rv
+    }
+
+    // one counter per block, suitable for plotting as a heat map
+    pub fn heat_map(&self, num_blocks: u32) -> Vec<u64> {
+        (0..num_blocks)
+            .map(|b| {
+                let a = self.get(b);
+                a.reads + a.writes
+            })
+            .collect()
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub fn block_access_stats(&self) -> BlockAccessStats {
+        self.access.borrow().clone()
+    }
+
+    pub fn reset_block_access_stats(&self) {
+        *self.access.borrow_mut() = Default::default();
+    }
+}
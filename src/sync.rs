@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use log::debug;
+use rusb::UsbContext;
+
+use crate::constants::{DRAIN_TIMEOUT, RESET_SYNC_TIMEOUT};
+use crate::error::*;
+use crate::rdb::RDBCommand;
+use crate::Handle;
+
+impl<C: UsbContext> Handle<C> {
+    // anything queued up before the console restarted its end is stale now
+    pub(crate) fn acknowledge_sync(&self) -> Result<()> {
+        self.demux.borrow_mut().clear();
+        self.send_rdb_signal(RDBCommand::HostSyncDone)
+    }
+
+    // reads until the console announces itself and acknowledges it; false if `timeout` passes
+    // without it doing so
+    fn await_sync(&self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.read_raw_rdb_packet() {
+                Ok((RDBCommand::DeviceSync, _)) => break,
+                Ok((cmd, _)) => debug!("discarding {cmd:?} while waiting for sync"),
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+        }
+
+        self.acknowledge_sync()?;
+        Ok(true)
+    }
+
+    // waits for a rebooted or reconnected console to announce itself, then brings the
+    // session back to where it was (re-running Init if it had been initialised)
+    #[allow(non_snake_case)]
+    pub fn WaitForSync(&mut self, timeout: Duration) -> Result<()> {
+        if !self.await_sync(timeout)? {
+            return Err(LibBBRDBError::SyncTimeout);
+        }
+
+        if self.initialised() {
+            self.Init()?;
+        }
+
+        Ok(())
+    }
+
+    // gets a session that's lost its place in the protocol going again without replugging:
+    // starts both endpoints over and throws away whatever's in flight. A console that restarted
+    // its end says so, and only then is there a sync to acknowledge; a card that had been read
+    // is read again
+    pub fn reset(&mut self) -> Result<()> {
+        self.clear_halts()?;
+
+        let discarded = self.drain_input(DRAIN_TIMEOUT);
+        if discarded > 0 {
+            debug!("discarded {discarded:#X} bytes while resetting");
+        }
+
+        if !self.await_sync(RESET_SYNC_TIMEOUT)? {
+            debug!("no sync from the console while resetting");
+        }
+
+        if self.initialised() {
+            self.Init()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashSet;
+use std::io::Cursor;
+
+use binrw::{binread, BinRead};
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::sksa_info::CmdHead;
+use crate::Handle;
+
+pub const TICKET_FILE: &str = "ticket.sys";
+
+// the title's content metadata, then the ticket head and its signature
+pub const TICKET_SIZE: usize = 0x2B4C;
+const TICKET_HEAD_OFFSET: usize = 0x29AC;
+const LIMIT_CODE_OFFSET: usize = TICKET_HEAD_OFFSET + 6;
+
+#[binread]
+#[br(big)]
+struct TicketHead {
+    bbid: u32,
+    tid: u16,
+    code: u16,
+    limit: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    pub head: CmdHead,
+    // 0 for a ticket any console can use
+    pub bbid: u32,
+    pub tid: u16,
+    pub code: u16,
+    pub limit: u16,
+    raw: Vec<u8>,
+}
+
+impl Ticket {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() != TICKET_SIZE {
+            return Err(LibBBRDBError::InvalidTicket(format!(
+                "a ticket is {TICKET_SIZE:#X} bytes, not {:#X}",
+                data.len()
+            )));
+        }
+
+        let head = TicketHead::read(&mut Cursor::new(&data[TICKET_HEAD_OFFSET..]))?;
+        Ok(Self {
+            head: CmdHead::parse(data)?,
+            bbid: head.bbid,
+            tid: head.tid,
+            code: head.code,
+            limit: head.limit,
+            raw: data.to_vec(),
+        })
+    }
+
+    pub fn content_id(&self) -> u32 {
+        self.head.content_id
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    // a code of 0 is a permanent ticket; anything else is a trial that runs out after `limit`
+    pub fn set_limit(&mut self, code: u16, limit: u16) {
+        self.raw[LIMIT_CODE_OFFSET..LIMIT_CODE_OFFSET + 4]
+            .copy_from_slice(&[code.to_be_bytes(), limit.to_be_bytes()].concat());
+        self.code = code;
+        self.limit = limit;
+    }
+
+    fn check(&self) -> Result<()> {
+        let id = self.content_id();
+        if self.head.size == 0 {
+            return Err(LibBBRDBError::InvalidTicket(format!(
+                "the ticket for {id:08x} is for empty content"
+            )));
+        }
+        if self.code != 0 && self.limit == 0 {
+            return Err(LibBBRDBError::InvalidTicket(format!(
+                "the trial ticket for {id:08x} has no limit left"
+            )));
+        }
+        Ok(())
+    }
+}
+
+// ticket.sys: a big-endian count, then that many tickets back to back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TicketSys {
+    pub tickets: Vec<Ticket>,
+}
+
+impl TicketSys {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (count, rest) = data
+            .split_first_chunk::<4>()
+            .ok_or_else(|| LibBBRDBError::InvalidTicket("missing ticket count".to_string()))?;
+        let count = u32::from_be_bytes(*count) as usize;
+
+        if rest.len() / TICKET_SIZE < count {
+            return Err(LibBBRDBError::InvalidTicket(format!(
+                "{count} tickets don't fit in {:#X} bytes",
+                data.len()
+            )));
+        }
+
+        Ok(Self {
+            tickets: rest
+                .chunks_exact(TICKET_SIZE)
+                .take(count)
+                .map(Ticket::from_bytes)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rv = Vec::with_capacity(4 + self.tickets.len() * TICKET_SIZE);
+        rv.extend((self.tickets.len() as u32).to_be_bytes());
+        for t in &self.tickets {
+            rv.extend(t.as_bytes());
+        }
+        rv
+    }
+
+    pub fn find(&self, content_id: u32) -> impl Iterator<Item = &Ticket> {
+        self.tickets
+            .iter()
+            .filter(move |t| t.content_id() == content_id)
+    }
+
+    pub fn add(&mut self, ticket: Ticket) -> Result<()> {
+        ticket.check()?;
+        if self.find(ticket.content_id()).next().is_some() {
+            return Err(LibBBRDBError::InvalidTicket(format!(
+                "{:08x} already has a ticket",
+                ticket.content_id()
+            )));
+        }
+
+        self.tickets.push(ticket);
+        Ok(())
+    }
+
+    // swaps in `ticket` for the one with the same content id, handing back the old one
+    pub fn replace(&mut self, ticket: Ticket) -> Result<Ticket> {
+        ticket.check()?;
+        let id = ticket.content_id();
+        match self.tickets.iter_mut().find(|t| t.content_id() == id) {
+            Some(t) => Ok(std::mem::replace(t, ticket)),
+            None => Err(LibBBRDBError::InvalidTicket(format!(
+                "{id:08x} has no ticket to replace"
+            ))),
+        }
+    }
+
+    // returns how many went
+    pub fn remove(&mut self, content_id: u32) -> usize {
+        let before = self.tickets.len();
+        self.tickets.retain(|t| t.content_id() != content_id);
+        before - self.tickets.len()
+    }
+
+    // what add and replace enforce, for a ticket.sys put together some other way
+    pub fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for t in &self.tickets {
+            t.check()?;
+            if !seen.insert(t.content_id()) {
+                return Err(LibBBRDBError::InvalidTicket(format!(
+                    "{:08x} has more than one ticket",
+                    t.content_id()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // None if the card has no ticket.sys at all
+    #[allow(non_snake_case)]
+    pub fn ReadTickets(&self) -> Result<Option<TicketSys>> {
+        self.ReadFile(TICKET_FILE)?
+            .map(|d| TicketSys::parse(&d))
+            .transpose()
+            .for_file("ReadTickets", TICKET_FILE)
+    }
+
+    // replaces ticket.sys in one FAT update, after checking the new one over
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn WriteTickets(&mut self, tickets: &TicketSys) -> Result<()> {
+        let params = vec![("tickets", tickets.tickets.len().to_string())];
+        self.audited_mut("WriteTickets", params, |this| {
+            this.check_writable()?;
+            tickets.validate()?;
+
+            this.transaction(|this| {
+                this.stage_file(&tickets.to_bytes(), TICKET_FILE)?;
+                this.update_fs()
+            })
+        })
+        .for_file("WriteTickets", TICKET_FILE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::CMD_HEAD_OFFSET;
+
+    const SIZE_OFFSET: usize = CMD_HEAD_OFFSET + 0x0C;
+    const CONTENT_ID_OFFSET: usize = CMD_HEAD_OFFSET + 0x98;
+
+    // a ticket with the fields TicketSys looks at filled in, and the rest a pattern that has to
+    // come back untouched
+    fn ticket_bytes(content_id: u32, code: u16, limit: u16) -> Vec<u8> {
+        let mut raw: Vec<u8> = (0..TICKET_SIZE)
+            .map(|i| (i as u8).wrapping_mul(13) ^ content_id as u8)
+            .collect();
+        raw[SIZE_OFFSET..][..4].copy_from_slice(&0x10_0000u32.to_be_bytes());
+        raw[CONTENT_ID_OFFSET..][..4].copy_from_slice(&content_id.to_be_bytes());
+        raw[TICKET_HEAD_OFFSET..][..4].copy_from_slice(&0x0123_4567u32.to_be_bytes());
+        raw[TICKET_HEAD_OFFSET + 4..][..2].copy_from_slice(&(content_id as u16).to_be_bytes());
+        raw[LIMIT_CODE_OFFSET..][..2].copy_from_slice(&code.to_be_bytes());
+        raw[LIMIT_CODE_OFFSET + 2..][..2].copy_from_slice(&limit.to_be_bytes());
+        raw
+    }
+
+    fn ticket(content_id: u32) -> Ticket {
+        Ticket::from_bytes(&ticket_bytes(content_id, 0, 0)).unwrap()
+    }
+
+    fn blob(tickets: &[Vec<u8>]) -> Vec<u8> {
+        let mut rv = (tickets.len() as u32).to_be_bytes().to_vec();
+        for t in tickets {
+            rv.extend(t);
+        }
+        rv
+    }
+
+    #[test]
+    fn ticket_sys_round_trips_byte_for_byte() {
+        let raw = blob(&[
+            ticket_bytes(0x0010_1001, 0, 0),
+            ticket_bytes(0x0010_2002, 5, 60),
+            ticket_bytes(0x0010_3003, 0, 0),
+        ]);
+
+        let sys = TicketSys::parse(&raw).unwrap();
+        assert_eq!(sys.tickets.len(), 3);
+        assert_eq!(sys.tickets[1].content_id(), 0x0010_2002);
+        assert_eq!(sys.tickets[1].bbid, 0x0123_4567);
+        assert_eq!(sys.tickets[1].tid, 0x2002);
+        assert_eq!((sys.tickets[1].code, sys.tickets[1].limit), (5, 60));
+        assert_eq!(sys.to_bytes(), raw);
+
+        assert_eq!(TicketSys::parse(&blob(&[])).unwrap().to_bytes(), blob(&[]));
+    }
+
+    #[test]
+    fn wrong_sizes_are_rejected() {
+        let raw = ticket_bytes(0x0010_1001, 0, 0);
+        assert!(Ticket::from_bytes(&raw[1..]).is_err());
+        assert!(Ticket::from_bytes(&[raw.as_slice(), &[0]].concat()).is_err());
+
+        assert!(TicketSys::parse(&[0, 0, 0]).is_err());
+        // counts more tickets than there are
+        let mut short = blob(&[raw.clone(), raw.clone()]);
+        short.truncate(short.len() - 1);
+        assert!(TicketSys::parse(&short).is_err());
+    }
+
+    #[test]
+    fn add_replace_and_remove() {
+        let mut sys = TicketSys::default();
+        sys.add(ticket(1)).unwrap();
+        sys.add(ticket(2)).unwrap();
+        assert!(sys.add(ticket(1)).is_err());
+
+        let mut trial = ticket(2);
+        trial.set_limit(5, 30);
+        let old = sys.replace(trial).unwrap();
+        assert_eq!((old.code, old.limit), (0, 0));
+        assert_eq!(sys.find(2).next().unwrap().limit, 30);
+        // set_limit goes into the bytes that get written back, not just the fields
+        let reparsed = TicketSys::parse(&sys.to_bytes()).unwrap();
+        assert_eq!(reparsed, sys);
+        assert!(sys.replace(ticket(3)).is_err());
+
+        assert_eq!(sys.remove(1), 1);
+        assert_eq!(sys.remove(1), 0);
+        assert_eq!(sys.tickets.len(), 1);
+    }
+
+    #[test]
+    fn validate_catches_what_add_would_refuse() {
+        let mut expired = ticket(2);
+        expired.set_limit(5, 0);
+        assert!(TicketSys::default().add(expired.clone()).is_err());
+
+        let sys = TicketSys {
+            tickets: vec![ticket(1), expired],
+        };
+        assert!(sys.validate().is_err());
+
+        let empty = Ticket::from_bytes(&{
+            let mut raw = ticket_bytes(3, 0, 0);
+            raw[SIZE_OFFSET..][..4].fill(0);
+            raw
+        })
+        .unwrap();
+        assert!(TicketSys {
+            tickets: vec![empty]
+        }
+        .validate()
+        .is_err());
+
+        let sys = TicketSys {
+            tickets: vec![ticket(1), ticket(1)],
+        };
+        assert!(sys.validate().is_err());
+
+        let sys = TicketSys {
+            tickets: vec![ticket(1), ticket(2)],
+        };
+        assert!(sys.validate().is_ok());
+    }
+}
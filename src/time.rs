@@ -0,0 +1,112 @@
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+use rusb::UsbContext;
+
+use crate::commands::Command;
+use crate::error::*;
+use crate::rdb::RDBCommand;
+use crate::Handle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleTime {
+    pub year: u8,
+    pub month: u8,
+    pub day: u8,
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl ConsoleTime {
+    pub fn from_datetime<Tz: TimeZone>(when: &DateTime<Tz>) -> Result<Self> {
+        // the RTC only holds two year digits
+        if !(2000..=2099).contains(&when.year()) {
+            return Err(LibBBRDBError::InvalidTime(format!(
+                "year {} is outside 2000-2099",
+                when.year()
+            )));
+        }
+
+        Ok(Self {
+            year: (when.year() % 100) as u8,
+            month: when.month() as u8,
+            day: when.day() as u8,
+            weekday: when.weekday() as u8,
+            hour: when.hour() as u8,
+            minute: when.minute() as u8,
+            second: when.second() as u8,
+        })
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let checks = [
+            ("year", self.year, 0, 99),
+            ("month", self.month, 1, 12),
+            ("day", self.day, 1, 31),
+            ("weekday", self.weekday, 0, 6),
+            ("hour", self.hour, 0, 23),
+            ("minute", self.minute, 0, 59),
+            ("second", self.second, 0, 59),
+        ];
+
+        for (name, value, min, max) in checks {
+            if !(min..=max).contains(&value) {
+                return Err(LibBBRDBError::InvalidTime(format!(
+                    "{name} {value} is outside {min}-{max}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        [
+            self.year,
+            self.month,
+            self.day,
+            self.weekday,
+            0,
+            self.hour,
+            self.minute,
+            self.second,
+        ]
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn SetTime<Tz: TimeZone>(&mut self, when: DateTime<Tz>) -> Result<ConsoleTime> {
+        let time = ConsoleTime::from_datetime(&when)?;
+        time.validate()?;
+
+        self.SetTimeRaw(time)?;
+
+        Ok(time)
+    }
+
+    #[allow(non_snake_case)]
+    pub fn SetTimeRaw(&mut self, time: ConsoleTime) -> Result<()> {
+        self.audited_mut("SetTime", vec![("time", format!("{time:?}"))], |this| {
+            let timedata = time.to_bytes();
+
+            let status = this.command_response(Command::SetTime, &timedata[..4], 1)?[0] as i32;
+            if status < 0 {
+                Err(LibBBRDBError::SetTime(status))
+            } else {
+                this.write_data(RDBCommand::HostData, &timedata[4..])?;
+                Ok(())
+            }
+        })
+    }
+
+    // none of the known RDB commands reads the RTC back, so there's nothing to probe yet
+    pub fn supports_get_time(&self) -> bool {
+        false
+    }
+
+    #[allow(non_snake_case)]
+    pub fn GetTime(&self) -> Result<ConsoleTime> {
+        Err(LibBBRDBError::Unsupported("reading the console time"))
+    }
+}
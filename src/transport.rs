@@ -0,0 +1,280 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use log::warn;
+use rusb::{DeviceHandle, UsbContext};
+use serde_json::{json, Value};
+
+use crate::audit::{from_hex, to_hex};
+use crate::constants::{RDB_BULK_EP_IN, RDB_BULK_EP_OUT, RDB_INTERFACE};
+use crate::error::*;
+use crate::queue::read_bulk_queued;
+#[cfg(test)]
+use crate::virtual_console::VirtualConsole;
+use crate::Handle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Transfer {
+    Out(u8, Vec<u8>),
+    In(u8, rusb::Result<Vec<u8>>),
+}
+
+fn error_name(e: rusb::Error) -> String {
+    format!("{e:?}")
+}
+
+fn error_from_name(name: &str) -> rusb::Error {
+    match name {
+        "Io" => rusb::Error::Io,
+        "InvalidParam" => rusb::Error::InvalidParam,
+        "Access" => rusb::Error::Access,
+        "NoDevice" => rusb::Error::NoDevice,
+        "NotFound" => rusb::Error::NotFound,
+        "Busy" => rusb::Error::Busy,
+        "Timeout" => rusb::Error::Timeout,
+        "Overflow" => rusb::Error::Overflow,
+        "Pipe" => rusb::Error::Pipe,
+        "Interrupted" => rusb::Error::Interrupted,
+        "NoMem" => rusb::Error::NoMem,
+        "NotSupported" => rusb::Error::NotSupported,
+        "BadDescriptor" => rusb::Error::BadDescriptor,
+        _ => rusb::Error::Other,
+    }
+}
+
+impl Transfer {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Out(ep, data) => json!({ "dir": "out", "ep": ep, "data": to_hex(data) }),
+            Self::In(ep, Ok(data)) => json!({ "dir": "in", "ep": ep, "data": to_hex(data) }),
+            Self::In(ep, Err(e)) => json!({ "dir": "in", "ep": ep, "error": error_name(*e) }),
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let ep = value["ep"].as_u64()? as u8;
+        let data = || value["data"].as_str().and_then(from_hex);
+
+        match value["dir"].as_str()? {
+            "out" => Some(Self::Out(ep, data()?)),
+            "in" => match value["error"].as_str() {
+                Some(e) => Some(Self::In(ep, Err(error_from_name(e)))),
+                None => Some(Self::In(ep, Ok(data()?))),
+            },
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct Recorder {
+    out: Box<dyn Write + Send>,
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Replayer {
+    transfers: RefCell<VecDeque<Transfer>>,
+}
+
+impl Replayer {
+    fn next(&self) -> rusb::Result<Transfer> {
+        self.transfers
+            .borrow_mut()
+            .pop_front()
+            .ok_or(rusb::Error::NoDevice)
+    }
+
+    fn write_bulk(&self, endpoint: u8, data: &[u8]) -> rusb::Result<usize> {
+        match self.next()? {
+            Transfer::Out(ep, expected) if ep == endpoint && expected == data => Ok(data.len()),
+            t => {
+                warn!("replay diverged: sent {data:02X?} to {endpoint:02X}, capture has {t:?}");
+                Err(rusb::Error::Other)
+            }
+        }
+    }
+
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8]) -> rusb::Result<usize> {
+        match self.next()? {
+            Transfer::In(ep, Ok(data)) if ep == endpoint => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+            Transfer::In(ep, Err(e)) if ep == endpoint => Err(e),
+            t => {
+                warn!("replay diverged: read from {endpoint:02X}, capture has {t:?}");
+                Err(rusb::Error::Other)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Transport<C: UsbContext> {
+    Usb(DeviceHandle<C>),
+    Replay(Replayer),
+    #[cfg(test)]
+    Virtual(VirtualConsole),
+}
+
+impl<C: UsbContext> Transport<C> {
+    fn release(&self, reattach: bool) -> rusb::Result<()> {
+        match self {
+            // the driver gets the interface back even if letting go of it failed
+            Self::Usb(h) => {
+                let released = h.release_interface(RDB_INTERFACE);
+                let reattached = if reattach {
+                    h.attach_kernel_driver(RDB_INTERFACE)
+                } else {
+                    Ok(())
+                };
+                released.and(reattached)
+            }
+            Self::Replay(_) => Ok(()),
+            #[cfg(test)]
+            Self::Virtual(_) => Ok(()),
+        }
+    }
+
+    fn clear_halt(&self, endpoint: u8) -> rusb::Result<()> {
+        match self {
+            Self::Usb(h) => h.clear_halt(endpoint),
+            Self::Replay(_) => Ok(()),
+            #[cfg(test)]
+            Self::Virtual(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn write_bulk(
+        &self,
+        endpoint: u8,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        match self {
+            Self::Usb(h) => h.write_bulk(endpoint, data, timeout),
+            Self::Replay(r) => r.write_bulk(endpoint, data),
+            #[cfg(test)]
+            Self::Virtual(v) => v.write_bulk(data),
+        }
+    }
+
+    // a capture holds the whole read as one transfer, so a replay needs no queue
+    pub(crate) fn read_bulk_queued(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        depth: usize,
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        match self {
+            Self::Usb(h) => read_bulk_queued(h, endpoint, buf, depth, timeout),
+            Self::Replay(r) => r.read_bulk(endpoint, buf),
+            #[cfg(test)]
+            Self::Virtual(v) => v.read_bulk(buf),
+        }
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    // a capture is one JSON object per line, as written by record_transfers_to
+    pub fn replay<R: BufRead>(capture: R) -> Result<Self> {
+        let mut transfers = VecDeque::new();
+
+        for line in capture.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let value = serde_json::from_str(&line).map_err(std::io::Error::from)?;
+            transfers
+                .push_back(Transfer::from_json(&value).ok_or(LibBBRDBError::InvalidCapture(line))?);
+        }
+
+        Ok(Self::with_transport(Transport::Replay(Replayer {
+            transfers: RefCell::new(transfers),
+        })))
+    }
+
+    pub fn replay_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::replay(BufReader::new(File::open(path)?))
+    }
+
+    pub fn record_transfers_to<W: Write + Send + 'static>(&self, out: W) {
+        *self.recorder.borrow_mut() = Some(Recorder { out: Box::new(out) });
+    }
+
+    pub fn record_transfers_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.record_transfers_to(BufWriter::new(File::create(path)?));
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) -> Result<()> {
+        if let Some(mut r) = self.recorder.borrow_mut().take() {
+            r.out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn record_transfer(&self, transfer: Transfer) {
+        let mut recorder = self.recorder.borrow_mut();
+        if let Some(r) = recorder.as_mut() {
+            if let Err(e) = writeln!(r.out, "{}", transfer.to_json()) {
+                warn!("transfer recording stopped: {e}");
+                *recorder = None;
+            }
+        }
+    }
+
+    pub(crate) fn release_transport(&self) -> Result<()> {
+        Ok(self.handle.release(self.kernel_driver_detached)?)
+    }
+
+    pub(crate) fn clear_halts(&self) -> Result<()> {
+        self.handle.clear_halt(RDB_BULK_EP_IN)?;
+        self.handle.clear_halt(RDB_BULK_EP_OUT)?;
+        Ok(())
+    }
+
+    pub(crate) fn transport_write(
+        &self,
+        endpoint: u8,
+        data: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let rv = self.handle.write_bulk(endpoint, data, timeout);
+        if self.recorder.borrow().is_some() {
+            let sent = rv.as_ref().map_or(&data[..0], |&n| &data[..n]);
+            self.record_transfer(Transfer::Out(endpoint, sent.to_vec()));
+        }
+        rv
+    }
+
+    pub(crate) fn transport_read(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let rv = self
+            .handle
+            .read_bulk_queued(endpoint, buf, self.queue_depth, timeout);
+        if self.recorder.borrow().is_some() {
+            let got = rv.map(|n| buf[..n].to_vec());
+            self.record_transfer(Transfer::In(endpoint, got));
+        }
+        rv
+    }
+}
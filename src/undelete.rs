@@ -0,0 +1,74 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::fs::BlockIndex;
+use crate::require_fat;
+use crate::Handle;
+
+// a file an older FAT generation still has, but the current one doesn't
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeletedFile {
+    pub name: String,
+    pub size: usize,
+    // the newest generation it's still in; pass this to RecoverFile
+    pub seqno: u32,
+    pub blocks: Vec<BlockIndex>,
+    // the chain is whole, none of its blocks have been reused and the name is free
+    pub recoverable: bool,
+}
+
+impl<C: UsbContext> Handle<C> {
+    #[allow(non_snake_case)]
+    pub fn ListDeletedFiles(&self) -> Result<Vec<DeletedFile>> {
+        self.audited("ListDeletedFiles", vec![], |this| {
+            let cardsize = this.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+            let generations = this.fat_generations(cardsize)?;
+
+            require_fat!(this, _p, current {
+                let mut rv: Vec<DeletedFile> = vec![];
+
+                // newest first, so each file is reported from the last generation that had it
+                for fat in generations.iter().filter(|f| f.seqno() < current.seqno()) {
+                    for file in fat.deleted_files(current) {
+                        if !rv.iter().any(|d| d.name == file.name && d.blocks == file.blocks) {
+                            rv.push(file);
+                        }
+                    }
+                }
+
+                Ok(rv)
+            })
+        })
+    }
+
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn RecoverFile(&mut self, filename: &str, seqno: u32) -> Result<()> {
+        let params = vec![
+            ("filename", filename.to_string()),
+            ("seqno", seqno.to_string()),
+        ];
+        self.audited_mut("RecoverFile", params, |this| {
+            this.check_writable()?;
+
+            let cardsize = this.card_size().ok_or(LibBBRDBError::NotInitialised)?;
+            let older = this
+                .fat_generations(cardsize)?
+                .into_iter()
+                .find(|f| f.seqno() == seqno)
+                .ok_or_else(|| {
+                    LibBBRDBError::NotRecoverable(
+                        filename.to_string(),
+                        format!("FAT generation {seqno} is no longer on the card"),
+                    )
+                })?;
+
+            this.transaction(|this| {
+                require_fat!(mut this, _p, fat { fat.restore_from(&older, filename) })?;
+                this.update_fs()
+            })
+        })
+        .for_file("RecoverFile", filename)
+    }
+}
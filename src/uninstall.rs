@@ -0,0 +1,100 @@
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::saves::{content_file, CONTENT_EXTENSIONS, SAVE_EXTENSIONS};
+use crate::ticket::TicketSys;
+#[cfg(feature = "writing")]
+use crate::ticket::TICKET_FILE;
+use crate::Handle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UninstallPlan {
+    pub content_id: u32,
+    // content first, then any saves going with it
+    pub files: Vec<String>,
+    // ticket.sys entries for the title
+    pub tickets: usize,
+    // saves that stay behind because they weren't asked for
+    pub kept_saves: Vec<String>,
+}
+
+impl UninstallPlan {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.tickets == 0
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    fn uninstall_plan(&self, content_id: u32, saves: bool) -> Result<(UninstallPlan, TicketSys)> {
+        let mut plan = UninstallPlan {
+            content_id,
+            files: vec![],
+            tickets: 0,
+            kept_saves: vec![],
+        };
+
+        let mut files = self
+            .ListFiles()?
+            .into_iter()
+            .filter_map(|(name, _)| match content_file(&name) {
+                Some((id, ext)) if id == content_id => Some((name, ext)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(_, ext)| !CONTENT_EXTENSIONS.contains(&ext.as_str()));
+
+        for (name, ext) in files {
+            if !SAVE_EXTENSIONS.contains(&ext.as_str()) || saves {
+                plan.files.push(name);
+            } else {
+                plan.kept_saves.push(name);
+            }
+        }
+
+        let mut tickets = self.ReadTickets()?.unwrap_or_default();
+        plan.tickets = tickets.remove(content_id);
+
+        Ok((plan, tickets))
+    }
+
+    // what UninstallTitle would do, without touching the card
+    #[allow(non_snake_case)]
+    pub fn PlanUninstall(&self, content_id: u32, saves: bool) -> Result<UninstallPlan> {
+        Ok(self.uninstall_plan(content_id, saves)?.0)
+    }
+
+    // deletes the title's files and drops its tickets from ticket.sys in one FAT update, so the
+    // card never has tickets for content that's gone or the other way round
+    #[cfg(feature = "writing")]
+    #[allow(non_snake_case)]
+    pub fn UninstallTitle(&mut self, content_id: u32, saves: bool) -> Result<UninstallPlan> {
+        let params = vec![
+            ("content_id", format!("{content_id:08x}")),
+            ("saves", saves.to_string()),
+        ];
+        self.audited_mut("UninstallTitle", params, |this| {
+            this.check_writable()?;
+
+            let (plan, tickets) = this.uninstall_plan(content_id, saves)?;
+            if plan.is_empty() {
+                return Err(LibBBRDBError::FileNotFound(format!("{content_id:08x}.app")));
+            }
+
+            this.transaction(|this| {
+                // before any deletes, so the new ticket.sys can't land on blocks the current
+                // generation still uses
+                if plan.tickets > 0 {
+                    this.stage_file(&tickets.to_bytes(), TICKET_FILE)?;
+                }
+                for name in &plan.files {
+                    this.delete_file(name)?;
+                }
+                this.update_fs()
+            })?;
+
+            Ok(plan)
+        })
+        .in_operation("UninstallTitle")
+    }
+}
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use log::trace;
 use rusb::{Device, DeviceHandle, DeviceList, GlobalContext, UsbContext};
 
 use crate::{
@@ -8,12 +9,13 @@ use crate::{
         RDB_INTERFACE, RDB_VENDOR_ID,
     },
     error::*,
-    Handle,
+    Handle, RetryPolicy,
 };
 
 pub type GlobalHandle = Handle<GlobalContext>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RDBType {
     Retail,
     Emsmon,
@@ -41,6 +43,116 @@ pub fn scan_devices() -> Result<Vec<Device<GlobalContext>>> {
     scan_devices_in(GlobalContext::default())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceStrategy {
+    // pick the device if there's exactly one, otherwise hand back the candidates
+    Single,
+    First,
+    // position in scan order, counting from 0
+    Index(usize),
+    BusAddress(u8, u8),
+    Bbid(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceCandidate<C: UsbContext> {
+    pub device: Device<C>,
+    pub kind: RDBType,
+    pub bus: u8,
+    pub address: u8,
+    pub bbid: Option<u32>,
+}
+
+impl<C: UsbContext> DeviceCandidate<C> {
+    fn new(device: Device<C>) -> Self {
+        Self {
+            kind: bbp_type(&device).unwrap_or(RDBType::Unknown),
+            bus: device.bus_number(),
+            address: device.address(),
+            bbid: None,
+            device,
+        }
+    }
+
+    fn probe_bbid(&mut self) {
+        self.bbid = Handle::new(&self.device)
+            .and_then(|mut h| {
+                h.set_retry_policy(RetryPolicy::none());
+                h.GetBBID()
+            })
+            .ok();
+    }
+
+    pub fn label(&self) -> String {
+        let bbid = match self.bbid {
+            Some(b) => format!("BBID {b:08X}"),
+            None => "BBID unknown".to_string(),
+        };
+        format!(
+            "bus {:03} device {:03}: {:?} ({bbid})",
+            self.bus, self.address, self.kind
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum DeviceChoice<C: UsbContext> {
+    Chosen(Device<C>),
+    Ambiguous(Vec<DeviceCandidate<C>>),
+}
+
+fn no_device_hint() -> &'static str {
+    if cfg!(target_os = "linux") {
+        "check the console is powered on and connected, and that a udev rule grants access to it"
+    } else if cfg!(target_os = "windows") {
+        "check the console is powered on and connected, and that the WinUSB driver is installed for it (e.g. with Zadig)"
+    } else {
+        "check the console is powered on and connected"
+    }
+}
+
+pub fn choose_device_in<C: UsbContext>(
+    context: C,
+    strategy: DeviceStrategy,
+) -> Result<DeviceChoice<C>> {
+    let mut candidates = scan_devices_in(context)?
+        .into_iter()
+        .map(DeviceCandidate::new)
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return Err(LibBBRDBError::NoDeviceFound(no_device_hint().to_string()));
+    }
+
+    let chosen = match strategy {
+        DeviceStrategy::Single if candidates.len() == 1 => candidates.pop(),
+        DeviceStrategy::Single => {
+            candidates.iter_mut().for_each(DeviceCandidate::probe_bbid);
+            return Ok(DeviceChoice::Ambiguous(candidates));
+        }
+        DeviceStrategy::First => candidates.into_iter().next(),
+        DeviceStrategy::Index(i) => candidates.into_iter().nth(i),
+        DeviceStrategy::BusAddress(bus, address) => candidates
+            .into_iter()
+            .find(|c| c.bus == bus && c.address == address),
+        DeviceStrategy::Bbid(bbid) => candidates.into_iter().find_map(|mut c| {
+            c.probe_bbid();
+            (c.bbid == Some(bbid)).then_some(c)
+        }),
+    };
+
+    match chosen {
+        Some(c) => Ok(DeviceChoice::Chosen(c.device)),
+        None => Err(LibBBRDBError::NoDeviceFound(format!(
+            "no connected console matched {strategy:?}"
+        ))),
+    }
+}
+
+pub fn choose_device(strategy: DeviceStrategy) -> Result<DeviceChoice<GlobalContext>> {
+    choose_device_in(GlobalContext::default(), strategy)
+}
+
 fn is_correct_descriptor<C: UsbContext>(device: &Device<C>) -> Result<bool> {
     match device.active_config_descriptor() {
         Ok(d) => Ok(d.number() == RDB_CONF_DESCRIPTOR),
@@ -48,11 +160,14 @@ fn is_correct_descriptor<C: UsbContext>(device: &Device<C>) -> Result<bool> {
     }
 }
 
-pub(crate) fn open_device<C: UsbContext>(device: &Device<C>) -> Result<DeviceHandle<C>> {
+// also says whether a kernel driver had to be detached, so closing can give the interface back
+pub(crate) fn open_device<C: UsbContext>(device: &Device<C>) -> Result<(DeviceHandle<C>, bool)> {
     let handle = device.open()?;
 
-    #[cfg(not(target_os = "windows"))]
-    if rusb::supports_detach_kernel_driver() && handle.kernel_driver_active(RDB_INTERFACE)? {
+    let detached = cfg!(not(target_os = "windows"))
+        && rusb::supports_detach_kernel_driver()
+        && handle.kernel_driver_active(RDB_INTERFACE)?;
+    if detached {
         handle.detach_kernel_driver(RDB_INTERFACE)?;
     }
 
@@ -69,24 +184,30 @@ pub(crate) fn open_device<C: UsbContext>(device: &Device<C>) -> Result<DeviceHan
         return Err(LibBBRDBError::IncorrectDescriptor);
     }
 
-    Ok(handle)
+    Ok((handle, detached))
 }
 
 impl<C: UsbContext> Handle<C> {
     pub(crate) fn bulk_transfer_send(&self, data: &[u8], timeout: Duration) -> Result<usize> {
-        //println!("raw send: {data:02X?}");
-        wrap_libusb_error(self.handle.write_bulk(RDB_BULK_EP_OUT, data, timeout))
+        trace!("raw send: {data:02X?}");
+        wrap_libusb_error(self.transport_write(RDB_BULK_EP_OUT, data, timeout))
     }
 
     pub(crate) fn bulk_transfer_receive(&self, len: usize, timeout: Duration) -> Result<Vec<u8>> {
         let mut buf = vec![0; len];
+        let n = self.bulk_transfer_receive_into(&mut buf, timeout)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
 
-        match self.handle.read_bulk(RDB_BULK_EP_IN, &mut buf, timeout) {
-            Ok(n) => {
-                //println!("recv {:x?}", &buf[..n]);
-                Ok(buf[..n].to_vec())
-            }
-            Err(e) => Err(e.into()),
-        }
+    // fills as much of `buf` as the console sends, without allocating
+    pub(crate) fn bulk_transfer_receive_into(
+        &self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        let n = wrap_libusb_error(self.transport_read(RDB_BULK_EP_IN, buf, timeout))?;
+        trace!("recv {:x?}", &buf[..n]);
+        Ok(n)
     }
 }
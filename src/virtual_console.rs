@@ -0,0 +1,506 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use rusb::UsbContext;
+
+use crate::commands::Command;
+use crate::fs::fix_fat_checksum;
+use crate::layout::CardLayout;
+use crate::progress::NoProgress;
+use crate::rdb::{to_u32, RDBCommand};
+use crate::transport::Transport;
+use crate::Handle;
+
+// a card that lives in memory; blocks that have never been written read back erased
+#[derive(Debug, Clone)]
+pub(crate) struct VirtualCard {
+    layout: CardLayout,
+    num_blocks: u32,
+    blocks: HashMap<u32, (Vec<u8>, Vec<u8>)>,
+}
+
+impl VirtualCard {
+    // one empty FAT generation in slot 0, with the SKSA and FAT areas reserved
+    pub(crate) fn formatted(layout: CardLayout, num_blocks: u32) -> Self {
+        let files = layout.file_area(num_blocks);
+
+        let mut fs = vec![0; layout.block_size];
+        for b in 0..num_blocks.min(0x1000) {
+            let entry: u16 = if files.contains(&b) { 0x0000 } else { 0xFFFD };
+            fs[b as usize * 2..][..2].copy_from_slice(&entry.to_be_bytes());
+        }
+        let footer = &mut fs[layout.block_size - 12..];
+        footer[..4].copy_from_slice(b"BBFS");
+        footer[4..8].copy_from_slice(&1u32.to_be_bytes());
+        fix_fat_checksum(&mut fs);
+
+        let mut card = Self {
+            layout,
+            num_blocks,
+            blocks: HashMap::new(),
+        };
+        card.program(layout.fat_block(num_blocks, 0), fs, card.erased_spare());
+        card
+    }
+
+    fn erased_spare(&self) -> Vec<u8> {
+        vec![0xFF; self.layout.spare_size]
+    }
+
+    fn read(&self, block: u32) -> (Vec<u8>, Vec<u8>) {
+        self.blocks
+            .get(&block)
+            .cloned()
+            .unwrap_or_else(|| (vec![0xFF; self.layout.block_size], self.erased_spare()))
+    }
+
+    fn program(&mut self, block: u32, data: Vec<u8>, spare: Vec<u8>) {
+        self.blocks.insert(block, (data, spare));
+    }
+}
+
+// where a card write goes wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    // the command never reaches the card
+    Lost,
+    // the power goes with half the block programmed
+    Torn,
+    // the block is written, but the answer never comes back
+    Unanswered,
+}
+
+// what the console will do with the next complete message from the host
+#[derive(Debug)]
+enum Expect {
+    Command,
+    Data(Command, u32),
+    Spare(u32, Vec<u8>),
+    Name(usize),
+    Checksum,
+}
+
+#[derive(Debug)]
+struct Console {
+    card: VirtualCard,
+    bbid: u32,
+    input: Vec<u8>,
+    message: Vec<u8>,
+    expect: Expect,
+    output: VecDeque<u8>,
+    // card writes so far, counting the one a fault stopped
+    writes: usize,
+    fault: Option<(usize, Fault)>,
+    // after a fault nothing more is heard from it
+    dead: bool,
+}
+
+// answers the block commands, and what Init asks, the way the console does; with a fault
+// planned, the nth card write (counting from 0) goes wrong and the console falls silent
+#[derive(Debug)]
+pub(crate) struct VirtualConsole {
+    console: RefCell<Console>,
+}
+
+const COMMANDS: [Command; 8] = [
+    Command::SetSeqNo,
+    Command::GetNumBlocks,
+    Command::GetBBID,
+    Command::ReadBlock,
+    Command::ReadBlockAndSpare,
+    Command::WriteBlock,
+    Command::WriteBlockAndSpare,
+    Command::ChksumFile,
+];
+
+impl Console {
+    fn packet(&mut self, cmd: RDBCommand, data: &[u8]) {
+        let mut body = [0; 3];
+        body[..data.len()].copy_from_slice(data);
+
+        self.output.push_back(((cmd as u8) << 2) | data.len() as u8);
+        self.output.extend(body);
+    }
+
+    fn ready(&mut self) {
+        self.packet(RDBCommand::DeviceReadyForData, &[]);
+    }
+
+    fn send_data(&mut self, data: &[u8]) {
+        self.packet(
+            RDBCommand::DeviceDataCT,
+            &(data.len() as u32).to_be_bytes()[1..],
+        );
+        for chunk in data.chunks(3) {
+            self.packet(RDBCommand::DeviceData, chunk);
+        }
+    }
+
+    fn respond(&mut self, command: Command, values: &[u32]) {
+        let mut data = (255 - command as u32).to_be_bytes().to_vec();
+        for v in values {
+            data.extend(v.to_be_bytes());
+        }
+        self.send_data(&data);
+    }
+
+    fn expecting(&self) -> usize {
+        match self.expect {
+            Expect::Command | Expect::Checksum => 8,
+            Expect::Data(..) => self.card.layout.block_size,
+            Expect::Spare(..) => self.card.layout.spare_size,
+            Expect::Name(len) => len,
+        }
+    }
+
+    // splits what the host has sent into packets, and acts on each message once it's complete
+    fn receive(&mut self, data: &[u8]) {
+        self.input.extend(data);
+
+        loop {
+            let Some(&head) = self.input.first() else {
+                return;
+            };
+            // the host sends packet types by their discriminants, which decoding doesn't mirror
+            let cmd = head >> 2;
+            let (skip, len) = if cmd == RDBCommand::HostDataB as u8 {
+                match self.input.get(1) {
+                    Some(&len) => (2, len as usize),
+                    None => return,
+                }
+            } else {
+                (1, (head & 3) as usize)
+            };
+            if self.input.len() < skip + len {
+                return;
+            }
+
+            let payload = self
+                .input
+                .drain(..skip + len)
+                .skip(skip)
+                .collect::<Vec<_>>();
+            // acknowledgements need no answer
+            if cmd == RDBCommand::HostData as u8 || cmd == RDBCommand::HostDataB as u8 {
+                self.message.extend(payload);
+            }
+
+            while !self.dead && self.message.len() >= self.expecting() {
+                let message = self.message.drain(..self.expecting()).collect();
+                self.handle(message);
+            }
+        }
+    }
+
+    fn handle(&mut self, message: Vec<u8>) {
+        let expect = std::mem::replace(&mut self.expect, Expect::Command);
+
+        match expect {
+            Expect::Command => {
+                let number = to_u32(&message[..4]);
+                let arg = to_u32(&message[4..]);
+                match COMMANDS.into_iter().find(|&c| c as u32 == number) {
+                    Some(c) => self.command(c, arg),
+                    // unknown commands go unanswered
+                    None => self.dead = true,
+                }
+            }
+            Expect::Data(Command::WriteBlock, block) => {
+                let spare = self.card.erased_spare();
+                self.write(Command::WriteBlock, block, message, spare);
+            }
+            Expect::Data(_, block) => {
+                self.expect = Expect::Spare(block, message);
+                self.ready();
+            }
+            Expect::Spare(block, data) => {
+                self.write(Command::WriteBlockAndSpare, block, data, message);
+            }
+            Expect::Name(_) => {
+                self.expect = Expect::Checksum;
+                self.ready();
+            }
+            // the files aren't looked at, so no sum ever matches and the host always writes
+            Expect::Checksum => {
+                self.respond(Command::ChksumFile, &[u32::MAX]);
+                self.ready();
+            }
+        }
+    }
+
+    fn command(&mut self, command: Command, arg: u32) {
+        match command {
+            Command::SetSeqNo => self.respond(command, &[1]),
+            Command::GetNumBlocks => self.respond(command, &[self.card.num_blocks]),
+            Command::GetBBID => self.respond(command, &[self.bbid]),
+            Command::ReadBlock | Command::ReadBlockAndSpare => {
+                let (data, spare) = self.card.read(arg);
+                self.respond(command, &[0]);
+                self.send_data(&data);
+                if command == Command::ReadBlockAndSpare {
+                    self.send_data(&spare);
+                }
+            }
+            Command::WriteBlock | Command::WriteBlockAndSpare => {
+                self.expect = Expect::Data(command, arg);
+            }
+            Command::ChksumFile => {
+                self.expect = Expect::Name((arg as usize + 3) & !3);
+            }
+            _ => unreachable!(),
+        }
+        self.ready();
+    }
+
+    fn write(&mut self, command: Command, block: u32, mut data: Vec<u8>, spare: Vec<u8>) {
+        let fault = self
+            .fault
+            .filter(|&(n, _)| n == self.writes)
+            .map(|(_, f)| f);
+        self.writes += 1;
+
+        match fault {
+            None => {
+                self.card.program(block, data, spare);
+                self.respond(command, &[0]);
+                self.ready();
+            }
+            Some(Fault::Lost) => self.dead = true,
+            Some(Fault::Torn) => {
+                let half = data.len() / 2;
+                data[half..].fill(0xFF);
+                let spare = self.card.erased_spare();
+                self.card.program(block, data, spare);
+                self.dead = true;
+            }
+            Some(Fault::Unanswered) => {
+                self.card.program(block, data, spare);
+                self.dead = true;
+            }
+        }
+    }
+}
+
+impl VirtualConsole {
+    pub(crate) fn new(card: VirtualCard, fault: Option<(usize, Fault)>) -> Self {
+        let mut console = Console {
+            card,
+            bbid: 0x1234,
+            input: vec![],
+            message: vec![],
+            expect: Expect::Command,
+            output: VecDeque::new(),
+            writes: 0,
+            fault,
+            dead: false,
+        };
+        console.ready();
+
+        Self {
+            console: RefCell::new(console),
+        }
+    }
+
+    pub(crate) fn card(&self) -> VirtualCard {
+        self.console.borrow().card.clone()
+    }
+
+    pub(crate) fn writes(&self) -> usize {
+        self.console.borrow().writes
+    }
+
+    pub(crate) fn write_bulk(&self, data: &[u8]) -> rusb::Result<usize> {
+        let mut console = self.console.borrow_mut();
+        if console.dead {
+            return Err(rusb::Error::Timeout);
+        }
+        console.receive(data);
+        Ok(data.len())
+    }
+
+    pub(crate) fn read_bulk(&self, buf: &mut [u8]) -> rusb::Result<usize> {
+        let mut console = self.console.borrow_mut();
+        if console.dead || console.output.is_empty() {
+            return Err(rusb::Error::Timeout);
+        }
+
+        let n = buf.len().min(console.output.len());
+        for (b, v) in buf.iter_mut().zip(console.output.drain(..n)) {
+            *b = v;
+        }
+        Ok(n)
+    }
+}
+
+impl<C: UsbContext> Handle<C> {
+    pub(crate) fn virtual_console(console: VirtualConsole) -> Self {
+        let mut handle = Self::with_transport(Transport::Virtual(console));
+        handle.set_progress_sink(NoProgress);
+        handle
+    }
+
+    pub(crate) fn console(&self) -> &VirtualConsole {
+        match &self.handle {
+            Transport::Virtual(v) => v,
+            _ => panic!("not a virtual console"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "writing"))]
+mod tests {
+    use rusb::GlobalContext;
+
+    use super::*;
+    use crate::constants::NUM_FATS;
+    use crate::error::*;
+    use crate::fs::FsIssue;
+
+    const CARD_BLOCKS: u32 = 0x1000;
+
+    fn data(seed: u8, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    fn mount(card: VirtualCard, fault: Option<(usize, Fault)>) -> Handle<GlobalContext> {
+        let mut handle = Handle::virtual_console(VirtualConsole::new(card, fault));
+        handle.Init().expect("mounting the virtual card");
+        handle
+    }
+
+    fn card_with(files: &[(&str, &[u8])]) -> VirtualCard {
+        let mut handle = mount(
+            VirtualCard::formatted(CardLayout::ique(), CARD_BLOCKS),
+            None,
+        );
+        for (name, data) in files {
+            handle.WriteFile(data, name).unwrap();
+        }
+        handle.console().card()
+    }
+
+    // stops the write at every card write it makes, every way a write can fail, and checks what
+    // the card mounts as afterwards: `name` holds `old` or `new`, and nothing else has changed
+    fn check_write(before: &[(&str, &[u8])], name: &str, new: &[u8]) {
+        let card = card_with(before);
+        let old = before
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, d)| d.to_vec());
+
+        let steps = {
+            let mut handle = mount(card.clone(), None);
+            handle.WriteFile(new, name).unwrap();
+            handle.console().writes()
+        };
+        assert!(steps > 0);
+
+        for step in 0..steps {
+            for fault in [Fault::Lost, Fault::Torn, Fault::Unanswered] {
+                let what = format!("{fault:?} at write {step} of {steps}");
+
+                let mut handle = mount(card.clone(), Some((step, fault)));
+                let rv = handle.WriteFile(new, name);
+                assert!(rv.is_err(), "{what}: the write succeeded");
+                let after = mount(handle.console().card(), None);
+
+                let issues = after.CheckFS().unwrap();
+                assert!(issues.is_empty(), "{what}: {issues:?}");
+
+                let got = after.ReadFile(name).unwrap();
+                assert!(
+                    got == old || got.as_deref() == Some(new),
+                    "{what}: {name} is neither the old nor the new contents"
+                );
+
+                let mut names = after
+                    .ListFiles()
+                    .unwrap()
+                    .into_iter()
+                    .map(|(n, _)| n)
+                    .collect::<Vec<_>>();
+                names.sort();
+                let mut expected = before
+                    .iter()
+                    .map(|(n, _)| n.to_string())
+                    .collect::<Vec<_>>();
+                if got.is_some() && old.is_none() {
+                    expected.push(name.to_string());
+                }
+                expected.sort();
+                assert_eq!(names, expected, "{what}");
+
+                for (n, d) in before.iter().filter(|(n, _)| *n != name) {
+                    assert_eq!(
+                        after.ReadFile(n).unwrap().as_deref(),
+                        Some(*d),
+                        "{what}: {n}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn virtual_card_round_trips_a_file() -> Result<()> {
+        let contents = data(1, 0x9000);
+        let card = card_with(&[("hello.bin", &contents)]);
+
+        let handle = mount(card, None);
+        assert_eq!(handle.ReadFile("hello.bin")?, Some(contents));
+        assert!(handle.CheckFS()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn looping_chain_is_caught() -> Result<()> {
+        let mut card = card_with(&[("loop.bin", &data(7, 0xC000))]);
+        let chain = mount(card.clone(), None)
+            .FileChain("loop.bin")?
+            .collect::<Vec<_>>();
+        assert_eq!(chain.len(), 3);
+
+        // point the second block back at the first, in every FAT generation on the card
+        for slot in 0..NUM_FATS {
+            let block = card.layout.fat_block(CARD_BLOCKS, slot);
+            let (mut fs, spare) = card.read(block);
+            if &fs[fs.len() - 12..][..4] != b"BBFS" {
+                continue;
+            }
+            fs[chain[1] as usize * 2..][..2].copy_from_slice(&(chain[0] as u16).to_be_bytes());
+            fix_fat_checksum(&mut fs);
+            card.program(block, fs, spare);
+        }
+
+        let handle = mount(card, None);
+        let e = handle.ReadFile("loop.bin").unwrap_err();
+        assert!(matches!(
+            e.root(),
+            LibBBRDBError::ChainLoop(name, block) if name == "loop.bin" && *block == chain[0]
+        ));
+        assert!(handle.CheckFS()?.iter().any(|i| matches!(
+            i,
+            FsIssue::Loop { file, block } if file == "loop.bin" && *block == chain[0]
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_new_file_leaves_old_or_new_state() {
+        let other = data(2, 0x5000);
+        check_write(&[("keep.rec", &other)], "new.bin", &data(3, 0x8100));
+    }
+
+    #[test]
+    fn interrupted_overwrite_leaves_old_or_new_state() {
+        let other = data(4, 0x4000);
+        let old = data(5, 0x6000);
+        check_write(
+            &[("keep.rec", &other), ("save.bin", &old)],
+            "save.bin",
+            &data(6, 0xA123),
+        );
+    }
+}
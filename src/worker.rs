@@ -0,0 +1,95 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{spawn, JoinHandle};
+
+use rusb::UsbContext;
+
+use crate::error::*;
+use crate::Handle;
+
+type Job<C> = Box<dyn FnOnce(&mut Handle<C>) + Send>;
+
+enum Message<C: UsbContext> {
+    Job(Job<C>),
+    Shutdown,
+}
+
+pub struct BbClient<C: UsbContext> {
+    tx: Sender<Message<C>>,
+}
+
+impl<C: UsbContext> Clone for BbClient<C> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<C: UsbContext> BbClient<C> {
+    // queues the operation and returns immediately; the result arrives on the receiver
+    pub fn submit<T, F>(&self, f: F) -> Result<Receiver<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Handle<C>) -> T + Send + 'static,
+    {
+        let (tx, rx) = channel();
+
+        self.tx
+            .send(Message::Job(Box::new(move |handle| {
+                let _ = tx.send(f(handle));
+            })))
+            .map_err(|_| LibBBRDBError::WorkerGone)?;
+
+        Ok(rx)
+    }
+
+    pub fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Handle<C>) -> T + Send + 'static,
+    {
+        self.submit(f)?
+            .recv()
+            .map_err(|_| LibBBRDBError::WorkerGone)
+    }
+}
+
+pub struct BbWorker<C: UsbContext> {
+    client: BbClient<C>,
+    thread: JoinHandle<Handle<C>>,
+}
+
+impl<C: UsbContext + 'static> BbWorker<C> {
+    pub fn spawn(handle: Handle<C>) -> Self {
+        let (tx, rx) = channel::<Message<C>>();
+
+        let thread = spawn(move || {
+            let mut handle = handle;
+
+            for message in rx {
+                match message {
+                    Message::Job(job) => job(&mut handle),
+                    Message::Shutdown => break,
+                }
+            }
+
+            handle
+        });
+
+        Self {
+            client: BbClient { tx },
+            thread,
+        }
+    }
+
+    pub fn client(&self) -> BbClient<C> {
+        self.client.clone()
+    }
+
+    // finishes any queued operations and hands the device back
+    pub fn shutdown(self) -> Result<Handle<C>> {
+        let _ = self.client.tx.send(Message::Shutdown);
+
+        self.thread.join().map_err(|_| LibBBRDBError::WorkerGone)
+    }
+}